@@ -1,20 +1,150 @@
 use clap::Parser;
+use lc3::asm::image::{ImageFormat, ImageRange};
+use lc3::vm::trace::TraceFormat;
 
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
 pub struct Cli {
-    /// Path of the ASM file
-    pub file_path: String,
+    /// Path of the ASM file, or of an lc3as `.obj` file
+    #[arg(required_unless_present = "resume")]
+    pub file_path: Option<String>,
 
-    /// Emit bin file as `out.bin`
+    /// Write the lc3as `.obj`, `.sym` and `.lst` files next to the ASM file. A
+    /// section other than the first gets its own `.obj`, named after its origin
     #[arg(short, long, action = clap::ArgAction::SetTrue)]
     pub emit_binary: bool,
 
-    /// Indicates that the path given is a binary file (UNIMPLEMENTED)
+    /// Indicates that the path given is an lc3as `.obj` file, as is any path ending
+    /// in `.obj`. Its `.sym` file and other sections (like `prog.x4000.obj`) are
+    /// read too, if there are any
     #[arg(short, long, action = clap::ArgAction::SetTrue)]
     pub binary_file: bool,
+
+    /// Print the program's registers (including the PSR) once it halts. If it
+    /// halted inside a trap, such as HALT, these are its registers when it made
+    /// the trap, with the PC it would return to
+    #[arg(short, long, action = clap::ArgAction::SetTrue)]
+    pub registers: bool,
+
+    /// Run the standard traps as host code instead of booting the OS image
+    #[arg(short, long, action = clap::ArgAction::SetTrue)]
+    pub fast_traps: bool,
+
+    /// Step through the program in an interactive debugger, which can also step backward
+    #[arg(short, long, action = clap::ArgAction::SetTrue)]
+    pub debug: bool,
+
+    /// Resume a machine saved with `--save-snapshot` instead of running an ASM file
+    #[arg(long, value_name = "SNAPSHOT", conflicts_with_all = ["file_path", "os", "fast_traps"])]
+    pub resume: Option<String>,
+
+    /// Save the whole machine to a snapshot file once the program stops
+    #[arg(long, value_name = "FILE")]
+    pub save_snapshot: Option<String>,
+
+    /// Stop the program after this many instructions
+    #[arg(long, value_name = "N")]
+    pub max_instructions: Option<u64>,
+
+    /// Stop the program once it has printed this many characters
+    #[arg(long, value_name = "N")]
+    pub max_output: Option<u64>,
+
+    /// Stop the program once it has read this many characters
+    #[arg(long, value_name = "N")]
+    pub max_input: Option<u64>,
+
+    /// Stop the program after running for this many milliseconds
+    #[arg(long, value_name = "MS")]
+    pub timeout: Option<u64>,
+
+    /// Write a line for every instruction that runs (its address, word, disassembly
+    /// and what it changed) to this file
+    #[arg(long, value_name = "FILE")]
+    pub trace: Option<String>,
+
+    /// Format of the `--trace` file: text, csv or jsonl
+    #[arg(long, value_name = "FORMAT", default_value = "text", value_parser = TraceFormat::parse)]
+    pub trace_format: TraceFormat,
+
+    /// Count the instructions run per address and opcode, the memory read and
+    /// written, and the traps called, and print a hotspot report once the program stops
+    #[arg(long, action = clap::ArgAction::SetTrue)]
+    pub profile: bool,
+
+    /// Track subroutine calls and returns, and print the call graph once the program stops
+    #[arg(long, action = clap::ArgAction::SetTrue)]
+    pub call_graph: bool,
+
+    /// Write the call graph to this file in the folded-stack format read by flamegraph tools
+    #[arg(long, value_name = "FILE")]
+    pub folded_stacks: Option<String>,
+
+    /// Record which source lines ran and which way each branch went, and write a
+    /// coverage report to this file once the program stops
+    #[arg(long, value_name = "FILE", requires = "file_path")]
+    pub coverage: Option<String>,
+
+    /// Format of the `--coverage` file: lcov, or annotated for the source with
+    /// every line prefixed by how often it ran
+    #[arg(long, value_name = "FORMAT", default_value = "lcov", value_parser = ["lcov", "annotated"])]
+    pub coverage_format: String,
+
+    /// Write the assembled program to this file as a memory image for Verilog,
+    /// an FPGA or Logisim
+    #[arg(long, value_name = "FILE", requires = "file_path")]
+    pub export_image: Option<String>,
+
+    /// Load a memory image into memory before the program runs. Can be given more
+    /// than once
+    #[arg(long, value_name = "FILE")]
+    pub load_image: Vec<String>,
+
+    /// Format of `--export-image` and `--load-image` files: readmemh, readmemb, ihex
+    /// or logisim [default: readmemh when exporting, guessed from the contents when loading]
+    #[arg(long, value_name = "FORMAT", value_parser = ImageFormat::parse)]
+    pub image_format: Option<ImageFormat>,
+
+    /// Addresses the `--export-image` file covers, or the part of each `--load-image`
+    /// file that is loaded, e.g. `x3000-x30FF` [default: the lowest to the highest
+    /// address the program fills when exporting, the whole image when loading]
+    #[arg(long, value_name = "START-END", value_parser = ImageRange::parse)]
+    pub image_range: Option<ImageRange>,
+
+    /// Word written to every address in `--image-range` the program leaves empty
+    #[arg(long, value_name = "WORD", default_value = "x0000", value_parser = parse_word)]
+    pub image_fill: u16,
+
+    /// ASM file of an OS segment (such as a trap vector table) in x0000-x2FFF. Can be
+    /// given more than once, and replaces the built-in OS
+    #[arg(long, value_name = "FILE")]
+    pub os: Vec<String>,
+
+    /// PSR to start the program with, e.g. `x8002` (defaults to user mode for programs in user space)
+    #[arg(long, value_parser = parse_word)]
+    pub psr: Option<u16>,
+
+    /// Initial user stack pointer [default: xFE00]
+    #[arg(long, value_parser = parse_word)]
+    pub usp: Option<u16>,
+
+    /// Initial supervisor stack pointer [default: x3000]
+    #[arg(long, value_parser = parse_word)]
+    pub ssp: Option<u16>,
 }
 
 pub fn get_cli() -> Cli {
     Cli::parse()
 }
+
+/// Parses a 16-bit word written the way the assembler accepts it (`x3000` or
+/// `#12288`), or as a plain decimal number.
+fn parse_word(s: &str) -> Result<u16, String> {
+    let parsed = if let Some(hex) = s.strip_prefix('x').or(s.strip_prefix('X')) {
+        u16::from_str_radix(hex, 16)
+    } else {
+        s.trim_start_matches('#').parse::<u16>()
+    };
+
+    return parsed.map_err(|_| format!("`{s}` is not a valid 16-bit value"));
+}
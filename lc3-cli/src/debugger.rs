@@ -0,0 +1,453 @@
+use crate::view;
+use lc3::asm::source_map::SourceMap;
+use lc3::vm::breakpoint::{Access, Breakpoint, Exception, StopReason, Watchpoint};
+use lc3::vm::delta::IoEvent;
+use lc3::vm::expr::Expr;
+use lc3::vm::history::DEFAULT_HISTORY_LIMIT;
+use lc3::vm::narrate::Narration;
+use lc3::vm::vm::{InstructionResult, TrapMode, VM};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{Write, stdin, stdout};
+
+const HELP: &str = "\
+Commands:
+  s, step [n]          run the next n instructions (default 1)
+  b, back [n]          undo the last n instructions (default 1)
+  rb, runback <addr>   step backward until the PC is <addr>
+  c, continue          run until a breakpoint, watchpoint, exception or halt
+  x, explain           explain the last instruction in plain English, field by field
+  n, narrate           turn explaining every step on or off
+  bp, break <addr> [hit <n>] [if <cond>]
+                       stop before the instruction at <addr> runs, from the
+                       nth time it is reached with <cond> true
+  del, delete <addr>   remove the breakpoint at <addr>
+  w, watch <addr>[..<end>] [r|w|rw] [if <cond>]
+                       stop after an instruction reads and/or writes the
+                       addresses (default w)
+  unwatch <addr>       remove the watchpoints starting at <addr>
+  i, info              list breakpoints and watchpoints
+  p, print <expr>      evaluate an expression, e.g. `M[COUNT] + R1`
+  r, regs              print the registers
+  m, mem <addr> [n]    print n words of memory starting at <addr> (default 1)
+  l, list [addr]       print the source around <addr> (default the PC)
+  save <file>          save the machine to a snapshot file
+  h, help              print this message
+  q, quit              exit the debugger
+
+Addresses and conditions are expressions over numbers (x41, #-5, 65), R0-R7,
+PC, IR, PSR, N, Z, P, memory (M[addr]) and labels, with the operators
+|| && == != < <= > >= + - !, e.g. `R0 == x41 && M[COUNT] > #5`.";
+
+/// How many lines `list` prints on each side of the address.
+const LIST_CONTEXT: usize = 3;
+
+/// The program's source, for showing lines instead of bare addresses. A program
+/// resumed from a snapshot has none.
+pub struct Listing<'a> {
+    pub source: &'a str,
+    pub source_map: &'a SourceMap,
+}
+
+impl<'a> Listing<'a> {
+    /// The source line that `address` was assembled from, if there is one.
+    fn line_at(&self, address: u16) -> Option<(usize, &'a str)> {
+        let line = self.source_map.line(address)?;
+        let text = self.source.lines().nth(line - 1)?;
+        return Some((line, text));
+    }
+
+    /// Prints the source around `address`, marking its line.
+    fn print_around(&self, address: u16) {
+        let Some((line, _)) = self.line_at(address) else {
+            println!("No source for x{address:04X}.");
+            return;
+        };
+        let lines: Vec<&str> = self.source.lines().collect();
+        let first = line.saturating_sub(LIST_CONTEXT).max(1);
+        let last = (line + LIST_CONTEXT).min(lines.len());
+        for number in first..=last {
+            let marker = if number == line { "=>" } else { "  " };
+            println!("{marker} {number:>4}  {}", lines[number - 1]);
+        }
+    }
+
+    /// Prints the line the instruction at `address` comes from, if known.
+    fn print_line(&self, address: u16) {
+        if let Some((line, text)) = self.line_at(address) {
+            println!("=> {line:>4}  {}", text.trim());
+        }
+    }
+}
+
+/// A line-based debugger around a VM that already has its program loaded.
+/// `symbols` are the program's labels, for use in expressions, and `listing`
+/// its source.
+pub fn run(vm: &mut VM, symbols: &HashMap<String, u16>, listing: &Listing) {
+    vm.history.set_limit(DEFAULT_HISTORY_LIMIT);
+    println!("{HELP}\n");
+    view::print_registers(&vm.registers);
+    listing.print_line(vm.registers.pc);
+    let mut narrate = false;
+
+    loop {
+        print!("\n(lc3) ");
+        stdout()
+            .flush()
+            .expect("Expected to be able to flush the debugger prompt");
+
+        let mut line = String::new();
+        if stdin().read_line(&mut line).unwrap_or(0) == 0 {
+            return;
+        }
+        // a condition is everything after `if`, and can contain spaces
+        let (line, condition) = match line.split_once(" if ") {
+            Some((line, condition)) => (line, Some(condition.trim())),
+            None => (line.as_str(), None),
+        };
+        let args: Vec<&str> = line.split_whitespace().collect();
+        let Some(&command) = args.first() else {
+            continue;
+        };
+
+        match command {
+            "s" | "step" => {
+                for _ in 0..count(&args) {
+                    if !step(vm, narrate) {
+                        break;
+                    }
+                }
+                listing.print_line(vm.registers.pc);
+            }
+            "b" | "back" => {
+                for _ in 0..count(&args) {
+                    if !vm.step_back() {
+                        println!("No more history to step back through.");
+                        break;
+                    }
+                }
+                print_location(vm, listing);
+            }
+            "rb" | "runback" => match args.get(1).map(|a| location(vm, symbols, a)) {
+                Some(Ok(address)) => {
+                    if !vm.run_back_to(address) {
+                        println!("Ran out of history before reaching x{address:04X}.");
+                    }
+                    print_location(vm, listing);
+                }
+                Some(Err(msg)) => println!("{msg}"),
+                None => println!("Usage: runback <addr>"),
+            },
+            "x" | "explain" => match vm.narrate_last_step() {
+                Some(narration) => print_narration(&narration),
+                None => println!("No instruction has run yet."),
+            },
+            "n" | "narrate" => {
+                narrate = !narrate;
+                println!("Narration is {}.", if narrate { "on" } else { "off" });
+            }
+            "c" | "continue" => {
+                let reason = continue_until_event(vm);
+                println!();
+                print_stop_reason(reason);
+                print_location(vm, listing);
+            }
+            "bp" | "break" => match parse_breakpoint(vm, symbols, &args, condition) {
+                Ok((address, bp)) => {
+                    vm.breakpoints.insert(address, bp);
+                    println!("Breakpoint set at x{address:04X}.");
+                }
+                Err(msg) => println!("{msg}"),
+            },
+            "del" | "delete" => match args.get(1).map(|a| location(vm, symbols, a)) {
+                Some(Ok(address)) => {
+                    if vm.breakpoints.remove(&address).is_none() {
+                        println!("There is no breakpoint at x{address:04X}.");
+                    }
+                }
+                Some(Err(msg)) => println!("{msg}"),
+                None => println!("Usage: delete <addr>"),
+            },
+            "w" | "watch" => match parse_watchpoint(vm, symbols, &args, condition) {
+                Ok(wp) => {
+                    println!("Watching {}.", describe_watchpoint(&wp));
+                    vm.memory.watchpoints.push(wp);
+                }
+                Err(msg) => println!("{msg}"),
+            },
+            "unwatch" => match args.get(1).map(|a| location(vm, symbols, a)) {
+                Some(Ok(address)) => {
+                    vm.memory.watchpoints.retain(|wp| wp.start != address);
+                }
+                Some(Err(msg)) => println!("{msg}"),
+                None => println!("Usage: unwatch <addr>"),
+            },
+            "i" | "info" => {
+                let mut breakpoints: Vec<(&u16, &Breakpoint)> = vm.breakpoints.iter().collect();
+                breakpoints.sort_by_key(|(address, _)| **address);
+                for (address, bp) in breakpoints {
+                    let mut line = format!("breakpoint x{address:04X}, hit {} times", bp.hits);
+                    if bp.stop_at_hit > 1 {
+                        line += &format!(", stops from hit {}", bp.stop_at_hit);
+                    }
+                    if let Some(condition) = &bp.condition {
+                        line += &format!(", if {condition}");
+                    }
+                    println!("{line}");
+                }
+                for wp in &vm.memory.watchpoints {
+                    println!("watchpoint {}", describe_watchpoint(wp));
+                }
+            }
+            "p" | "print" => {
+                let source = line.trim_start()[command.len()..].trim();
+                match Expr::parse(source, symbols) {
+                    Ok(expr) => {
+                        let value = expr.eval(&vm.registers, &vm.memory);
+                        println!("x{value:04X} (#{})", value as i16);
+                    }
+                    Err(msg) => println!("{msg}"),
+                }
+            }
+            "r" | "regs" => view::print_registers(&vm.registers),
+            "m" | "mem" => match args.get(1).map(|a| location(vm, symbols, a)) {
+                Some(Ok(address)) => {
+                    for i in 0..count(&args[1..]) {
+                        let loc = address.wrapping_add(i as u16);
+                        println!("x{:04X}  x{:04X}", loc, vm.memory.get(loc));
+                    }
+                }
+                Some(Err(msg)) => println!("{msg}"),
+                None => println!("Usage: mem <addr> [n]"),
+            },
+            "l" | "list" => match args.get(1).map(|a| location(vm, symbols, a)) {
+                Some(Ok(address)) => listing.print_around(address),
+                Some(Err(msg)) => println!("{msg}"),
+                None => listing.print_around(vm.registers.pc),
+            },
+            "save" => match args.get(1) {
+                Some(path) => match vm.save_snapshot() {
+                    Ok(snapshot) => match fs::write(path, snapshot) {
+                        Ok(()) => println!("Saved a snapshot to `{path}`."),
+                        Err(_) => println!("Could not write the snapshot to `{path}`."),
+                    },
+                    Err(msg) => println!("{msg}"),
+                },
+                None => println!("Usage: save <file>"),
+            },
+            "h" | "help" => println!("{HELP}"),
+            "q" | "quit" => return,
+            _ => println!("Unknown command `{command}`. Type `help` for a list of commands."),
+        }
+    }
+}
+
+/// Runs one instruction and prints what it changed, or explains it if `narrate`
+/// is on. Returns `false` once the program can't continue.
+fn step(vm: &mut VM, narrate: bool) -> bool {
+    if vm.is_halted() {
+        println!("The program has halted.");
+        return false;
+    }
+
+    let result = vm.run_single_command();
+    if let InstructionResult::AwaitingInput = result {
+        if vm.trap_mode == TrapMode::Os && !vm.memory.devices.wait_for_keyboard(&mut vm.io) {
+            return false;
+        }
+    }
+
+    if narrate {
+        if let Some(narration) = vm.narrate_last_step() {
+            print_narration(&narration);
+            return !vm.is_halted();
+        }
+    }
+
+    let delta = vm.last_delta();
+    let mut changes = vec![];
+    if let Some(record) = vm.last_trace() {
+        changes.push(format!(
+            "x{:04X}: x{:04X} {}",
+            record.pc, record.word, record.disassembly
+        ));
+    }
+    for change in &delta.registers {
+        changes.push(format!(
+            "R{} x{:04X} -> x{:04X}",
+            change.register, change.old, change.new
+        ));
+    }
+    if let Some(cc) = delta.cc {
+        changes.push(format!("CC {} -> {}", cc_str(cc.old), cc_str(cc.new)));
+    }
+    for write in &delta.memory {
+        changes.push(format!(
+            "M[x{:04X}] x{:04X} -> x{:04X}",
+            write.address, write.old, write.new
+        ));
+    }
+    for event in &delta.io {
+        match event {
+            IoEvent::Input(c) => changes.push(format!("read {c:?}")),
+            IoEvent::Output(c) => changes.push(format!("wrote {c:?}")),
+        }
+    }
+    println!("{}", changes.join(", "));
+
+    return !vm.is_halted();
+}
+
+/// Runs until `run_until_event` reports something other than the program
+/// waiting on the keyboard, which just blocks until a key is typed.
+fn continue_until_event(vm: &mut VM) -> StopReason {
+    loop {
+        let reason = vm.run_until_event(None);
+        if reason == StopReason::AwaitingInput
+            && vm.trap_mode == TrapMode::Os
+            && vm.memory.devices.wait_for_keyboard(&mut vm.io)
+        {
+            continue;
+        }
+        return reason;
+    }
+}
+
+fn print_narration(narration: &Narration) {
+    println!("x{:04X}: {narration}", narration.pc);
+    for line in narration.layout().lines() {
+        println!("         {line}");
+    }
+}
+
+fn print_stop_reason(reason: StopReason) {
+    match reason {
+        StopReason::Breakpoint(address) => println!("Stopped at the breakpoint at x{address:04X}."),
+        StopReason::Watchpoint(hit) => {
+            let access = match hit.access {
+                Access::Read => "read",
+                Access::Write => "written",
+            };
+            println!(
+                "Stopped because x{:04X} was {access} (value x{:04X}).",
+                hit.address, hit.value
+            );
+        }
+        StopReason::Halted => println!("The program has halted."),
+        StopReason::AwaitingInput => println!("The program is waiting for input that never came."),
+        StopReason::Exception(exception) => {
+            let name = match exception {
+                Exception::PrivilegeMode => "privilege mode exception",
+                Exception::IllegalOpcode => "illegal opcode exception",
+                Exception::AccessControlViolation => "access control violation",
+            };
+            println!("Stopped after a {name}.");
+        }
+        StopReason::StepLimit => println!("Stopped at the step limit."),
+        StopReason::LimitExceeded(limit) => println!("Stopped by the {limit:?} resource limit."),
+    }
+}
+
+/// Evaluates an address written as an expression, such as `LOOP` or `x3000 + 2`.
+fn location(vm: &VM, symbols: &HashMap<String, u16>, source: &str) -> Result<u16, String> {
+    let expr = Expr::parse(source, symbols)?;
+    return Ok(expr.eval(&vm.registers, &vm.memory));
+}
+
+fn parse_condition(
+    symbols: &HashMap<String, u16>,
+    condition: Option<&str>,
+) -> Result<Option<Expr>, String> {
+    return condition
+        .map(|condition| Expr::parse(condition, symbols))
+        .transpose();
+}
+
+/// Parses `break <addr> [hit <n>] [if <cond>]`.
+fn parse_breakpoint(
+    vm: &VM,
+    symbols: &HashMap<String, u16>,
+    args: &[&str],
+    condition: Option<&str>,
+) -> Result<(u16, Breakpoint), String> {
+    let usage = "Usage: break <addr> [hit <n>] [if <cond>]".to_string();
+    let address = location(vm, symbols, args.get(1).ok_or(usage.clone())?)?;
+
+    let stop_at_hit = match &args[2..] {
+        [] => 0,
+        ["hit", n] => n.parse::<u64>().map_err(|_| usage.clone())?,
+        _ => return Err(usage),
+    };
+
+    let condition = parse_condition(symbols, condition)?;
+    return Ok((address, Breakpoint::with_condition(condition, stop_at_hit)));
+}
+
+/// Parses `watch <addr>[..<end>] [r|w|rw] [if <cond>]`.
+fn parse_watchpoint(
+    vm: &VM,
+    symbols: &HashMap<String, u16>,
+    args: &[&str],
+    condition: Option<&str>,
+) -> Result<Watchpoint, String> {
+    let usage = "Usage: watch <addr>[..<end>] [r|w|rw] [if <cond>]".to_string();
+    let range = args.get(1).ok_or(usage.clone())?;
+    // `..`, since `-` is subtraction, as in `watch COUNT-1`
+    let (start, end) = match range.split_once("..") {
+        Some((start, end)) => (location(vm, symbols, start)?, location(vm, symbols, end)?),
+        None => (location(vm, symbols, range)?, location(vm, symbols, range)?),
+    };
+
+    let (on_read, on_write) = match args.get(2).copied() {
+        None | Some("w") => (false, true),
+        Some("r") => (true, false),
+        Some("rw") => (true, true),
+        Some(_) => return Err(usage),
+    };
+
+    let mut wp = Watchpoint::new(start, end, on_read, on_write);
+    wp.condition = parse_condition(symbols, condition)?;
+    return Ok(wp);
+}
+
+fn describe_watchpoint(wp: &Watchpoint) -> String {
+    let access = match (wp.on_read, wp.on_write) {
+        (true, true) => "reads and writes",
+        (true, false) => "reads",
+        _ => "writes",
+    };
+    let mut description = if wp.start == wp.end {
+        format!("x{:04X} for {access}", wp.start)
+    } else {
+        format!("x{:04X}..x{:04X} for {access}", wp.start, wp.end)
+    };
+    if let Some(condition) = &wp.condition {
+        description += &format!(", if {condition}");
+    }
+    return description;
+}
+
+fn print_location(vm: &VM, listing: &Listing) {
+    println!(
+        "PC x{:04X} ({} steps of history left)",
+        vm.registers.pc,
+        vm.history.len()
+    );
+    listing.print_line(vm.registers.pc);
+}
+
+fn count(args: &[&str]) -> usize {
+    return args
+        .get(1)
+        .and_then(|n| n.parse::<usize>().ok())
+        .unwrap_or(1);
+}
+
+fn cc_str(cc: u16) -> &'static str {
+    match cc {
+        0b100 => "N",
+        0b010 => "Z",
+        0b001 => "P",
+        _ => "-",
+    }
+}
@@ -1,45 +1,364 @@
 mod cli;
+mod debugger;
 mod stdio;
+mod view;
 
 use lc3;
 use lc3::asm::asm::Asm;
+use lc3::asm::image::{self, ImageFormat, ImageRange};
+use lc3::asm::object;
 use lc3::io::Lc3IO;
-use lc3::vm::vm::VM;
-use std::fs;
+use lc3::program::Program;
+use lc3::vm::breakpoint::StopReason;
+use lc3::vm::callgraph::CallGraph;
+use lc3::vm::coverage::Coverage;
+use lc3::vm::limits::ResourceLimits;
+use lc3::vm::profile::Profile;
+use lc3::vm::trace::TraceWriter;
+use lc3::vm::vm::{TrapMode, VM};
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
 use stdio::*;
 
+/// Exit code for a program that was stopped by `--max-instructions`,
+/// `--max-output`, `--max-input` or `--timeout`, so batch tools can tell it apart.
+const LIMIT_EXCEEDED_EXIT_CODE: i32 = 2;
+
 fn main() {
     let cli = cli::get_cli();
 
-    if cli.emit_binary && cli.binary_file {
+    let binary_file = cli.binary_file
+        || cli
+            .file_path
+            .as_ref()
+            .is_some_and(|path| path.to_lowercase().ends_with(".obj"));
+
+    if cli.emit_binary && binary_file {
         println!("Cannot both emit a binary file and run from a binary file.");
         return;
     }
 
-    let file_path_input = cli.file_path;
-    let file;
-    if let Ok(path) = fs::read_to_string(file_path_input) {
-        file = path;
-    } else {
-        println!("The asm file provided does not exist.");
-        return;
+    let mut program = None;
+    let mut source = String::new();
+    if let Some(file_path) = cli.file_path.as_ref().filter(|_| binary_file) {
+        match load_object_file(Path::new(file_path)) {
+            Ok(out) => program = Some(out),
+            Err(msg) => {
+                println!("{msg}");
+                return;
+            }
+        }
+    } else if let Some(file_path) = &cli.file_path {
+        let Ok(file) = fs::read_to_string(file_path) else {
+            println!("The asm file provided does not exist.");
+            return;
+        };
+
+        let io = Lc3IO::new(Box::new(StdIOTarget {}));
+        let mut asm = Asm::new(io);
+        let Some(out) = asm.run(file.clone()) else {
+            return;
+        };
+
+        if cli.emit_binary {
+            if let Err(msg) = emit_object_files(Path::new(file_path), &out, &file) {
+                println!("{msg}");
+                return;
+            }
+        }
+        if let Some(path) = &cli.export_image {
+            let format = cli.image_format.unwrap_or(ImageFormat::ReadMemH);
+            let range = cli.image_range.unwrap_or(ImageRange::of(&out));
+            let text = image::export(&out, format, range, cli.image_fill);
+            if fs::write(path, text).is_err() {
+                println!("Could not write the memory image to `{path}`.");
+                return;
+            }
+        }
+        program = Some(out);
+        source = file;
     }
+    let symbols = program
+        .as_ref()
+        .map(|p| p.symbols.clone())
+        .unwrap_or_default();
+    let source_map = program
+        .as_ref()
+        .and_then(|p| p.debug_info.clone())
+        .unwrap_or_default();
 
     let io = Lc3IO::new(Box::new(StdIOTarget {}));
-    let mut asm = Asm::new(io);
-    let binary_file: Vec<u16>;
-    if let Some(out) = asm.run(file) {
-        binary_file = out;
+
+    let trap_mode = if cli.fast_traps {
+        TrapMode::FastPath
     } else {
-        return;
+        TrapMode::Os
+    };
+
+    let mut vm = VM::with_trap_mode(io, trap_mode);
+
+    if !cli.os.is_empty() {
+        let mut segments = vec![];
+        for path in &cli.os {
+            let Ok(source) = fs::read_to_string(path) else {
+                println!("The OS file `{path}` does not exist.");
+                return;
+            };
+
+            let mut asm = Asm::new(Lc3IO::new(Box::new(StdIOTarget {})));
+            match asm.run(source) {
+                Some(segment) => segments.push(segment),
+                None => return,
+            }
+        }
+
+        if let Err(msg) = vm.load_os(segments) {
+            println!("{msg}");
+            return;
+        }
     }
 
-    if cli.emit_binary {
-        asm.emit_bin(&binary_file, "out.bin".to_string());
+    vm.start.psr = cli.psr;
+    if let Some(usp) = cli.usp {
+        vm.start.usp = usp;
+    }
+    if let Some(ssp) = cli.ssp {
+        vm.start.ssp = ssp;
     }
+    vm.limits = ResourceLimits {
+        max_instructions: cli.max_instructions,
+        max_output_chars: cli.max_output,
+        max_input_reads: cli.max_input,
+        max_wall_time_ms: cli.timeout,
+    };
 
-    let io = Lc3IO::new(Box::new(StdIOTarget {}));
+    if let Some(path) = &cli.resume {
+        let Ok(bytes) = fs::read(path) else {
+            println!("The snapshot file `{path}` does not exist.");
+            return;
+        };
+        if let Err(msg) = vm.load_snapshot(&bytes) {
+            println!("{msg}");
+            return;
+        }
+    } else if let Some(program) = &program {
+        if let Err(msg) = vm.load(program) {
+            println!("{msg}");
+            return;
+        }
+    }
+
+    for path in &cli.load_image {
+        match load_image_file(path, cli.image_format, cli.image_range, &vm) {
+            Ok(image) => {
+                for segment in &image.segments {
+                    if let Err(msg) = vm.memory.load_segment(segment) {
+                        println!("{msg}");
+                        return;
+                    }
+                }
+            }
+            Err(msg) => {
+                println!("{msg}");
+                return;
+            }
+        }
+    }
+
+    if let Some(path) = &cli.trace {
+        let Ok(file) = File::create(path) else {
+            println!("Could not create the trace file `{path}`.");
+            return;
+        };
+        vm.tracer = Some(Box::new(TraceWriter::new(
+            BufWriter::new(file),
+            cli.trace_format,
+        )));
+    }
+
+    if cli.profile {
+        vm.profile = Some(Profile::new());
+    }
+    if cli.call_graph || cli.folded_stacks.is_some() {
+        vm.call_graph = Some(CallGraph::new(&symbols));
+    }
+    if cli.coverage.is_some() {
+        vm.coverage = Some(Coverage::new());
+    }
+
+    let mut exit_code = 0;
+    if cli.debug {
+        let listing = debugger::Listing {
+            source: &source,
+            source_map: &source_map,
+        };
+        debugger::run(&mut vm, &symbols, &listing);
+    } else {
+        if let StopReason::LimitExceeded(limit) = vm.resume() {
+            view::print_limit_exceeded(limit, &vm.usage);
+            exit_code = LIMIT_EXCEEDED_EXIT_CODE;
+        }
+    }
+
+    if let Some(profile) = &vm.profile {
+        println!("\n");
+        print!("{}", profile.report(&vm.memory, &symbols));
+    }
 
-    let mut vm = VM::new(io);
-    vm.run(binary_file);
+    if let Some(call_graph) = &vm.call_graph {
+        if cli.call_graph {
+            println!("\n");
+            print!("{}", call_graph.report());
+        }
+        if let Some(path) = &cli.folded_stacks {
+            if fs::write(path, call_graph.folded()).is_err() {
+                println!("Could not write the call graph to `{path}`.");
+            }
+        }
+    }
+
+    if let (Some(coverage), Some(path), Some(program)) = (&vm.coverage, &cli.coverage, &program) {
+        let report = match cli.coverage_format.as_str() {
+            "annotated" => coverage.annotate(program, &source),
+            _ => coverage.lcov(program, cli.file_path.as_deref().unwrap_or("")),
+        };
+        if fs::write(path, report).is_err() {
+            println!("Could not write the coverage report to `{path}`.");
+        }
+        let (lines, branches) = coverage.summary(program);
+        println!("\n\nCoverage: {lines:.1}% of lines, {branches:.1}% of branches");
+    }
+
+    if let Err(msg) = vm.finish_trace() {
+        println!("{msg}");
+    }
+
+    if let Some(path) = &cli.save_snapshot {
+        match vm.save_snapshot() {
+            Ok(snapshot) => {
+                if fs::write(path, snapshot).is_err() {
+                    println!("Could not write the snapshot to `{path}`.");
+                }
+            }
+            Err(msg) => println!("{msg}"),
+        }
+    }
+
+    if cli.registers && !cli.debug {
+        println!();
+        view::print_registers(&vm.program_registers());
+    }
+
+    std::process::exit(exit_code);
+}
+
+/// Reads an lc3as `.obj` file, and the `.sym` file beside it if there is one.
+/// The other sections `--emit-binary` wrote for the same program, like
+/// `prog.x4000.obj` beside `prog.obj`, are loaded too.
+fn load_object_file(path: &Path) -> Result<Program, String> {
+    let Ok(bytes) = fs::read(path) else {
+        return Err(format!(
+            "The object file `{}` does not exist.",
+            path.display()
+        ));
+    };
+    let mut program = object::read_obj(&bytes)?;
+
+    for section in section_object_files(path) {
+        let Ok(bytes) = fs::read(&section) else {
+            return Err(format!("Could not read `{}`.", section.display()));
+        };
+        let image = object::read_obj(&bytes)?;
+        program.segments.extend(image.segments);
+    }
+
+    if let Ok(text) = fs::read_to_string(path.with_extension("sym")) {
+        program.symbols = object::read_sym(&text)?;
+    }
+    return Ok(program);
+}
+
+/// The files named like `prog.x4000.obj` next to `prog.obj`, in address order.
+fn section_object_files(path: &Path) -> Vec<PathBuf> {
+    let Some(stem) = path.file_stem().and_then(|stem| stem.to_str()) else {
+        return vec![];
+    };
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let Ok(entries) = fs::read_dir(dir) else {
+        return vec![];
+    };
+
+    let mut sections: Vec<(u16, PathBuf)> = entries
+        .filter_map(|entry| {
+            let name = entry.ok()?.file_name().into_string().ok()?;
+            let address = name
+                .strip_prefix(stem)?
+                .strip_prefix(".x")?
+                .strip_suffix(".obj")?;
+            if address.len() != 4 {
+                return None;
+            }
+            let address = u16::from_str_radix(address, 16).ok()?;
+            return Some((address, dir.join(name)));
+        })
+        .collect();
+    sections.sort();
+    return sections.into_iter().map(|(_, path)| path).collect();
+}
+
+/// Reads the part of a memory image in `range` (or all of it), guessing its
+/// format from its contents unless `format` is given. A whole Logisim image is
+/// refused while the VM has an OS, since it would fill the OS's memory from x0000.
+fn load_image_file(
+    path: &str,
+    format: Option<ImageFormat>,
+    range: Option<ImageRange>,
+    vm: &VM,
+) -> Result<Program, String> {
+    let Ok(text) = fs::read_to_string(path) else {
+        return Err(format!("The memory image `{path}` does not exist."));
+    };
+    let format = format.unwrap_or(ImageFormat::detect(&text));
+    let image = image::import(&text, format, range)?;
+
+    if format == ImageFormat::Logisim && range.is_none() && vm.trap_mode == TrapMode::Os {
+        return Err(format!(
+            "The Logisim image `{path}` starts at x0000, so loading all of it would overwrite the OS. \
+             Pass --image-range to load only the program's part, e.g. `--image-range x3000-x30FF`."
+        ));
+    }
+    return Ok(image);
+}
+
+/// Writes the `.obj`, `.sym` and `.lst` files lc3as would for the ASM file at
+/// `path`. Sections after the first go to files like `prog.x4000.obj`.
+fn emit_object_files(path: &Path, program: &Program, source: &str) -> Result<(), String> {
+    let mut files = vec![
+        (
+            path.with_extension("sym"),
+            object::write_sym(&program.symbols).into_bytes(),
+        ),
+        (
+            path.with_extension("lst"),
+            object::write_lst(program, source).into_bytes(),
+        ),
+    ];
+    for (i, segment) in program.segments.iter().enumerate() {
+        let extension = match i {
+            0 => "obj".to_string(),
+            _ => format!("x{:04X}.obj", segment.origin),
+        };
+        files.push((path.with_extension(extension), object::write_obj(segment)));
+    }
+
+    for (file, contents) in files {
+        if fs::write(&file, contents).is_err() {
+            return Err(format!("Could not write `{}`.", file.display()));
+        }
+    }
+    return Ok(());
 }
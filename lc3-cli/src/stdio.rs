@@ -2,6 +2,7 @@ use crossterm::event::*;
 use crossterm::terminal;
 use lc3::io::IOTarget;
 use std::io::*;
+use std::time::Duration;
 
 pub struct StdIOTarget;
 
@@ -59,6 +60,35 @@ impl IOTarget for StdIOTarget {
         return Some(out_c);
     }
 
+    fn poll_char(&mut self) -> Option<char> {
+        terminal::enable_raw_mode().expect("Expected to be able to enter raw mode in poll_char()");
+
+        let mut out_c: Option<char> = None;
+
+        while out_c.is_none() && poll(Duration::ZERO).unwrap_or(false) {
+            if let Ok(Event::Key(key)) = read() {
+                if key.kind != KeyEventKind::Press {
+                    continue;
+                }
+                match key.code {
+                    KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                        terminal::disable_raw_mode().expect(
+                            "Expected to be able to disable raw mode after pressing ctrl+c",
+                        );
+                        std::process::exit(130);
+                    }
+                    KeyCode::Char(c) => out_c = Some(c),
+                    KeyCode::Enter => out_c = Some('\n'),
+                    _ => {}
+                }
+            }
+        }
+
+        terminal::disable_raw_mode()
+            .expect("Expected to be able to turn off raw mode after polling stdin");
+        return out_c;
+    }
+
     fn put_char(&mut self, c: char) {
         print!("{}", c);
         std::io::stdout().flush().expect(
@@ -0,0 +1,43 @@
+use lc3::vm::limits::{Limit, ResourceUsage};
+use lc3::vm::registers::Registers;
+
+/// Prints the general purpose registers, followed by the PC, IR and PSR.
+/// The PSR is also broken down into its privilege, priority and condition code fields.
+pub fn print_registers(reg: &Registers) {
+    for row in 0..2 {
+        let mut line = String::new();
+        for i in (row * 4)..(row * 4 + 4) {
+            line.push_str(&format!("R{}  x{:04X}    ", i, reg.get(i)));
+        }
+        println!("{}", line.trim_end());
+    }
+
+    let privilege = if reg.is_user_mode() {
+        "User"
+    } else {
+        "Supervisor"
+    };
+    println!(
+        "PC  x{:04X}    IR  x{:04X}    PSR x{:04X} ({}, priority {}, CC {})",
+        reg.pc,
+        reg.ir,
+        reg.psr,
+        privilege,
+        reg.priority(),
+        reg.cc_str(),
+    );
+}
+
+/// Reports which resource limit stopped the program, and how much it had used.
+pub fn print_limit_exceeded(limit: Limit, usage: &ResourceUsage) {
+    let reason = match limit {
+        Limit::Instructions => "it ran too many instructions",
+        Limit::OutputChars => "it reached the output limit",
+        Limit::InputReads => "it reached the input limit",
+        Limit::WallTime => "it ran out of time",
+    };
+    println!(
+        "\nThe program was stopped because {reason} ({} instructions, {} characters printed, {} read).",
+        usage.instructions, usage.output_chars, usage.input_reads
+    );
+}
@@ -0,0 +1,56 @@
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+const PROGRAM: &str = "
+.orig x3000
+        lea r0, msg
+        puts
+        halt
+msg     .stringz \"hi\"
+.end
+";
+
+fn lc3(dir: &Path, args: &[&str]) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_lc3"))
+        .current_dir(dir)
+        .args(args)
+        .stdin(Stdio::null())
+        .output()
+        .expect("lc3 runs");
+    return String::from_utf8_lossy(&output.stdout).to_string();
+}
+
+#[test]
+fn test_logisim_image_round_trip() {
+    let dir = std::env::temp_dir().join(format!("lc3-load-image-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("hi.asm"), PROGRAM).unwrap();
+
+    let export = [
+        "hi.asm",
+        "--export-image",
+        "hi.raw",
+        "--image-format",
+        "logisim",
+    ];
+    assert_eq!(lc3(&dir, &export), "hi");
+
+    // the image fills x0000 up to the program, which is where the OS lives
+    let whole = lc3(&dir, &["hi.asm", "--load-image", "hi.raw"]);
+    assert!(whole.contains("would overwrite the OS"), "{whole}");
+
+    let part = [
+        "hi.asm",
+        "--load-image",
+        "hi.raw",
+        "--image-range",
+        "x3000-x3005",
+    ];
+    assert_eq!(lc3(&dir, &part), "hi");
+
+    let fast = ["hi.asm", "--load-image", "hi.raw", "--fast-traps"];
+    assert_eq!(lc3(&dir, &fast), "hi");
+
+    fs::remove_dir_all(&dir).unwrap();
+}
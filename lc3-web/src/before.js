@@ -95,6 +95,11 @@ for (let i = 0; i < visibleCount; i++) {
   row.className = "memory-row";
 
   const addr = document.createElement("div");
+  addr.title = "Click to toggle a breakpoint";
+  addr.addEventListener("click", () => {
+    VM.toggle_breakpoint(row.address);
+    render_memory(true);
+  });
   row.appendChild(addr);
 
   const hex = document.createElement("div");
@@ -126,16 +131,25 @@ function render_memory(refresh = false) {
   const start = Math.max(0, firstVisible - BUFFER);
 
   viewport.style.transform = `translateY(${start * ROW_HEIGHT}px)`;
+  const maxHits = VM.profile_max_hits();
 
   for (let i = 0; i < rows.length; i++) {
     const addr = start + i;
     const row = rows[i];
     const pc = VM.get_pc();
 
+    row.address = addr;
     row.classList.remove("mem-pc-loc");
     if (addr === pc) {
       row.classList.add("mem-pc-loc");
     }
+    row.classList.toggle("mem-breakpoint", VM.has_breakpoint(addr));
+
+    // heat-map the profiled instruction counts, relative to the hottest address
+    const hits = maxHits > 0 && addr < TOTAL_ROWS ? VM.profile_hits(addr) : 0;
+    row.classList.toggle("mem-hot", hits > 0);
+    row.style.setProperty("--heat", hits / Math.max(maxHits, 1));
+    row.title = hits > 0 ? `Executed ${hits} times` : "";
 
     if (addr >= TOTAL_ROWS) {
       row.style.display = "none";
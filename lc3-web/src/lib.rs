@@ -5,6 +5,7 @@ use lc3::asm::asm::Asm;
 use lc3::asm::lexer::*;
 use lc3::asm::token::*;
 use lc3::io::Lc3IO;
+use lc3::program::Program;
 use lc3::vm::vm::OPCODE_DELTA;
 use wasm_bindgen::prelude::*;
 
@@ -17,7 +18,7 @@ extern "C" {
 }
 
 #[wasm_bindgen]
-pub fn assemble(file: String) -> Option<Vec<u16>> {
+pub fn assemble(file: String) -> Option<Program> {
     let io = Lc3IO::new(Box::new(WebIO::new()));
     let mut asm = Asm::new(io);
     return asm.run(file);
@@ -5,6 +5,9 @@ import { VM, jumpToPc, render_memory } from "./before.js";
 await init();
 
 const Error = Object.freeze({ NONE: 0, FAIL: 1 });
+const RUN_CHUNK_STEPS = 10000;
+const MAX_INSTRUCTIONS = 50_000_000;
+const TRACE_MAX_LINES = 1000;
 
 // EVENT LISTENERS -----------------------------------------
 const inputStream = document.getElementById("inputStream");
@@ -37,7 +40,11 @@ async function inputToStream(e) {
   }
 
   if (await VM.is_awaiting_input()) {
-    await VM.set_reg(0, c.charCodeAt(0));
+    if (VM.input_goes_to_register()) {
+      await VM.set_reg(0, c.charCodeAt(0));
+    } else {
+      inputStream.value += c;
+    }
     await VM.set_awaiting_input(false);
     enableStepAndRunButtons();
     if (VM.get_is_running()) {
@@ -84,11 +91,32 @@ async function run() {
     return;
   }
 
+  narrationView.textContent = "";
+
+  // run in chunks so the page stays responsive, stopping at breakpoints and watchpoints
   while (!VM.is_halted()) {
-    await stepInstruction();
+    let reason = VM.run_until_event(RUN_CHUNK_STEPS);
+    updateTrace();
+    if (reason === "StepLimit") {
+      await new Promise((resolve) => setTimeout(resolve, 0));
+      continue;
+    }
 
-    let awaiting_input = await VM.is_awaiting_input();
-    if (awaiting_input) {
+    if (reason === "AwaitingInput") {
+      updateRenderSidePanel();
+      disableStepAndRunButtons();
+      return;
+    }
+    if (reason.LimitExceeded !== undefined) {
+      innerConsole.value += `\n[Stopped after ${MAX_INSTRUCTIONS} instructions. Is there an infinite loop?]\n`;
+      VM.set_is_running(false);
+      updateRenderSidePanel();
+      disableStepAndRunButtons();
+      return;
+    }
+    if (reason !== "Halted") {
+      console.log("Stopped:", reason);
+      VM.set_is_running(false);
       updateRenderSidePanel();
       return;
     }
@@ -123,6 +151,14 @@ stepButton.addEventListener("click", (e) => {
   stepInstruction();
 });
 
+const stepBackButton = document.getElementById("stepBackButton");
+stepBackButton.addEventListener("click", (e) => {
+  if (VM.step_back()) {
+    enableStepAndRunButtons();
+    updateRenderSidePanel();
+  }
+});
+
 const loadButton = document.getElementById("loadButton");
 loadButton.addEventListener("click", async (e) => {
   let file = editor.value;
@@ -135,19 +171,19 @@ loadButton.addEventListener("click", async (e) => {
 });
 
 async function loadToMachine(file) {
-  let binary = assemble(file);
+  let program = assemble(file);
 
-  if (binary === undefined) {
+  if (program === undefined) {
     return false;
   }
 
-  VM.set_pc(binary[0]);
-
   await VM.reset_machine();
-  VM.load_into_memory(binary);
+  VM.set_max_instructions(MAX_INSTRUCTIONS);
+  VM.load_into_memory(program);
 
   updateRegisterDisplay();
   render_memory(true);
+  updateExecutingLine();
 
   VM.set_program_loaded(true);
   enableStepAndRunButtons();
@@ -166,6 +202,9 @@ async function stepInstruction() {
   // }
 
   let stepResult = await VM.step();
+  updateTrace();
+  updateNarration();
+  updateExecutingLine();
 
   if (!VM.get_is_running()) {
     updateRenderSidePanel();
@@ -186,8 +225,25 @@ function updateRenderSidePanel() {
   updateRegisterDisplay();
   render_memory(true);
   jumpToPc();
+  updateExecutingLine();
+}
+
+// highlights the source line of the instruction the PC points at
+function updateExecutingLine() {
+  let line = VM.source_line(VM.get_pc());
+  if (line === undefined) {
+    editor.classList.remove("executing");
+    return;
+  }
+  editor.style.setProperty("--executing-line-index", line - 1);
+  editor.classList.add("executing");
 }
 
+// the highlighted line no longer matches once the source is edited
+editor.addEventListener("input", () => {
+  editor.classList.remove("executing");
+});
+
 function updateRegisterDisplay() {
   let r0 = VM.get_reg_value_as_hex(0);
   let r1 = VM.get_reg_value_as_hex(1);
@@ -199,6 +255,7 @@ function updateRegisterDisplay() {
   let r7 = VM.get_reg_value_as_hex(7);
   let pc = VM.get_pc_value_as_hex();
   let ir = VM.get_ir_value_as_hex();
+  let psr = VM.get_psr_value_as_hex();
   let n = VM.get_n_reg_value();
   let z = VM.get_z_reg_value();
   let p = VM.get_p_reg_value();
@@ -229,6 +286,7 @@ function updateRegisterDisplay() {
   document.getElementById("r7Value").innerHTML = r7;
   document.getElementById("pcValue").innerHTML = pc;
   document.getElementById("irValue").innerHTML = ir;
+  document.getElementById("psrValue").innerHTML = psr;
 
   handle_enable_bit(document.getElementById("nRegValue"), n, bool_to_int(n));
   handle_enable_bit(document.getElementById("zRegValue"), z, bool_to_int(z));
@@ -248,6 +306,63 @@ clearInputBufferButton.addEventListener("click", async (e) => {
   inputStream.value = "";
 });
 
+// NARRATION -----------------------------------------------
+const narrationView = document.getElementById("narrationView");
+
+// explains the instruction that was just stepped, e.g. "ADD R1, R1, #10: R1 ← x0000 + 10 = x000A"
+function updateNarration() {
+  let narration = VM.narrate_last_step();
+  if (narration === undefined) {
+    narrationView.textContent = "";
+    return;
+  }
+  let pc = narration.pc.toString(16).toUpperCase().padStart(4, "0");
+  let bits = narration.fields.map((field) => `${field.name}=${field.bits}`).join(" ");
+  narrationView.textContent = `x${pc}: ${narration.instruction}: ${narration.effects.join("; ")}\n${bits}`;
+}
+
+// PROFILER ------------------------------------------------
+// counts how often each address runs, and heat-maps the memory view with it
+const profileButton = document.getElementById("profileButton");
+let profiling = false;
+
+profileButton.addEventListener("click", (e) => {
+  profiling = !profiling;
+  VM.set_profiling(profiling);
+  profileButton.innerHTML = profiling ? "Profile: On" : "Profile: Off";
+  render_memory(true);
+});
+
+// EXECUTION TRACE -----------------------------------------
+const traceView = document.getElementById("traceView");
+const traceButton = document.getElementById("traceButton");
+let tracing = false;
+
+traceButton.addEventListener("click", (e) => {
+  tracing = !tracing;
+  VM.set_tracing(tracing);
+  traceButton.innerHTML = tracing ? "Trace: On" : "Trace: Off";
+});
+
+const clearTraceButton = document.getElementById("clearTraceButton");
+clearTraceButton.addEventListener("click", (e) => {
+  traceView.value = "";
+});
+
+// appends the lines recorded since the last update, keeping only the last TRACE_MAX_LINES
+function updateTrace() {
+  if (!tracing) {
+    return;
+  }
+  let lines = VM.take_trace();
+  if (lines === "") {
+    return;
+  }
+  let all = (traceView.value === "" ? lines : traceView.value + "\n" + lines).split("\n");
+  traceView.value = all.slice(-TRACE_MAX_LINES).join("\n");
+  traceView.scrollTop = traceView.scrollHeight;
+}
+
 function disableStepAndRunButtons() {
   stepButton.disabled = true;
   runButton.disabled = true;
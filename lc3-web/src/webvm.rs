@@ -2,17 +2,42 @@
  * &mut VM functions available in VM. Otherwise, type safetyu is violated.
  */
 use super::webio::WebIO;
+use lc3::asm::source_map::SourceMap;
 use lc3::io::Lc3IO;
+use lc3::program::Program;
+use lc3::vm::breakpoint::{Breakpoint, StopReason, Watchpoint};
+use lc3::vm::delta::StateDelta;
+use lc3::vm::history::DEFAULT_HISTORY_LIMIT;
+use lc3::vm::narrate::Narration;
+use lc3::vm::profile::Profile;
+use lc3::vm::trace::{TraceFormat, TraceRecord, TraceSink};
 use lc3::vm::vm::InstructionResult;
 use lc3::vm::vm::VM;
+use std::cell::RefCell;
+use std::rc::Rc;
 use wasm_bindgen::prelude::*;
 
+/// Keeps trace lines until the page takes them with `take_trace`.
+struct TraceBuffer {
+    lines: Rc<RefCell<Vec<String>>>,
+}
+
+impl TraceSink for TraceBuffer {
+    fn record(&mut self, record: &TraceRecord) {
+        self.lines
+            .borrow_mut()
+            .push(TraceFormat::Text.format(record));
+    }
+}
+
 #[wasm_bindgen]
 struct WebVM {
     vm: VM,
     awaiting_input: bool,
     is_running: bool,
     program_loaded: bool,
+    trace: Rc<RefCell<Vec<String>>>,
+    source_map: SourceMap,
 }
 
 #[allow(dead_code)]
@@ -20,15 +45,21 @@ struct WebVM {
 impl WebVM {
     #[wasm_bindgen(constructor)]
     pub fn new() -> WebVM {
+        let mut vm = VM::new(Lc3IO::new(Box::new(WebIO::new())));
+        vm.history.set_limit(DEFAULT_HISTORY_LIMIT);
         WebVM {
-            vm: VM::new(Lc3IO::new(Box::new(WebIO::new()))),
+            vm: vm,
             awaiting_input: false,
             is_running: false,
             program_loaded: false,
+            trace: Rc::new(RefCell::new(vec![])),
+            source_map: SourceMap::new(),
         }
     }
 
-    pub async fn step(&mut self) -> Result<(), JsValue> {
+    /// Runs one instruction, and returns everything it changed so the page can
+    /// update only the registers and memory that were touched.
+    pub fn step(&mut self) -> StateDelta {
         let result = self.vm.run_single_command();
         match result {
             InstructionResult::AwaitingInput => {
@@ -38,22 +69,138 @@ impl WebVM {
                 self.awaiting_input = false;
             }
         }
-        return Ok(());
+        return self.vm.last_delta();
+    }
+
+    /// A plain-English explanation of the instruction the last `step` ran.
+    pub fn narrate_last_step(&self) -> Option<Narration> {
+        return self.vm.narrate_last_step();
+    }
+
+    /// Runs at most `max_steps` instructions, stopping early at a breakpoint,
+    /// watchpoint, exception, halt, or when the program waits for input.
+    pub fn run_until_event(&mut self, max_steps: u32) -> StopReason {
+        let reason = self.vm.run_until_event(Some(max_steps as u64));
+        self.awaiting_input = reason == StopReason::AwaitingInput;
+        return reason;
+    }
+
+    /// Caps how many instructions a loaded program may run, so an infinite loop
+    /// stops with a `LimitExceeded` reason instead of running forever.
+    pub fn set_max_instructions(&mut self, max: Option<u32>) {
+        self.vm.limits.max_instructions = max.map(|max| max as u64);
+    }
+
+    /// Turns the per-instruction trace on or off. Lines are kept until
+    /// `take_trace` is called.
+    pub fn set_tracing(&mut self, enabled: bool) {
+        if enabled {
+            self.vm.tracer = Some(Box::new(TraceBuffer {
+                lines: self.trace.clone(),
+            }));
+        } else {
+            self.vm.tracer = None;
+            self.trace.borrow_mut().clear();
+        }
+    }
+
+    /// The trace lines recorded since the last call, one per instruction.
+    pub fn take_trace(&mut self) -> String {
+        let lines = std::mem::take(&mut *self.trace.borrow_mut());
+        return lines.join("\n");
+    }
+
+    /// Turns counting executed instructions per address on or off. The counts
+    /// start over whenever a program is loaded.
+    pub fn set_profiling(&mut self, enabled: bool) {
+        self.vm.profile = enabled.then(Profile::new);
+    }
+
+    /// How many times the instruction at `address` has run, for heat-mapping memory.
+    pub fn profile_hits(&self, address: u16) -> u32 {
+        let Some(profile) = &self.vm.profile else {
+            return 0;
+        };
+        return profile.executed.get(&address).copied().unwrap_or(0) as u32;
     }
 
-    pub fn load_into_memory(&mut self, file: Vec<u16>) {
-        self.vm.memory.load_file(file);
+    /// The most times any one address has run.
+    pub fn profile_max_hits(&self) -> u32 {
+        let Some(profile) = &self.vm.profile else {
+            return 0;
+        };
+        return profile
+            .hotspots(1)
+            .first()
+            .map_or(0, |(_, hits)| *hits as u32);
     }
 
+    /// Adds a breakpoint at `address`, or removes the one that is there.
+    /// Returns whether there is a breakpoint at `address` now.
+    pub fn toggle_breakpoint(&mut self, address: u16) -> bool {
+        if self.vm.breakpoints.remove(&address).is_some() {
+            return false;
+        }
+        self.vm.breakpoints.insert(address, Breakpoint::new());
+        return true;
+    }
+
+    pub fn has_breakpoint(&self, address: u16) -> bool {
+        return self.vm.breakpoints.contains_key(&address);
+    }
+
+    pub fn add_watchpoint(&mut self, start: u16, end: u16, on_read: bool, on_write: bool) {
+        self.vm
+            .memory
+            .watchpoints
+            .push(Watchpoint::new(start, end, on_read, on_write));
+    }
+
+    pub fn clear_watchpoints(&mut self) {
+        self.vm.memory.watchpoints.clear();
+    }
+
+    /// Undoes the last instruction. Returns `false` if there is no history left.
+    pub fn step_back(&mut self) -> bool {
+        self.awaiting_input = false;
+        return self.vm.step_back();
+    }
+
+    /// Steps backward until the PC is `address`. Returns `false` if the history
+    /// ran out first.
+    pub fn run_back_to(&mut self, address: u16) -> bool {
+        self.awaiting_input = false;
+        return self.vm.run_back_to(address);
+    }
+
+    /// Loads an assembled program and points the PC at its entry point. Its
+    /// source map is kept so addresses can be traced back to the editor.
+    pub fn load_into_memory(&mut self, program: Program) {
+        if let Err(msg) = self.vm.load(&program) {
+            self.vm.io.print_error(msg);
+            return;
+        }
+        self.source_map = program.debug_info.unwrap_or_default();
+    }
+
+    /// The 1-based source line the word at `address` was assembled from, if any.
+    pub fn source_line(&self, address: u16) -> Option<usize> {
+        return self.source_map.line(address);
+    }
+
+    /// Clears the machine and reloads the OS.
     pub async fn reset_machine(&mut self) {
-        self.vm.memory.clear();
-        self.vm.registers.halt = false;
-        self.vm.registers.n = false;
-        self.vm.registers.z = false;
-        self.vm.registers.p = false;
+        self.vm.reset();
         self.awaiting_input = false;
     }
 
+    /// Whether a typed character should be handed straight to R0, which is how
+    /// the fast-path GETC and IN wait for input. Otherwise the program is polling
+    /// the keyboard, and the character belongs in the input stream.
+    pub fn input_goes_to_register(&self) -> bool {
+        return self.awaiting_input && !self.vm.memory.devices.keyboard_starved;
+    }
+
     pub fn set_pc(&mut self, new_pc: u16) {
         self.vm.registers.pc = new_pc;
     }
@@ -63,7 +210,7 @@ impl WebVM {
     }
 
     pub fn is_halted(&mut self) -> bool {
-        return self.vm.registers.halt;
+        return self.vm.is_halted();
     }
 
     pub async fn set_reg(&mut self, reg: usize, value: u16) {
@@ -85,6 +232,23 @@ impl WebVM {
         return format!("x{:04X}", value);
     }
 
+    pub fn raise_interrupt(&mut self, vector: u8, priority: u8) {
+        self.vm.interrupts.raise(vector, priority);
+    }
+
+    pub fn get_psr_value_as_hex(&self) -> String {
+        let value = self.vm.registers.psr;
+        return format!("x{:04X}", value);
+    }
+
+    pub fn is_user_mode(&self) -> bool {
+        return self.vm.registers.is_user_mode();
+    }
+
+    pub fn get_priority(&self) -> u8 {
+        return self.vm.registers.priority();
+    }
+
     pub async fn is_awaiting_input(&self) -> bool {
         return self.awaiting_input;
     }
@@ -114,14 +278,14 @@ impl WebVM {
     }
 
     pub fn get_n_reg_value(&self) -> bool {
-        return self.vm.registers.n;
+        return self.vm.registers.n();
     }
 
     pub fn get_z_reg_value(&self) -> bool {
-        return self.vm.registers.z;
+        return self.vm.registers.z();
     }
 
     pub fn get_p_reg_value(&self) -> bool {
-        return self.vm.registers.p;
+        return self.vm.registers.p();
     }
 }
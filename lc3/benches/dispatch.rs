@@ -0,0 +1,351 @@
+//! Compares the decoded `match` dispatch (with and without the decode cache)
+//! against the dispatch it replaced: a `HashMap` lookup and a virtual call per
+//! step, with the fields bit-sliced out of the word one bit at a time.
+//!
+//! Run with `cargo bench -p lc3 --bench dispatch`.
+
+use lc3::asm::asm::Asm;
+use lc3::io::{DebugIO, Lc3IO};
+use lc3::program::Program;
+use lc3::vm::instructions::Instruction;
+use lc3::vm::vm::{TrapMode, VM};
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+const RUNS: u32 = 10;
+
+/// A loop that touches arithmetic, memory and branches, and runs a little
+/// over a million instructions.
+const PROGRAM: &str = "
+.orig x3000
+        ld r1, count
+outer   and r2, r2, #0
+        add r2, r2, #10
+inner   ld r3, value
+        add r3, r3, r2
+        st r3, value
+        ldr r4, r5, #0
+        not r4, r4
+        add r2, r2, #-1
+        brp inner
+        add r1, r1, #-1
+        brp outer
+        halt
+count   .fill #15000
+value   .fill #0
+.end
+";
+
+#[derive(Clone, Copy)]
+enum Dispatch {
+    /// The instructions as they were before the decoded dispatch.
+    BitSlicing,
+    Decoded,
+    DecodeCache,
+}
+
+fn vm(dispatch: Dispatch) -> VM {
+    let mut vm = VM::with_trap_mode(Lc3IO::new(Box::new(DebugIO::new())), TrapMode::FastPath);
+    vm.registers.r[5] = 0x3000;
+
+    match dispatch {
+        Dispatch::BitSlicing => {
+            // only the opcodes the loop runs; HALT runs once, so it is left to `execute`
+            let ins: Vec<(u8, Box<dyn Instruction>)> = vec![
+                (0, Box::new(old::Br {})),
+                (1, Box::new(old::Add {})),
+                (2, Box::new(old::Ld {})),
+                (3, Box::new(old::St {})),
+                (5, Box::new(old::And {})),
+                (6, Box::new(old::Ldr {})),
+                (9, Box::new(old::Not {})),
+            ];
+            for (opcode, i) in ins {
+                vm.instructions.insert(opcode, i);
+            }
+        }
+        Dispatch::Decoded => {}
+        Dispatch::DecodeCache => vm.set_decode_cache(true),
+    }
+    return vm;
+}
+
+fn bench(name: &str, program: &Program, dispatch: Dispatch) {
+    let mut total = Duration::ZERO;
+
+    for _ in 0..RUNS {
+        let mut vm = vm(dispatch);
+        let start = Instant::now();
+        vm.run(black_box(program))
+            .expect("benchmark program fits in memory");
+        total += start.elapsed();
+        assert!(vm.is_halted());
+        assert_eq!(vm.registers.r[1], 0);
+    }
+
+    println!("{name:<32} {:>10.2?} per run", total / RUNS);
+}
+
+fn main() {
+    let mut asm = Asm::new(Lc3IO::new(Box::new(DebugIO::new())));
+    let program = asm.run(PROGRAM.to_string()).expect("benchmark program");
+
+    bench("bit slicing (before)", &program, Dispatch::BitSlicing);
+    bench("decoded match", &program, Dispatch::Decoded);
+    bench(
+        "decoded match + decode cache",
+        &program,
+        Dispatch::DecodeCache,
+    );
+}
+
+/// The instructions the loop uses, as they were implemented before `Op`: every
+/// field is sliced out of the word on each run, and offsets are sign-extended
+/// by `get_offset` one bit at a time.
+mod old {
+    use lc3::io::Lc3IO;
+    use lc3::vm::instructions::Instruction;
+    use lc3::vm::memory::Memory;
+    use lc3::vm::registers::Registers;
+    use lc3::vm::vm::InstructionResult;
+
+    macro_rules! instructions {
+        ($($name:ident),*) => {
+            $(
+                #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+                pub struct $name;
+            )*
+        };
+    }
+
+    instructions!(Add, And, Br, Ld, Ldr, Not, St);
+
+    #[cfg_attr(feature = "serde", typetag::serde)]
+    impl Instruction for Add {
+        fn exe(
+            &self,
+            value: u16,
+            reg: &mut Registers,
+            _mem: &mut Memory,
+            _io: &mut Lc3IO,
+        ) -> InstructionResult {
+            let mut i = value;
+
+            let dr = i >> 9;
+            i -= dr << 9;
+
+            let sr1 = i >> 6;
+            i -= sr1 << 6;
+
+            let new_value: u16;
+            let code = get_bit_index(value, 5);
+
+            match code {
+                0 => {
+                    let sr2 = i;
+
+                    let v1 = reg.get(sr1 as usize);
+                    let v2 = reg.get(sr2 as usize);
+
+                    new_value = (v1 as i16).wrapping_add(v2 as i16) as u16;
+                }
+                1 => {
+                    let reg_val = reg.get(sr1 as usize);
+                    let imm_val = get_offset(value, 5);
+                    new_value = (reg_val as i16).wrapping_add(imm_val as i16) as u16;
+                }
+                _ => unreachable!(),
+            }
+
+            reg.set(dr as usize, new_value);
+
+            set_nzp(reg, new_value);
+            return InstructionResult::Ready;
+        }
+    }
+
+    #[cfg_attr(feature = "serde", typetag::serde)]
+    impl Instruction for And {
+        fn exe(
+            &self,
+            value: u16,
+            reg: &mut Registers,
+            _mem: &mut Memory,
+            _io: &mut Lc3IO,
+        ) -> InstructionResult {
+            let mut i = value;
+
+            let dr = i >> 9;
+            i -= dr << 9;
+
+            let sr1 = i >> 6;
+            i -= sr1 << 6;
+
+            let code = get_bit_index(value, 5);
+
+            let new_value = match code {
+                0 => reg.get(sr1 as usize) & reg.get(i as usize),
+                1 => reg.get(sr1 as usize) & get_offset(value, 5),
+                _ => unreachable!(),
+            };
+            reg.set(dr as usize, new_value);
+
+            set_nzp(reg, new_value);
+            return InstructionResult::Ready;
+        }
+    }
+
+    #[cfg_attr(feature = "serde", typetag::serde)]
+    impl Instruction for Br {
+        fn exe(
+            &self,
+            value: u16,
+            reg: &mut Registers,
+            _mem: &mut Memory,
+            _io: &mut Lc3IO,
+        ) -> InstructionResult {
+            let n = get_bit_index(value, 11);
+            let z = get_bit_index(value, 10);
+            let p = get_bit_index(value, 9);
+
+            if (n == 1 && z == 1 && p == 1)
+                || (n == 1 && reg.n())
+                || (z == 1 && reg.z())
+                || (p == 1 && reg.p())
+            {
+                let pcoffset9 = get_offset(value, 9);
+                reg.pc = get_pcoffset_location(reg, pcoffset9);
+            }
+            return InstructionResult::Ready;
+        }
+    }
+
+    #[cfg_attr(feature = "serde", typetag::serde)]
+    impl Instruction for Ld {
+        fn exe(
+            &self,
+            value: u16,
+            reg: &mut Registers,
+            mem: &mut Memory,
+            _io: &mut Lc3IO,
+        ) -> InstructionResult {
+            let dr = value >> 9;
+            let pcoffset9 = get_offset(value, 9);
+            let relative_pc_address = get_pcoffset_location(reg, pcoffset9);
+
+            let new_value = mem.get(relative_pc_address);
+            set_nzp(reg, new_value);
+            reg.set(dr as usize, new_value);
+            return InstructionResult::Ready;
+        }
+    }
+
+    #[cfg_attr(feature = "serde", typetag::serde)]
+    impl Instruction for Ldr {
+        fn exe(
+            &self,
+            value: u16,
+            reg: &mut Registers,
+            mem: &mut Memory,
+            _io: &mut Lc3IO,
+        ) -> InstructionResult {
+            let mut buffer = value;
+            let dr = buffer >> 9;
+            buffer -= dr << 9;
+            let base_r = buffer >> 6;
+
+            let offset = get_offset(value, 6);
+            let address = reg.get(base_r as usize);
+
+            let target_location = (address as i16).wrapping_add(offset as i16) as u16;
+
+            let new_value = mem.get(target_location);
+            set_nzp(reg, new_value);
+            reg.set(dr as usize, new_value);
+            return InstructionResult::Ready;
+        }
+    }
+
+    #[cfg_attr(feature = "serde", typetag::serde)]
+    impl Instruction for Not {
+        fn exe(
+            &self,
+            value: u16,
+            reg: &mut Registers,
+            _mem: &mut Memory,
+            _io: &mut Lc3IO,
+        ) -> InstructionResult {
+            let mut i = value;
+            let dr = i >> 9;
+            i -= dr << 9;
+            let sr = i >> 6;
+
+            let not_val = !reg.get(sr as usize);
+            reg.set(dr as usize, not_val);
+
+            set_nzp(reg, not_val);
+            return InstructionResult::Ready;
+        }
+    }
+
+    #[cfg_attr(feature = "serde", typetag::serde)]
+    impl Instruction for St {
+        fn exe(
+            &self,
+            value: u16,
+            reg: &mut Registers,
+            mem: &mut Memory,
+            _io: &mut Lc3IO,
+        ) -> InstructionResult {
+            let sr = value >> 9;
+            let pcoffset9 = get_offset(value, 9);
+            let location = get_pcoffset_location(reg, pcoffset9);
+
+            mem.set(location, reg.get(sr as usize));
+            return InstructionResult::Ready;
+        }
+    }
+
+    fn get_offset(mut value: u16, num_bits: i32) -> u16 {
+        let mut pos: u32 = 1;
+        let mut buf: u16 = 0;
+        let mut bit = 0;
+
+        for _ in 0..num_bits {
+            bit = (value % 2) * pos as u16;
+            buf += bit;
+            pos *= 2;
+            value >>= 1;
+        }
+
+        // value is negative if the last bit was not zero
+        if bit > 1 {
+            let remaining_bits = 16 - num_bits;
+
+            for _ in 0..remaining_bits {
+                buf += pos as u16;
+                pos *= 2;
+            }
+        }
+
+        return buf;
+    }
+
+    fn get_bit_index(value: u16, index: i32) -> u16 {
+        return value >> index & 1;
+    }
+
+    fn set_nzp(reg: &mut Registers, value: u16) {
+        let signed = value as i16;
+        reg.set_cc(signed < 0, signed == 0, signed > 0);
+    }
+
+    fn get_pcoffset_location(reg: &Registers, value: u16) -> u16 {
+        if value as i16 >= 0 {
+            return reg.pc + value;
+        }
+        let negated_value = !(value as i16) + 1;
+
+        return reg.pc - negated_value as u16;
+    }
+}
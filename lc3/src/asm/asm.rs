@@ -2,10 +2,11 @@ use super::asm_ins::OpcodeIns;
 use super::directive::Directive;
 use super::lexer::*;
 use super::semantic::*;
+use super::source_map::{SourceLocation, SourceMap, Span};
 use super::token::*;
 use crate::io::*;
-use std::fs;
-use std::io::Write;
+use crate::program::{Program, Segment};
+use std::collections::HashMap;
 
 #[allow(dead_code)]
 pub struct Asm {
@@ -14,6 +15,7 @@ pub struct Asm {
     token_index: usize,
     memory_location: usize,
     io: Lc3IO,
+    source_map: SourceMap,
 }
 
 #[allow(dead_code)]
@@ -25,10 +27,11 @@ impl Asm {
             token_index: 0,
             memory_location: 0,
             io: io,
+            source_map: SourceMap::new(),
         }
     }
 
-    pub fn run(&mut self, input_file: String) -> Option<Vec<u16>> {
+    pub fn run(&mut self, input_file: String) -> Option<Program> {
         // 1. Verify that file is syntactically valid
         if !self
             .lexer
@@ -61,45 +64,109 @@ impl Asm {
 
         // self.symbol_table = self.semantic_checker.symbol_table;
 
-        // 4. Assemble Vec<Token> into binary Vec<u16> & Symbol Table
+        // 4. Assemble Vec<Token> into a Program with its symbol table and source map
         return Some(self.assemble(tokens));
     }
 
-    pub fn assemble(&mut self, tokens: Vec<Token>) -> Vec<u16> {
+    /// Every label defined in the last file that was run, with its address.
+    pub fn symbol_table(&self) -> HashMap<String, u16> {
+        return self
+            .semantic_checker
+            .symbol_table
+            .iter()
+            .map(|(label, (address, _))| (label.clone(), *address as u16))
+            .collect();
+    }
+
+    /// The source line of every word in the last file that was run.
+    pub fn source_map(&self) -> &SourceMap {
+        return &self.source_map;
+    }
+
+    pub fn assemble(&mut self, tokens: Vec<Token>) -> Program {
         // Every token is already assumed completely semantically valid. Therefore, there
         // are no errors that should occur in this step. If we receive an instruction, it is
         // guaranteed to have all of its operands.
 
-        let mut binary_file: Vec<u16> = vec![];
+        let mut segments: Vec<Segment> = vec![];
+        self.source_map = SourceMap::new();
 
         self.set_origin(&tokens);
-        binary_file.push(self.memory_location as u16);
+        segments.push(Segment::new(self.memory_location as u16, vec![]));
 
+        // a label names the next word, which may be on a later line
+        let mut label: Option<String> = None;
         while self.token_index < tokens.len() {
-            if let TokenType::Label(_) = tokens[self.token_index].inner_token {
+            if let TokenType::Label(name) = &tokens[self.token_index].inner_token {
+                label = Some(name.clone());
                 self.token_index += 1;
                 continue;
             }
 
+            // every section after the first starts a segment of its own
+            if let TokenType::Directive(Directive::ORIG) = &tokens[self.token_index].inner_token {
+                self.set_origin(&tokens);
+                segments.push(Segment::new(self.memory_location as u16, vec![]));
+                continue;
+            }
+
+            let first = self.token_index;
+            let start = self.memory_location;
+            let words = &mut segments.last_mut().unwrap().words;
             match &tokens[self.token_index].inner_token {
                 TokenType::Instruction(instruction) => {
                     self.increment();
-                    binary_file.push(self.handle_instruction(instruction, &tokens));
+                    words.push(self.handle_instruction(instruction, &tokens));
                 }
                 TokenType::Directive(directive) => {
                     self.token_index += 1;
-                    let memory_vec = self.handle_directive(directive, &tokens);
-                    for value in memory_vec {
-                        binary_file.push(value);
-                    }
+                    words.extend(self.handle_directive(directive, &tokens));
                 }
                 _ => {
                     unreachable!();
                 }
             }
+
+            let instruction = matches!(tokens[first].inner_token, TokenType::Instruction(_));
+            for address in start..self.memory_location {
+                let location = self.locate(&tokens[first], &tokens[self.token_index - 1]);
+                self.source_map.insert(
+                    address as u16,
+                    SourceLocation {
+                        label: if address == start { label.take() } else { None },
+                        instruction: instruction,
+                        ..location
+                    },
+                );
+            }
         }
 
-        return binary_file;
+        // a section with nothing in it loads nothing
+        let entry = segments[0].origin;
+        segments.retain(|segment| !segment.words.is_empty());
+
+        let mut program = Program::new(entry, vec![]);
+        program.segments = segments;
+        program.symbols = self.symbol_table();
+        program.debug_info = Some(self.source_map.clone());
+        return program;
+    }
+
+    /// Where the statement from token `first` to token `last` is in the source.
+    fn locate(&self, first: &Token, last: &Token) -> SourceLocation {
+        return SourceLocation {
+            line: first.line_num,
+            columns: Span {
+                start: first.from,
+                end: last.to + 1,
+            },
+            offsets: Span {
+                start: first.file_relative_from,
+                end: last.file_relative_to + 1,
+            },
+            label: None,
+            instruction: false,
+        };
     }
 
     pub fn increment(&mut self) {
@@ -115,7 +182,7 @@ impl Asm {
         self.token_index += 1; // skip .orig
 
         if let TokenType::Number(origin) = tokens[self.token_index].inner_token {
-            self.memory_location = origin as usize;
+            self.memory_location = origin as u16 as usize;
             self.token_index += 1;
         } else {
             unreachable!();
@@ -126,14 +193,19 @@ impl Asm {
 
         match directive {
             Directive::END => return output,
-            Directive::FILL => {
-                if let TokenType::Number(value) = tokens[self.token_index].inner_token {
-                    output.push(value as u16);
+            Directive::FILL => match &tokens[self.token_index].inner_token {
+                TokenType::Number(value) => {
+                    output.push(*value as u16);
                     self.memory_location += 1;
-                } else {
-                    unreachable!();
                 }
-            }
+                TokenType::Label(label) => {
+                    let (address, _) = self.semantic_checker.symbol_table.get(label)
+                        .expect(&format!("Expected that the label `{}` would be defined and verified in the semantic checker", label));
+                    output.push(*address as u16);
+                    self.memory_location += 1;
+                }
+                _ => unreachable!(),
+            },
             Directive::BLKW => {
                 if let TokenType::Number(count) = tokens[self.token_index].inner_token {
                     for _ in 0..count {
@@ -437,16 +509,6 @@ impl Asm {
         // println!("cut_imm \t= {:#018b}", cut_imm);
         return instruction + cut_imm;
     }
-
-    pub fn emit_bin(&self, binary_file: &Vec<u16>, output_name: String) {
-        let mut file = fs::File::create(output_name.clone())
-            .expect(&format!("Was unable to make `{}`", output_name));
-
-        for command in binary_file {
-            file.write(&command.to_be_bytes())
-                .expect("Could not emit binary correctly");
-        }
-    }
 }
 
 #[cfg(test)]
@@ -489,8 +551,8 @@ mod tests {
 
         let bin = asm.assemble(stream);
 
-        assert!(bin[0] == 3000);
-        assert!(bin.len() == 1);
+        assert!(bin.entry == 3000);
+        assert!(bin.is_empty());
 
         let mut asm = Asm::new(Lc3IO::new(Box::new(DebugIO::new())));
 
@@ -501,8 +563,8 @@ mod tests {
 
         let bin = asm.assemble(stream);
 
-        assert!(bin[0] == 42);
-        assert!(bin.len() == 1);
+        assert!(bin.entry == 42);
+        assert!(bin.is_empty());
     }
 
     #[test]
@@ -518,8 +580,27 @@ mod tests {
 
         let bin = asm.assemble(stream);
 
-        assert!(bin[1] == 0b0000_0000_0000_1010);
-        assert!(bin[2] == 1999);
+        assert!(bin.segments[0].words[0] == 0b0000_0000_0000_1010);
+        assert!(bin.segments[0].words[1] == 1999);
+    }
+
+    #[test]
+    fn test_asm_directive_fill_label() {
+        let mut asm = Asm::new(Lc3IO::new(Box::new(DebugIO::new())));
+
+        asm.semantic_checker.symbol_table.insert(
+            String::from("routine"),
+            (0x0200, mk_token(TokenType::Label(String::from("routine")))),
+        );
+
+        let stream = get_file(vec![
+            TokenType::Directive(Directive::FILL),
+            TokenType::Label(String::from("routine")),
+        ]);
+
+        let bin = asm.assemble(stream);
+
+        assert_eq!(bin.segments[0].words[0], 0x0200);
     }
 
     #[test]
@@ -533,10 +614,10 @@ mod tests {
 
         let bin = asm.assemble(stream);
 
-        assert!(bin[1] == 0);
-        assert!(bin[2] == 0);
-        assert!(bin[3] == 0);
-        assert!(bin.len() == 4);
+        assert!(bin.segments[0].words[0] == 0);
+        assert!(bin.segments[0].words[1] == 0);
+        assert!(bin.segments[0].words[2] == 0);
+        assert!(bin.len() == 3);
     }
 
     #[test]
@@ -550,17 +631,17 @@ mod tests {
 
         let bin = asm.assemble(stream);
 
-        assert!(bin[1] as u8 == 'H' as u8);
-        assert!(bin[2] as u8 == 'E' as u8);
-        assert!(bin[3] as u8 == 'L' as u8);
-        assert!(bin[4] as u8 == 'P' as u8);
-        assert!(bin[5] as u8 == ' ' as u8);
-        assert!(bin[6] as u8 == 'M' as u8);
-        assert!(bin[7] as u8 == 'E' as u8);
-        assert!(bin[8] as u8 == '!' as u8);
-        assert!(bin[9] as u8 == '\0' as u8);
-
-        assert!(bin.len() == 10);
+        assert!(bin.segments[0].words[0] as u8 == 'H' as u8);
+        assert!(bin.segments[0].words[1] as u8 == 'E' as u8);
+        assert!(bin.segments[0].words[2] as u8 == 'L' as u8);
+        assert!(bin.segments[0].words[3] as u8 == 'P' as u8);
+        assert!(bin.segments[0].words[4] as u8 == ' ' as u8);
+        assert!(bin.segments[0].words[5] as u8 == 'M' as u8);
+        assert!(bin.segments[0].words[6] as u8 == 'E' as u8);
+        assert!(bin.segments[0].words[7] as u8 == '!' as u8);
+        assert!(bin.segments[0].words[8] as u8 == '\0' as u8);
+
+        assert!(bin.len() == 9);
     }
 
     #[test]
@@ -576,7 +657,7 @@ mod tests {
 
         let bin = asm.assemble(stream);
 
-        assert_eq!(bin[1], 0b0001_001_001_1_01010);
+        assert_eq!(bin.segments[0].words[0], 0b0001_001_001_1_01010);
     }
 
     #[test]
@@ -592,7 +673,7 @@ mod tests {
 
         let bin = asm.assemble(stream);
 
-        assert_eq!(bin[1], 0b0001_001_001_0_00_111);
+        assert_eq!(bin.segments[0].words[0], 0b0001_001_001_0_00_111);
     }
 
     #[test]
@@ -608,7 +689,7 @@ mod tests {
 
         let bin = asm.assemble(stream);
 
-        assert_eq!(bin[1], 0b0101_001_001_1_01010);
+        assert_eq!(bin.segments[0].words[0], 0b0101_001_001_1_01010);
     }
 
     #[test]
@@ -624,7 +705,7 @@ mod tests {
 
         let bin = asm.assemble(stream);
 
-        assert_eq!(bin[1], 0b0101_001_001_0_00_111);
+        assert_eq!(bin.segments[0].words[0], 0b0101_001_001_0_00_111);
     }
 
     #[test]
@@ -657,14 +738,14 @@ mod tests {
 
         let bin = asm.assemble(stream);
 
-        assert_eq!(bin[1], 0b0000_000_000001001);
-        assert_eq!(bin[2], 0b0000_001_000001000);
-        assert_eq!(bin[3], 0b0000_010_000000111);
-        assert_eq!(bin[4], 0b0000_011_000000110);
-        assert_eq!(bin[5], 0b0000_100_000000101);
-        assert_eq!(bin[6], 0b0000_101_000000100);
-        assert_eq!(bin[7], 0b0000_110_000000011);
-        assert_eq!(bin[8], 0b0000_111_000000010);
+        assert_eq!(bin.segments[0].words[0], 0b0000_000_000001001);
+        assert_eq!(bin.segments[0].words[1], 0b0000_001_000001000);
+        assert_eq!(bin.segments[0].words[2], 0b0000_010_000000111);
+        assert_eq!(bin.segments[0].words[3], 0b0000_011_000000110);
+        assert_eq!(bin.segments[0].words[4], 0b0000_100_000000101);
+        assert_eq!(bin.segments[0].words[5], 0b0000_101_000000100);
+        assert_eq!(bin.segments[0].words[6], 0b0000_110_000000011);
+        assert_eq!(bin.segments[0].words[7], 0b0000_111_000000010);
     }
 
     #[test]
@@ -683,7 +764,7 @@ mod tests {
 
         let bin = asm.assemble(stream);
 
-        assert_eq!(bin[1], 0b0100_1_00011111111); // because the pc is one ahead of the current instruction
+        assert_eq!(bin.segments[0].words[0], 0b0100_1_00011111111); // because the pc is one ahead of the current instruction
     }
 
     #[test]
@@ -697,7 +778,7 @@ mod tests {
 
         let bin = asm.assemble(stream);
 
-        assert_eq!(bin[1], 0b0100_000_110_000000); // because the pc is one ahead of the current instruction
+        assert_eq!(bin.segments[0].words[0], 0b0100_000_110_000000); // because the pc is one ahead of the current instruction
     }
 
     #[test]
@@ -717,7 +798,7 @@ mod tests {
 
         let bin = asm.assemble(stream);
 
-        assert_eq!(bin[1], 0b0010_001_000000000);
+        assert_eq!(bin.segments[0].words[0], 0b0010_001_000000000);
     }
 
     #[test]
@@ -737,7 +818,7 @@ mod tests {
 
         let bin = asm.assemble(stream);
 
-        assert_eq!(bin[1], 0b1010_001_000000000);
+        assert_eq!(bin.segments[0].words[0], 0b1010_001_000000000);
     }
 
     #[test]
@@ -753,7 +834,7 @@ mod tests {
 
         let bin = asm.assemble(stream);
 
-        assert_eq!(bin[1], 0b0110_000_111_010000);
+        assert_eq!(bin.segments[0].words[0], 0b0110_000_111_010000);
     }
 
     #[test]
@@ -773,7 +854,7 @@ mod tests {
 
         let bin = asm.assemble(stream);
 
-        assert_eq!(bin[1], 0b1110_001_000000000);
+        assert_eq!(bin.segments[0].words[0], 0b1110_001_000000000);
     }
 
     #[test]
@@ -788,7 +869,7 @@ mod tests {
 
         let bin = asm.assemble(stream);
 
-        assert_eq!(bin[1], 0b1001_001_001_111111);
+        assert_eq!(bin.segments[0].words[0], 0b1001_001_001_111111);
     }
 
     #[test]
@@ -808,7 +889,7 @@ mod tests {
 
         let bin = asm.assemble(stream);
 
-        assert_eq!(bin[1], 0b0011_001_000000000);
+        assert_eq!(bin.segments[0].words[0], 0b0011_001_000000000);
     }
 
     #[test]
@@ -828,7 +909,7 @@ mod tests {
 
         let bin = asm.assemble(stream);
 
-        assert_eq!(bin[1], 0b1011_001_000000000);
+        assert_eq!(bin.segments[0].words[0], 0b1011_001_000000000);
     }
 
     #[test]
@@ -844,7 +925,7 @@ mod tests {
 
         let bin = asm.assemble(stream);
 
-        assert_eq!(bin[1], 0b0111_000_111_010000);
+        assert_eq!(bin.segments[0].words[0], 0b0111_000_111_010000);
     }
 
     #[test]
@@ -863,13 +944,13 @@ mod tests {
 
         let bin = asm.assemble(stream);
 
-        assert_eq!(bin[1], 0b1111_0000_0001_0100);
-        assert_eq!(bin[2], 0b1111_0000_0001_0101);
-        assert_eq!(bin[3], 0b1111_0000_0001_0110);
-        assert_eq!(bin[4], 0b1111_0000_0001_0111);
-        assert_eq!(bin[5], 0b1111_0000_0001_1000);
-        assert_eq!(bin[6], 0b1111_0000_0001_1001);
-        assert_eq!(bin[7], 0b1111_0000_0010_0000);
+        assert_eq!(bin.segments[0].words[0], 0b1111_0000_0001_0100);
+        assert_eq!(bin.segments[0].words[1], 0b1111_0000_0001_0101);
+        assert_eq!(bin.segments[0].words[2], 0b1111_0000_0001_0110);
+        assert_eq!(bin.segments[0].words[3], 0b1111_0000_0001_0111);
+        assert_eq!(bin.segments[0].words[4], 0b1111_0000_0001_1000);
+        assert_eq!(bin.segments[0].words[5], 0b1111_0000_0001_1001);
+        assert_eq!(bin.segments[0].words[6], 0b1111_0000_0010_0000);
     }
 
     #[test]
@@ -897,7 +978,73 @@ mod tests {
 
         let bin = asm.assemble(stream);
 
-        assert_eq!(bin[1], 0b1011_001_111111111);
-        assert_eq!(bin[2], 0b1011_001_000000001);
+        assert_eq!(bin.segments[0].words[0], 0b1011_001_111111111);
+        assert_eq!(bin.segments[0].words[1], 0b1011_001_000000001);
+    }
+
+    #[test]
+    fn test_multiple_sections() {
+        let mut asm = Asm::new(Lc3IO::new(Box::new(DebugIO::new())));
+        let program = asm
+            .run(
+                ".orig x3000
+        ldi r0, ptr
+        halt
+ptr     .fill data
+.end
+
+.orig x4000
+data    .fill x1234
+        .fill ptr
+.end"
+                    .to_string(),
+            )
+            .unwrap();
+
+        assert_eq!(program.entry, 0x3000);
+        assert_eq!(
+            program.segments,
+            vec![
+                Segment::new(0x3000, vec![0b1010_000_000000001, 0xF025, 0x4000]),
+                Segment::new(0x4000, vec![0x1234, 0x3002]),
+            ]
+        );
+        assert_eq!(program.symbols["data"], 0x4000);
+        assert_eq!(asm.source_map().line(0x4001), Some(9));
+    }
+
+    #[test]
+    fn test_source_map() {
+        let mut asm = Asm::new(Lc3IO::new(Box::new(DebugIO::new())));
+        asm.run(
+            ".orig x3000
+loop    add r1, r1, #-1
+        brp loop
+msg     .stringz \"hi\"
+.end"
+                .to_string(),
+        )
+        .unwrap();
+
+        let map = asm.source_map();
+        assert_eq!(
+            map.get(0x3000),
+            Some(&SourceLocation {
+                line: 2,
+                columns: Span { start: 8, end: 23 },
+                offsets: Span { start: 20, end: 35 },
+                label: Some("loop".to_string()),
+                instruction: true
+            })
+        );
+        assert_eq!(map.address_of("msg"), Some(0x3002));
+        assert_eq!(map.get(0x3001).unwrap().label, None);
+        assert_eq!(map.line(0x3001), Some(3));
+        assert_eq!(map.instructions_on(3), vec![0x3001]);
+        // "hi" and its terminator all come from line 4
+        assert_eq!(map.line(0x3004), Some(4));
+        assert!(!map.get(0x3004).unwrap().instruction);
+        assert_eq!(map.get(0x3004).unwrap().columns, Span { start: 8, end: 21 });
+        assert_eq!(map.get(0x3005), None);
     }
 }
@@ -23,6 +23,7 @@ pub enum OperandType {
     Label,
     Imm,
     RegOrImm,
+    ImmOrLabel,
     String,
 }
 
@@ -58,6 +59,7 @@ impl OperandType {
             OperandType::Label => "a label".to_string(),
             OperandType::Imm => "an immediate value".to_string(),
             OperandType::RegOrImm => "a register or immediate value".to_string(),
+            OperandType::ImmOrLabel => "an immediate value or label".to_string(),
             OperandType::String => "a string".to_string(),
         }
     }
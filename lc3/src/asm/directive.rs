@@ -30,9 +30,8 @@ impl Directive {
 
     pub fn get_expected_operands(&self) -> VecDeque<OperandType> {
         match self {
-            Directive::ORIG | Directive::FILL | Directive::BLKW => {
-                vec![OperandType::Imm].into_iter().collect()
-            }
+            Directive::ORIG | Directive::BLKW => vec![OperandType::Imm].into_iter().collect(),
+            Directive::FILL => vec![OperandType::ImmOrLabel].into_iter().collect(),
             Directive::STRINGZ => vec![OperandType::String].into_iter().collect(),
             _ => vec![].into_iter().collect(),
         }
@@ -0,0 +1,458 @@
+// Memory images for hardware courses that build the LC-3 in Verilog, on an FPGA
+// or in Logisim: the same words a program loads into memory, in the text formats
+// those tools read.
+
+use crate::program::{Program, Segment};
+
+/// How many words an Intel HEX data record holds.
+const IHEX_RECORD_WORDS: usize = 8;
+
+/// Runs of at least this many equal words are written as `count*value` in
+/// Logisim images.
+const LOGISIM_MIN_RUN: usize = 4;
+
+/// How many words or runs go on each line of a Logisim image.
+const LOGISIM_LINE_ITEMS: usize = 8;
+
+const LOGISIM_HEADER: &str = "v2.0 raw";
+
+/// Text formats of a memory image, for loading programs into hardware
+/// simulations of the LC-3.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ImageFormat {
+    /// Hex words, one per line, read by Verilog's `$readmemh`. `@addr` lines
+    /// move to another address.
+    ReadMemH,
+    /// The same, with 16-digit binary words, for `$readmemb`.
+    ReadMemB,
+    /// Intel HEX. Addresses count 16-bit words rather than bytes, the way
+    /// Quartus reads images for memories wider than a byte, and each word is
+    /// stored high byte first.
+    IntelHex,
+    /// The "v2.0 raw" format Logisim's RAM and ROM components load. It always
+    /// starts at address 0.
+    Logisim,
+}
+
+impl ImageFormat {
+    pub fn parse(name: &str) -> Result<ImageFormat, String> {
+        match name.to_lowercase().as_str() {
+            "readmemh" => return Ok(ImageFormat::ReadMemH),
+            "readmemb" => return Ok(ImageFormat::ReadMemB),
+            "ihex" | "intel-hex" => return Ok(ImageFormat::IntelHex),
+            "logisim" => return Ok(ImageFormat::Logisim),
+            _ => {
+                return Err(format!(
+                    "`{name}` is not an image format. Use readmemh, readmemb, ihex or logisim"
+                ));
+            }
+        }
+    }
+
+    /// Guesses the format of an image from its contents.
+    pub fn detect(text: &str) -> ImageFormat {
+        let mut words = text
+            .lines()
+            .map(|line| line.split("//").next().unwrap_or("").trim())
+            .filter(|line| !line.is_empty());
+
+        match words.next() {
+            Some(LOGISIM_HEADER) => return ImageFormat::Logisim,
+            Some(line) if line.starts_with(':') => return ImageFormat::IntelHex,
+            _ => {}
+        }
+
+        let binary = text
+            .split_whitespace()
+            .filter(|word| !word.starts_with('@') && !word.starts_with("//"))
+            .all(|word| word.replace('_', "").len() == 16);
+        if binary {
+            return ImageFormat::ReadMemB;
+        }
+        return ImageFormat::ReadMemH;
+    }
+}
+
+/// The addresses an exported image covers, or the part of an image that is
+/// imported, both ends included.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ImageRange {
+    pub start: u16,
+    pub end: u16,
+}
+
+impl ImageRange {
+    /// From the lowest address `program` fills to the highest.
+    pub fn of(program: &Program) -> ImageRange {
+        let start = program.segments.iter().map(|s| s.origin).min().unwrap_or(0);
+        let end = program
+            .segments
+            .iter()
+            .filter(|s| !s.words.is_empty())
+            .map(|s| (s.end() - 1) as u16)
+            .max()
+            .unwrap_or(start);
+        return ImageRange {
+            start: start,
+            end: end,
+        };
+    }
+
+    /// Parses a range written as `<start>-<end>`, e.g. `x3000-x30FF`.
+    pub fn parse(s: &str) -> Result<ImageRange, String> {
+        let Some((start, end)) = s.split_once('-') else {
+            return Err(format!("`{s}` is not a range like `x3000-x30FF`"));
+        };
+        let range = ImageRange {
+            start: parse_address(start)?,
+            end: parse_address(end)?,
+        };
+        if range.start > range.end {
+            return Err(format!("The range `{s}` ends before it starts"));
+        }
+        return Ok(range);
+    }
+}
+
+fn parse_address(s: &str) -> Result<u16, String> {
+    let s = s.trim();
+    let parsed = match s.strip_prefix(['x', 'X']) {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => s.trim_start_matches('#').parse::<u16>(),
+    };
+    return parsed.map_err(|_| format!("`{s}` is not a valid address"));
+}
+
+/// The words of `program` from `range.start` to `range.end`, with `fill` in every
+/// address the program leaves empty.
+fn words_in(program: &Program, range: ImageRange, fill: u16) -> Vec<u16> {
+    return (range.start..=range.end)
+        .map(|address| program.word_at(address).unwrap_or(fill))
+        .collect();
+}
+
+/// `program` as an image of `range`, with `fill` in every address the program
+/// leaves empty. Logisim images always start at address 0, so they are filled
+/// from there up to `range.start` too.
+pub fn export(program: &Program, format: ImageFormat, range: ImageRange, fill: u16) -> String {
+    match format {
+        ImageFormat::ReadMemH | ImageFormat::ReadMemB => {
+            let mut out = format!("@{:04X}\n", range.start);
+            for word in words_in(program, range, fill) {
+                match format {
+                    ImageFormat::ReadMemH => out += &format!("{word:04X}\n"),
+                    _ => out += &format!("{word:016b}\n"),
+                }
+            }
+            return out;
+        }
+        ImageFormat::IntelHex => {
+            let words = words_in(program, range, fill);
+            let mut out = String::new();
+            for (i, chunk) in words.chunks(IHEX_RECORD_WORDS).enumerate() {
+                let address = range.start as usize + i * IHEX_RECORD_WORDS;
+                let data: Vec<u8> = chunk.iter().flat_map(|word| word.to_be_bytes()).collect();
+                out += &ihex_record(address as u16, 0x00, &data);
+            }
+            out += &ihex_record(0, 0x01, &[]);
+            return out;
+        }
+        ImageFormat::Logisim => {
+            let words = words_in(
+                program,
+                ImageRange {
+                    start: 0,
+                    end: range.end,
+                },
+                fill,
+            );
+            let mut out = format!("{LOGISIM_HEADER}\n");
+            let mut items = vec![];
+            let mut i = 0;
+            while i < words.len() {
+                let run = words[i..]
+                    .iter()
+                    .take_while(|word| **word == words[i])
+                    .count();
+                if run >= LOGISIM_MIN_RUN {
+                    items.push(format!("{run}*{:x}", words[i]));
+                    i += run;
+                } else {
+                    items.push(format!("{:x}", words[i]));
+                    i += 1;
+                }
+            }
+            for line in items.chunks(LOGISIM_LINE_ITEMS) {
+                out += &line.join(" ");
+                out += "\n";
+            }
+            return out;
+        }
+    }
+}
+
+/// One Intel HEX record, with its checksum.
+fn ihex_record(address: u16, kind: u8, data: &[u8]) -> String {
+    let mut bytes = vec![data.len() as u8];
+    bytes.extend(address.to_be_bytes());
+    bytes.push(kind);
+    bytes.extend(data);
+    let sum = bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+    bytes.push(sum.wrapping_neg());
+
+    let hex: String = bytes.iter().map(|byte| format!("{byte:02X}")).collect();
+    return format!(":{hex}\n");
+}
+
+/// Reads an image back into a program, with a segment for each run of
+/// consecutive addresses it fills. Only the words in `range` are kept, if it is
+/// given, since a Logisim image also fills everything below the program. The
+/// program starts at the first address it fills.
+pub fn import(
+    text: &str,
+    format: ImageFormat,
+    range: Option<ImageRange>,
+) -> Result<Program, String> {
+    let words = match format {
+        ImageFormat::ReadMemH => import_readmem(text, 16)?,
+        ImageFormat::ReadMemB => import_readmem(text, 2)?,
+        ImageFormat::IntelHex => import_ihex(text)?,
+        ImageFormat::Logisim => import_logisim(text)?,
+    };
+
+    let mut segments: Vec<Segment> = vec![];
+    let in_range = |address: &u16| range.is_none_or(|r| (r.start..=r.end).contains(address));
+    for (address, word) in words.into_iter().filter(|(address, _)| in_range(address)) {
+        match segments.last_mut() {
+            Some(segment) if segment.end() == address as usize => segment.words.push(word),
+            _ => segments.push(Segment::new(address, vec![word])),
+        }
+    }
+
+    let mut program = Program::new(0, vec![]);
+    program.entry = segments.first().map_or(0, |segment| segment.origin);
+    program.segments = segments;
+    return Ok(program);
+}
+
+/// The words of a `$readmemh` or `$readmemb` file with their addresses.
+fn import_readmem(text: &str, radix: u32) -> Result<Vec<(u16, u16)>, String> {
+    let mut words = vec![];
+    let mut address: usize = 0;
+    for (number, line) in text.lines().enumerate() {
+        let line = line.split("//").next().unwrap_or("");
+        for item in line.split_whitespace() {
+            let (digits, radix) = match item.strip_prefix('@') {
+                Some(hex) => (hex, 16),
+                None => (item, radix),
+            };
+            let Ok(value) = usize::from_str_radix(&digits.replace('_', ""), radix) else {
+                return Err(format!(
+                    "Line {} has `{item}`, which is not a word",
+                    number + 1
+                ));
+            };
+
+            if item.starts_with('@') {
+                address = value;
+                continue;
+            }
+            if value > u16::MAX as usize || address > u16::MAX as usize {
+                return Err(format!("Line {} does not fit in LC-3 memory", number + 1));
+            }
+            words.push((address as u16, value as u16));
+            address += 1;
+        }
+    }
+    return Ok(words);
+}
+
+/// The words of an Intel HEX file with their addresses.
+fn import_ihex(text: &str) -> Result<Vec<(u16, u16)>, String> {
+    let mut words = vec![];
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let error = |msg: &str| format!("Line {} of the Intel HEX file {msg}", number + 1);
+
+        let Some(hex) = line.strip_prefix(':') else {
+            return Err(error("does not start with `:`"));
+        };
+        let bytes: Option<Vec<u8>> = (0..hex.len())
+            .step_by(2)
+            .map(|i| {
+                hex.get(i..i + 2)
+                    .and_then(|b| u8::from_str_radix(b, 16).ok())
+            })
+            .collect();
+        let Some(bytes) = bytes.filter(|bytes| bytes.len() >= 5) else {
+            return Err(error("is not a record"));
+        };
+        if bytes.len() != bytes[0] as usize + 5 {
+            return Err(error("is not as long as its byte count says"));
+        }
+        if bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)) != 0 {
+            return Err(error("has the wrong checksum"));
+        }
+
+        let address = u16::from_be_bytes([bytes[1], bytes[2]]);
+        let data = &bytes[4..bytes.len() - 1];
+        match bytes[3] {
+            0x00 => {
+                if data.len() % 2 != 0 {
+                    return Err(error("holds half a word"));
+                }
+                for (i, pair) in data.chunks_exact(2).enumerate() {
+                    let word = u16::from_be_bytes([pair[0], pair[1]]);
+                    words.push((address.wrapping_add(i as u16), word));
+                }
+            }
+            0x01 => break,
+            // start addresses don't matter to memory
+            0x03 | 0x05 => {}
+            _ => {
+                return Err(error(
+                    "uses an extended address, which LC-3 memory doesn't need",
+                ));
+            }
+        }
+    }
+    return Ok(words);
+}
+
+/// The words of a Logisim "v2.0 raw" file with their addresses.
+fn import_logisim(text: &str) -> Result<Vec<(u16, u16)>, String> {
+    let mut lines = text.lines().enumerate();
+    if lines.next().map(|(_, line)| line.trim()) != Some(LOGISIM_HEADER) {
+        return Err(format!(
+            "A Logisim image must start with `{LOGISIM_HEADER}`"
+        ));
+    }
+
+    let mut words = vec![];
+    for (number, line) in lines {
+        let line = line.split('#').next().unwrap_or("");
+        for item in line.split_whitespace() {
+            let (count, value) = match item.split_once('*') {
+                Some((count, value)) => (count.parse::<usize>().ok(), value),
+                None => (Some(1), item),
+            };
+            let (Some(count), Ok(value)) = (count, u16::from_str_radix(value, 16)) else {
+                return Err(format!(
+                    "Line {} has `{item}`, which is not a word",
+                    number + 1
+                ));
+            };
+            if words.len() + count > 0x10000 {
+                return Err(format!("Line {} does not fit in LC-3 memory", number + 1));
+            }
+            for _ in 0..count {
+                words.push((words.len() as u16, value));
+            }
+        }
+    }
+    return Ok(words);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn program() -> Program {
+        let mut program = Program::new(0x3000, vec![0xE002, 0xF022, 0xF025]);
+        program.segments.push(Segment::new(0x3005, vec![0x0048]));
+        return program;
+    }
+
+    #[test]
+    fn test_export() {
+        let range = ImageRange::of(&program());
+        assert_eq!(
+            range,
+            ImageRange {
+                start: 0x3000,
+                end: 0x3005
+            }
+        );
+
+        let readmemh = export(&program(), ImageFormat::ReadMemH, range, 0xFFFF);
+        assert_eq!(readmemh, "@3000\nE002\nF022\nF025\nFFFF\nFFFF\n0048\n");
+
+        let range = ImageRange::parse("x3000-x3001").unwrap();
+        let readmemb = export(&program(), ImageFormat::ReadMemB, range, 0);
+        assert_eq!(readmemb, "@3000\n1110000000000010\n1111000000100010\n");
+
+        let ihex = export(&program(), ImageFormat::IntelHex, range, 0);
+        assert_eq!(ihex, ":04300000E002F022D8\n:00000001FF\n");
+
+        let range = ImageRange::parse("x0002-x3001").unwrap();
+        let logisim = export(&program(), ImageFormat::Logisim, range, 0);
+        assert_eq!(logisim, "v2.0 raw\n12288*0 e002 f022\n");
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let range = ImageRange::of(&program());
+        for format in [
+            ImageFormat::ReadMemH,
+            ImageFormat::ReadMemB,
+            ImageFormat::IntelHex,
+            ImageFormat::Logisim,
+        ] {
+            let image = export(&program(), format, range, 0);
+            assert_eq!(ImageFormat::detect(&image), format);
+
+            let imported = import(&image, format, None).unwrap();
+            for address in 0x3000..=0x3005 {
+                let expected = program().word_at(address).unwrap_or(0);
+                assert_eq!(imported.word_at(address), Some(expected));
+            }
+        }
+    }
+
+    #[test]
+    fn test_import_errors() {
+        assert!(import(":04300000E002F022C7\n", ImageFormat::IntelHex, None).is_err());
+        assert!(import("v2.0 raw\n3*zz\n", ImageFormat::Logisim, None).is_err());
+        assert!(import("0000\n", ImageFormat::Logisim, None).is_err());
+        assert!(import("@3000\n1G00\n", ImageFormat::ReadMemH, None).is_err());
+
+        let sparse = import(
+            "@3000 0001 0002 // code\n@4000 0003\n",
+            ImageFormat::ReadMemH,
+            None,
+        )
+        .unwrap();
+        assert_eq!(
+            sparse.segments,
+            vec![
+                Segment::new(0x3000, vec![1, 2]),
+                Segment::new(0x4000, vec![3])
+            ]
+        );
+        assert_eq!(sparse.entry, 0x3000);
+    }
+
+    #[test]
+    fn test_import_range() {
+        let range = ImageRange::of(&program());
+        let image = export(&program(), ImageFormat::Logisim, range, 0);
+
+        // without a range, the zeros below the program are loaded too
+        let all = import(&image, ImageFormat::Logisim, None).unwrap();
+        assert_eq!(all.segments[0].origin, 0);
+        assert_eq!(all.entry, 0);
+
+        let part = import(&image, ImageFormat::Logisim, Some(range)).unwrap();
+        assert_eq!(
+            part.segments,
+            vec![Segment::new(
+                0x3000,
+                vec![0xE002, 0xF022, 0xF025, 0, 0, 0x0048]
+            )]
+        );
+        assert_eq!(part.entry, 0x3000);
+    }
+}
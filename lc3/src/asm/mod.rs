@@ -2,7 +2,10 @@ pub mod asm;
 pub mod asm_error;
 pub mod asm_ins;
 pub mod directive;
+pub mod image;
 pub mod lexer;
+pub mod object;
 pub mod semantic;
+pub mod source_map;
 pub mod syntax;
 pub mod token;
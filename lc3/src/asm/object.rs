@@ -0,0 +1,230 @@
+// The file formats of the textbook's `lc3tools` (`lc3as` and `lc3sim`), so
+// programs can move between this emulator and the official tools.
+//
+// - `.obj`: the origin, then every word, all as big-endian 16-bit numbers.
+//   It holds a single section, so a program with several gets one per section.
+// - `.sym`: the symbol table as `//`-commented text.
+// - `.lst`: each source line next to the addresses and words it assembled to.
+
+use super::source_map::SourceMap;
+use crate::program::{Program, Segment};
+use std::collections::HashMap;
+
+/// The header `lc3as` writes at the top of a `.sym` file.
+const SYM_HEADER: &str = "// Symbol table
+// Scope level 0:
+//\tSymbol Name       Page Address
+//\t----------------  ------------
+";
+
+/// How wide the address and word columns of a `.lst` line are, so lines without
+/// words still line up.
+const LST_WORD_WIDTH: usize = 30;
+
+/// A section as a `.obj` file.
+pub fn write_obj(segment: &Segment) -> Vec<u8> {
+    return std::iter::once(&segment.origin)
+        .chain(&segment.words)
+        .flat_map(|word| word.to_be_bytes())
+        .collect();
+}
+
+/// Reads a `.obj` file into a program that starts running at its origin.
+pub fn read_obj(bytes: &[u8]) -> Result<Program, String> {
+    if bytes.len() < 2 {
+        return Err("The object file is empty, so it has no origin".to_string());
+    }
+    if bytes.len() % 2 != 0 {
+        return Err(format!(
+            "The object file is {} bytes long, which is not a whole number of 16-bit words",
+            bytes.len()
+        ));
+    }
+
+    let words: Vec<u16> = bytes
+        .chunks_exact(2)
+        .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+        .collect();
+    if words[0] as usize + words.len() - 1 > 0x10000 {
+        return Err(format!(
+            "The object file's {} words do not fit in memory after its origin x{:04X}",
+            words.len() - 1,
+            words[0]
+        ));
+    }
+
+    return Ok(Program::new(words[0], words[1..].to_vec()));
+}
+
+/// The symbol table as a `.sym` file, sorted by address like `lc3as` does.
+pub fn write_sym(symbols: &HashMap<String, u16>) -> String {
+    let mut sorted: Vec<(&String, &u16)> = symbols.iter().collect();
+    sorted.sort_by_key(|(name, address)| (**address, name.as_str()));
+
+    let mut out = SYM_HEADER.to_string();
+    for (name, address) in sorted {
+        out += &format!("//\t{name:<16}  {address:04X}\n");
+    }
+    out += "\n";
+    return out;
+}
+
+/// Reads the symbols out of a `.sym` file.
+pub fn read_sym(text: &str) -> Result<HashMap<String, u16>, String> {
+    let mut symbols = HashMap::new();
+    // everything up to the dashes under the column names is header
+    let mut in_table = false;
+    for (number, line) in text.lines().enumerate() {
+        let Some(line) = line.trim().strip_prefix("//") else {
+            if line.trim().is_empty() {
+                continue;
+            }
+            return Err(format!(
+                "Line {} of the symbol file is not a comment",
+                number + 1
+            ));
+        };
+
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if !in_table {
+            in_table = fields.first().is_some_and(|field| field.starts_with("---"));
+            continue;
+        }
+
+        match fields[..] {
+            [] => {}
+            [name, address] => match u16::from_str_radix(address, 16) {
+                Ok(address) => {
+                    symbols.insert(name.to_string(), address);
+                }
+                Err(_) => {
+                    return Err(format!(
+                        "Line {} of the symbol file gives `{name}` the address `{address}`, which is not a hexadecimal word",
+                        number + 1
+                    ));
+                }
+            },
+            _ => {
+                return Err(format!(
+                    "Line {} of the symbol file is not a symbol name followed by its address",
+                    number + 1
+                ));
+            }
+        }
+    }
+
+    return Ok(symbols);
+}
+
+/// A listing of `source`, which `program` was assembled from: every line, with
+/// the address, hex and binary of the words it assembled to in front of it. A
+/// line that fills several words, like `.STRINGZ`, gets a row for each.
+pub fn write_lst(program: &Program, source: &str) -> String {
+    let empty = SourceMap::new();
+    let source_map = program.debug_info.as_ref().unwrap_or(&empty);
+
+    let mut by_line: HashMap<usize, Vec<u16>> = HashMap::new();
+    for (address, location) in source_map.iter() {
+        by_line.entry(location.line).or_default().push(address);
+    }
+
+    let mut out = String::new();
+    for (index, text) in source.lines().enumerate() {
+        let line = index + 1;
+        let addresses = by_line.get(&line).map(Vec::as_slice).unwrap_or(&[]);
+
+        let words: Vec<String> = addresses
+            .iter()
+            .map(|address| {
+                let word = program.word_at(*address).unwrap_or(0);
+                format!("({address:04X}) {word:04X}  {word:016b}")
+            })
+            .collect();
+
+        match words.split_first() {
+            Some((first, rest)) => {
+                out += &format!("{first} ({line:>4}) {text}").trim_end();
+                out += "\n";
+                for word in rest {
+                    out += &format!("{word}\n");
+                }
+            }
+            None => {
+                out += &format!("{:LST_WORD_WIDTH$} ({line:>4}) {text}", "").trim_end();
+                out += "\n";
+            }
+        }
+    }
+    return out;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::asm::Asm;
+    use crate::io::{DebugIO, Lc3IO};
+
+    const SOURCE: &str = ".ORIG x3000
+        LEA R0, HI
+        PUTS
+        HALT
+HI      .STRINGZ \"Hi\"
+.END";
+
+    fn assemble() -> Program {
+        let mut asm = Asm::new(Lc3IO::new(Box::new(DebugIO::new())));
+        return asm.run(SOURCE.to_string()).unwrap();
+    }
+
+    #[test]
+    fn test_obj_round_trip() {
+        let program = assemble();
+        let bytes = write_obj(&program.segments[0]);
+
+        assert_eq!(bytes[..4], [0x30, 0x00, 0xE0, 0x02]);
+        assert_eq!(read_obj(&bytes).unwrap().segments, program.segments);
+        assert!(read_obj(&bytes[..3]).is_err());
+        assert!(read_obj(&[]).is_err());
+    }
+
+    #[test]
+    fn test_sym() {
+        let program = assemble();
+        let sym = write_sym(&program.symbols);
+
+        assert_eq!(
+            sym,
+            "// Symbol table
+// Scope level 0:
+//\tSymbol Name       Page Address
+//\t----------------  ------------
+//\tHI                3003
+
+"
+        );
+        assert_eq!(read_sym(&sym).unwrap(), program.symbols);
+        assert!(read_sym("HI 3003").is_err());
+    }
+
+    #[test]
+    fn test_lst() {
+        let lst = write_lst(&assemble(), SOURCE);
+        let lines: Vec<&str> = lst.lines().collect();
+
+        assert_eq!(
+            lines[0],
+            "                               (   1) .ORIG x3000"
+        );
+        assert_eq!(
+            lines[1],
+            "(3000) E002  1110000000000010 (   2)         LEA R0, HI"
+        );
+        assert_eq!(
+            lines[4],
+            "(3003) 0048  0000000001001000 (   5) HI      .STRINGZ \"Hi\""
+        );
+        assert_eq!(lines[5], "(3004) 0069  0000000001101001");
+        assert_eq!(lines[6], "(3005) 0000  0000000000000000");
+        assert_eq!(lines[7], "                               (   6) .END");
+    }
+}
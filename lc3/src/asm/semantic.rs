@@ -7,6 +7,8 @@ use super::{
 use std::collections::{HashMap, VecDeque};
 
 const ARCH_LIMIT: i32 = 16;
+/// Addresses are 16 bits, so memory ends at xFFFF.
+const ARCH_MEMORY_SIZE: i32 = 1 << ARCH_LIMIT;
 
 const CODE_INS_NO_OPERAND: &'static str = "SM000";
 const CODE_RECEIVED_UNEXPECTED_INS: &'static str = "SM001";
@@ -27,6 +29,11 @@ const CODE_NUMBER_OUT_OF_BOUNDS: &'static str = "SM015";
 const CODE_ORIG_NOT_GIVEN_NUMBER: &'static str = "SM016";
 const CODE_FILE_NOT_VALID: &'static str = "SM017";
 const CODE_FILE_EMPTY: &'static str = "SM018";
+const CODE_LABEL_OUT_OF_RANGE: &'static str = "SM019";
+const CODE_ORIG_INSIDE_SECTION: &'static str = "SM020";
+const CODE_OUTSIDE_SECTION: &'static str = "SM021";
+const CODE_OVERLAPPING_SECTIONS: &'static str = "SM022";
+const CODE_SECTION_PAST_END_OF_MEMORY: &'static str = "SM023";
 
 #[allow(dead_code)]
 pub struct SemanticChecker {
@@ -39,6 +46,15 @@ pub struct SemanticChecker {
     expected_operands: VecDeque<OperandType>,
     curr_ins_token: Token,
     end_encountered: bool,
+    /// Labels used as PC-relative operands, with the PC the offset is relative
+    /// to and how many bits the offset has, checked once every label is known.
+    label_references: Vec<(Token, i32, i32)>,
+    in_orig_directive: bool,
+    /// The `.ORIG` token and start address of the section being checked, if
+    /// its `.END` hasn't been reached yet.
+    open_section: Option<(Token, i32)>,
+    /// Every finished section's `.ORIG` token, start and end (exclusive).
+    sections: Vec<(Token, i32, i32)>,
 }
 
 #[allow(dead_code)]
@@ -54,6 +70,10 @@ impl SemanticChecker {
             expected_operands: VecDeque::new(),
             curr_ins_token: Token::get_useless_token(),
             end_encountered: false,
+            label_references: vec![],
+            in_orig_directive: false,
+            open_section: None,
+            sections: vec![],
         }
     }
 
@@ -98,8 +118,11 @@ impl SemanticChecker {
         }
 
         self.verify_all_used_labels_defined();
+        self.verify_label_offsets_in_range();
+        self.verify_sections_fit_in_memory();
+        self.verify_sections_do_not_overlap();
 
-        if !self.end_encountered {
+        if !self.end_encountered || self.open_section.is_some() {
             self.errors.push(AsmError::new(
                 String::from(CODE_NO_END),
                 "",
@@ -133,6 +156,7 @@ impl SemanticChecker {
                 ),
             ));
         }
+        self.verify_inside_section(token);
         self.curr_ins_token = token.clone(); // These should be optimized out. In errors they are acceptable, but we should not take a performance hit to valid code.
         self.memory_location += 1;
 
@@ -153,6 +177,11 @@ impl SemanticChecker {
                 ),
             ));
         }
+        match directive {
+            Directive::ORIG => self.open_section(token),
+            Directive::END => self.close_section(),
+            _ => self.verify_inside_section(token),
+        }
         if self.is_end(directive) {
             self.end_encountered = true;
         }
@@ -183,10 +212,17 @@ impl SemanticChecker {
         let expected = self.expected_operands.pop_front().unwrap();
 
         match expected {
-            OperandType::Label => {
-                /* ... */
+            OperandType::Label | OperandType::ImmOrLabel => {
                 self.used_labels
                     .insert(token.original_match.clone(), token.clone());
+
+                // `.FILL` holds the label's address, but instructions hold an offset to it
+                if let TokenType::Instruction(instruction) = &self.curr_ins_token.inner_token {
+                    if let Some(width) = instruction.get_immediate_value_width() {
+                        self.label_references
+                            .push((token.clone(), self.memory_location, width));
+                    }
+                }
             }
             _ => {
                 self.errors.push(AsmError::from(
@@ -218,13 +254,20 @@ impl SemanticChecker {
         let expected: OperandType = self.expected_operands.pop_front().unwrap();
 
         match expected {
-            OperandType::Imm | OperandType::RegOrImm => {
+            OperandType::Imm | OperandType::RegOrImm | OperandType::ImmOrLabel => {
                 self.verify_immediate_value_in_range(token);
 
                 if self.in_blkw_directive {
                     self.in_blkw_directive = false;
                     self.memory_location += *number as i32;
                 }
+                if self.in_orig_directive {
+                    self.in_orig_directive = false;
+                    self.memory_location = *number as u16 as i32;
+                    if let Some((_, start)) = &mut self.open_section {
+                        *start = self.memory_location;
+                    }
+                }
             }
             _ => {
                 self.errors.push(AsmError::from(
@@ -357,7 +400,7 @@ impl SemanticChecker {
     pub fn set_memory_orig(&mut self, tokens: &Vec<Token>) {
         match tokens[1].inner_token {
             TokenType::Number(location) => {
-                self.memory_location = location as i32;
+                self.memory_location = location as u16 as i32;
             }
             _ => {
                 AsmError::from(
@@ -381,6 +424,42 @@ impl SemanticChecker {
         }
     }
 
+    /// Starts a section at a `.ORIG`, whose address comes with the next token.
+    fn open_section(&mut self, token: &Token) {
+        if self.open_section.is_some() {
+            self.errors.push(AsmError::from(
+                String::from(CODE_ORIG_INSIDE_SECTION),
+                &self.original_file[token.line_num],
+                token.clone(),
+                ErrorType::LogicalError,
+                "a `.ORIG` directive cannot start a new section before the previous section is closed. To resolve this error, add `.END` on the line before it.",
+            ));
+        }
+        self.in_orig_directive = true;
+        self.open_section = Some((token.clone(), self.memory_location));
+    }
+
+    fn close_section(&mut self) {
+        if let Some((token, start)) = self.open_section.take() {
+            self.sections.push((token, start, self.memory_location));
+        }
+    }
+
+    /// Statements after a `.END` must come after a new `.ORIG`.
+    fn verify_inside_section(&mut self, token: &Token) {
+        if self.end_encountered && self.open_section.is_none() {
+            self.errors.push(AsmError::from(
+                String::from(CODE_OUTSIDE_SECTION),
+                &self.original_file[token.line_num],
+                token.clone(),
+                ErrorType::LogicalError,
+                "this is after a `.END` directive, so it does not belong to any section. To resolve this error, start a new section with `.ORIG` above it.",
+            ));
+            // one error per stray section is enough
+            self.open_section = Some((token.clone(), self.memory_location));
+        }
+    }
+
     pub fn move_memory_location_directive(&mut self, directive: &Directive) {
         match directive {
             Directive::FILL => {
@@ -424,8 +503,7 @@ impl SemanticChecker {
             if !self.symbol_table.contains_key(label) {
                 self.errors.push(AsmError::from(
                     String::from(CODE_USED_UNDEFINED_LABEL),
-                    &self
-                        .original_file[self.used_labels.get(label).unwrap().line_num],
+                    &self.original_file[self.used_labels.get(label).unwrap().line_num],
                     self.used_labels.get(label).unwrap().clone(),
                     ErrorType::LabelError,
                     &format!("the label `{}` was never defined within the file.", label),
@@ -434,6 +512,77 @@ impl SemanticChecker {
         }
     }
 
+    /// Labels in other sections can be too far away for an instruction's offset.
+    fn verify_label_offsets_in_range(&mut self) {
+        for (token, pc, width) in &self.label_references {
+            let Some((address, _)) = self.symbol_table.get(&token.original_match) else {
+                continue;
+            };
+            let offset = address - pc;
+            let (lower, upper) = self.get_twos_complement_range(*width);
+            if offset < lower || offset > upper {
+                self.errors.push(AsmError::from(
+                    String::from(CODE_LABEL_OUT_OF_RANGE),
+                    &self.original_file[token.line_num],
+                    token.clone(),
+                    ErrorType::BoundError,
+                    &format!(
+                        "the label `{}` is {} words away, but this instruction takes a {}-bit offset, so the label must be within `[{}, {}]` words. To reach a label in another section, put its address in a `.FILL` and go through it with `LDI`, `STI` or `JSRR`.",
+                        token.original_match, offset, width, lower, upper,
+                    ),
+                ));
+            }
+        }
+    }
+
+    /// A section starting near the top of memory can run past xFFFF.
+    fn verify_sections_fit_in_memory(&mut self) {
+        for (token, start, end) in &self.sections {
+            if *end > ARCH_MEMORY_SIZE {
+                self.errors.push(AsmError::from(
+                    String::from(CODE_SECTION_PAST_END_OF_MEMORY),
+                    &self.original_file[token.line_num],
+                    token.clone(),
+                    ErrorType::LogicalError,
+                    &format!(
+                        "this section starts at x{:04X} and fills {} words, which runs past the end of memory at xFFFF.",
+                        start,
+                        end - start
+                    ),
+                ));
+            }
+        }
+    }
+
+    fn verify_sections_do_not_overlap(&mut self) {
+        let mut sections: Vec<&(Token, i32, i32)> = self.sections.iter().collect();
+        sections.sort_by_key(|(_, start, _)| *start);
+
+        // the section reaching furthest so far, which any later start must be past
+        let mut furthest: Option<(i32, i32)> = None;
+        for (token, start, end) in sections {
+            if let Some((other_start, other_end)) = furthest {
+                if *start < other_end && start < end {
+                    self.errors.push(AsmError::from(
+                        String::from(CODE_OVERLAPPING_SECTIONS),
+                        &self.original_file[token.line_num],
+                        token.clone(),
+                        ErrorType::LogicalError,
+                        &format!(
+                            "this section starts at x{:04X}, inside the section that fills x{:04X}-x{:04X}.",
+                            start,
+                            other_start,
+                            other_end - 1
+                        ),
+                    ));
+                }
+            }
+            if start < end && furthest.is_none_or(|(_, other_end)| *end > other_end) {
+                furthest = Some((*start, *end));
+            }
+        }
+    }
+
     fn verify_immediate_value_in_range(&mut self, value: &Token) {
         let width: i32;
 
@@ -584,6 +733,102 @@ name ret
         assert_eq!(errors[0].code, CODE_USED_UNDEFINED_LABEL);
     }
 
+    #[test]
+    fn test_fill_with_label() {
+        let file = r#"
+        .ORIG x0000
+        .FILL routine
+routine RET
+        .FILL missing
+        .END
+        "#;
+
+        let errors: Vec<AsmError> = get_semantic_errors(file);
+
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].code, CODE_USED_UNDEFINED_LABEL);
+    }
+
+    #[test]
+    fn test_multiple_sections() {
+        let file = r#"
+        .ORIG x3000
+        LDI R0, table_ptr
+        HALT
+table_ptr .FILL table
+        .END
+
+        .ORIG x4000
+table   .FILL x1234
+        .END
+        "#;
+
+        assert_eq!(get_semantic_errors(file).len(), 0);
+        let (location, _) = get_symbol_table(file).remove("table").unwrap();
+        assert_eq!(location, 0x4000);
+    }
+
+    #[test]
+    fn test_label_in_other_section_out_of_range() {
+        let file = r#"
+        .ORIG x3000
+        LD R0, far
+        .END
+        .ORIG x4000
+far     .FILL #1
+        .END
+        "#;
+
+        let errors: Vec<AsmError> = get_semantic_errors(file);
+
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].code, CODE_LABEL_OUT_OF_RANGE);
+    }
+
+    #[test]
+    fn test_section_errors() {
+        let overlapping = r#"
+        .ORIG x3000
+        .BLKW #16
+        .END
+        .ORIG x300F
+        .FILL #1
+        .END
+        "#;
+        let errors = get_semantic_errors(overlapping);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].code, CODE_OVERLAPPING_SECTIONS);
+
+        let unclosed = r#"
+        .ORIG x3000
+        RET
+        .ORIG x4000
+        RET
+        .END
+        "#;
+        let errors = get_semantic_errors(unclosed);
+        assert_eq!(errors[0].code, CODE_ORIG_INSIDE_SECTION);
+
+        let past_end = r#"
+        .ORIG xFFFF
+        ADD R0, R0, #1
+        ADD R0, R0, #1
+        .END
+        "#;
+        let errors = get_semantic_errors(past_end);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].code, CODE_SECTION_PAST_END_OF_MEMORY);
+
+        let stray = r#"
+        .ORIG x3000
+        RET
+        .END
+        RET
+        "#;
+        let errors = get_semantic_errors(stray);
+        assert_eq!(errors[0].code, CODE_OUTSIDE_SECTION);
+    }
+
     #[test]
     fn test_instruction_on_same_line() {
         let file = r#"
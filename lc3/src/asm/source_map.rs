@@ -0,0 +1,88 @@
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+#[cfg(feature = "serde")]
+use tsify::Tsify;
+
+/// A range of characters, from `start` up to but not including `end`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize, Tsify))]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+/// Where the word at an address came from in the source file.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize, Tsify))]
+pub struct SourceLocation {
+    /// 1-based line number.
+    pub line: usize,
+    /// The statement's columns within its line, 0-based, from the opcode or
+    /// directive to its last operand.
+    pub columns: Span,
+    /// The same statement as offsets into the whole file.
+    pub offsets: Span,
+    /// The label naming this address, if any.
+    pub label: Option<String>,
+    /// Whether the word is an instruction, rather than data from `.FILL`,
+    /// `.BLKW` or `.STRINGZ`.
+    pub instruction: bool,
+}
+
+/// Maps the addresses an assembled file fills to the source lines they came from.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize, Tsify))]
+#[cfg_attr(feature = "serde", tsify(into_wasm_abi, from_wasm_abi))]
+pub struct SourceMap {
+    locations: BTreeMap<u16, SourceLocation>,
+}
+
+impl SourceMap {
+    pub fn new() -> SourceMap {
+        SourceMap {
+            locations: BTreeMap::new(),
+        }
+    }
+
+    pub fn insert(&mut self, address: u16, location: SourceLocation) {
+        self.locations.insert(address, location);
+    }
+
+    pub fn get(&self, address: u16) -> Option<&SourceLocation> {
+        return self.locations.get(&address);
+    }
+
+    /// The address a label names, if it names one in this file.
+    pub fn address_of(&self, label: &str) -> Option<u16> {
+        return self
+            .iter()
+            .find(|(_, location)| location.label.as_deref() == Some(label))
+            .map(|(address, _)| address);
+    }
+
+    pub fn line(&self, address: u16) -> Option<usize> {
+        return self.get(address).map(|location| location.line);
+    }
+
+    /// Every mapped address and its location, in address order.
+    pub fn iter(&self) -> impl Iterator<Item = (u16, &SourceLocation)> {
+        return self
+            .locations
+            .iter()
+            .map(|(address, location)| (*address, location));
+    }
+
+    /// The addresses of the instructions assembled from `line`.
+    pub fn instructions_on(&self, line: usize) -> Vec<u16> {
+        return self
+            .iter()
+            .filter(|(_, location)| location.instruction && location.line == line)
+            .map(|(address, _)| address)
+            .collect();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.locations.is_empty();
+    }
+}
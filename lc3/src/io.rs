@@ -1,3 +1,4 @@
+use crate::vm::delta::IoEvent;
 #[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
 use std::collections::VecDeque;
@@ -10,19 +11,84 @@ use typetag;
 #[cfg_attr(feature = "serde", tsify(into_wasm_abi, from_wasm_abi))]
 pub struct Lc3IO {
     target: Box<dyn IOTarget>,
+    /// Characters read and written between `start_journal` and `stop_journal`.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    journal: Vec<IoEvent>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    journaling: bool,
+    /// Characters handed back with `unread`, which are read again before any new input.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    unread: Vec<char>,
 }
 
 impl Lc3IO {
     pub fn new(target: Box<dyn IOTarget>) -> Lc3IO {
-        Lc3IO { target: target }
+        Lc3IO {
+            target: target,
+            journal: vec![],
+            journaling: false,
+            unread: vec![],
+        }
     }
 
     pub fn get_char(&mut self) -> Option<char> {
-        return self.target.get_char();
+        let c = self.unread.pop().or_else(|| self.target.get_char());
+        self.record(c.map(IoEvent::Input));
+        return c;
+    }
+
+    pub fn poll_char(&mut self) -> Option<char> {
+        let c = self.unread.pop().or_else(|| self.target.poll_char());
+        self.record(c.map(IoEvent::Input));
+        return c;
     }
 
     pub fn put_char(&mut self, c: char) {
         self.target.put_char(c);
+        self.record(Some(IoEvent::Output(c)));
+    }
+
+    /// Hands a character that was already read back, so it is the next one read.
+    /// This is how stepping backward undoes input.
+    pub fn unread(&mut self, c: char) {
+        self.unread.push(c);
+    }
+
+    /// Input that has arrived but not been read yet: characters handed back with
+    /// `unread`, then whatever the IO target has buffered, in the order they
+    /// will be read.
+    pub fn pending_input(&self) -> Vec<char> {
+        let mut input: Vec<char> = self.unread.iter().rev().copied().collect();
+        input.extend(self.target.pending_input());
+        return input;
+    }
+
+    /// Queues `input` to be read before anything the IO target provides,
+    /// replacing any characters handed back with `unread`.
+    pub fn set_pending_input(&mut self, input: &[char]) {
+        self.unread = input.iter().rev().copied().collect();
+    }
+
+    /// Forgets the last journal, and records every character read or written
+    /// until `stop_journal`.
+    pub fn start_journal(&mut self) {
+        self.journal.clear();
+        self.journaling = true;
+    }
+
+    pub fn stop_journal(&mut self) {
+        self.journaling = false;
+    }
+
+    /// The IO recorded by the last `start_journal`, in order.
+    pub fn journal(&self) -> &[IoEvent] {
+        return &self.journal;
+    }
+
+    fn record(&mut self, event: Option<IoEvent>) {
+        if let (true, Some(event)) = (self.journaling, event) {
+            self.journal.push(event);
+        }
     }
 
     pub fn print_error(&mut self, error: String) {
@@ -36,6 +102,17 @@ impl Lc3IO {
 pub trait IOTarget {
     fn get_char(&mut self) -> Option<char>;
     fn put_char(&mut self, c: char);
+
+    /// Like `get_char`, but must never block waiting for input. This is what the
+    /// keyboard device uses to poll for a new character.
+    fn poll_char(&mut self) -> Option<char> {
+        return self.get_char();
+    }
+
+    /// Input the target has buffered that has not been read yet, if it knows.
+    fn pending_input(&self) -> Vec<char> {
+        return vec![];
+    }
 }
 
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize, Tsify))]
@@ -52,6 +129,13 @@ impl DebugIO {
             output_stream: VecDeque::new(),
         }
     }
+
+    pub fn with_input(input: &str) -> DebugIO {
+        DebugIO {
+            input_stream: input.chars().collect(),
+            output_stream: VecDeque::new(),
+        }
+    }
 }
 
 #[cfg_attr(feature = "serde", typetag::serde)]
@@ -62,4 +146,7 @@ impl IOTarget for DebugIO {
     fn put_char(&mut self, c: char) {
         self.output_stream.push_back(c);
     }
+    fn pending_input(&self) -> Vec<char> {
+        return self.input_stream.iter().copied().collect();
+    }
 }
@@ -1,3 +1,4 @@
 pub mod asm;
 pub mod io;
+pub mod program;
 pub mod vm;
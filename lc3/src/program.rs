@@ -0,0 +1,113 @@
+use crate::asm::source_map::SourceMap;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+#[cfg(feature = "serde")]
+use tsify::Tsify;
+#[cfg(feature = "serde")]
+use wasm_bindgen::prelude::*;
+
+/// A run of words to be loaded at consecutive addresses starting at `origin`.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize, Tsify))]
+pub struct Segment {
+    pub origin: u16,
+    pub words: Vec<u16>,
+}
+
+impl Segment {
+    pub fn new(origin: u16, words: Vec<u16>) -> Segment {
+        Segment {
+            origin: origin,
+            words: words,
+        }
+    }
+
+    /// The address one past the last word, which may be past xFFFF.
+    pub fn end(&self) -> usize {
+        return self.origin as usize + self.words.len();
+    }
+
+    pub fn contains(&self, address: u16) -> bool {
+        return address >= self.origin && (address as usize) < self.end();
+    }
+
+    /// Fails if the segment runs past xFFFF, so it cannot be loaded.
+    pub fn verify_fits_in_memory(&self) -> Result<(), String> {
+        if self.end() > 1 << 16 {
+            return Err(format!(
+                "The segment at x{:04X} is {} words long, which runs past the end of memory",
+                self.origin,
+                self.words.len()
+            ));
+        }
+        return Ok(());
+    }
+}
+
+/// An assembled program, ready to be loaded into memory: its segments, where it
+/// starts running, and what the assembler knew about it.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize, Tsify))]
+#[cfg_attr(feature = "serde", tsify(into_wasm_abi, from_wasm_abi))]
+pub struct Program {
+    pub segments: Vec<Segment>,
+    /// The address the PC starts at.
+    pub entry: u16,
+    /// Every label, with its address.
+    pub symbols: HashMap<String, u16>,
+    /// Where each address came from in the source, for programs that were
+    /// assembled rather than loaded from a binary.
+    pub debug_info: Option<SourceMap>,
+}
+
+impl Program {
+    /// A program of a single segment that starts running at its first word.
+    pub fn new(origin: u16, words: Vec<u16>) -> Program {
+        Program {
+            segments: vec![Segment::new(origin, words)],
+            entry: origin,
+            symbols: HashMap::new(),
+            debug_info: None,
+        }
+    }
+
+    /// The word the program puts at `address`, if any of its segments covers it.
+    pub fn word_at(&self, address: u16) -> Option<u16> {
+        return self
+            .segments
+            .iter()
+            .find(|segment| segment.contains(address))
+            .map(|segment| segment.words[(address - segment.origin) as usize]);
+    }
+
+    /// How many words the program fills.
+    pub fn len(&self) -> usize {
+        return self
+            .segments
+            .iter()
+            .map(|segment| segment.words.len())
+            .sum();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.len() == 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_word_at() {
+        let mut program = Program::new(0x3000, vec![1, 2, 3]);
+        program.segments.push(Segment::new(0x4000, vec![4]));
+
+        assert_eq!(program.entry, 0x3000);
+        assert_eq!(program.word_at(0x3002), Some(3));
+        assert_eq!(program.word_at(0x3003), None);
+        assert_eq!(program.word_at(0x4000), Some(4));
+        assert_eq!(program.len(), 4);
+    }
+}
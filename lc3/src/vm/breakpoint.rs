@@ -0,0 +1,166 @@
+use super::expr::Expr;
+use super::limits::Limit;
+use super::memory::Memory;
+use super::registers::Registers;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "serde")]
+use tsify::Tsify;
+
+/// Whether a watched location was read or written.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize, Tsify))]
+pub enum Access {
+    Read,
+    Write,
+}
+
+/// A breakpoint on an address. Each time the PC reaches it while `condition`
+/// holds (or always, without a condition), it counts a hit, and the machine
+/// stops from the `stop_at_hit`th hit on.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize, Tsify))]
+pub struct Breakpoint {
+    pub condition: Option<Expr>,
+    /// 0 and 1 both stop at every hit.
+    pub stop_at_hit: u64,
+    pub hits: u64,
+}
+
+impl Breakpoint {
+    pub fn new() -> Breakpoint {
+        Breakpoint {
+            condition: None,
+            stop_at_hit: 0,
+            hits: 0,
+        }
+    }
+
+    pub fn with_condition(condition: Option<Expr>, stop_at_hit: u64) -> Breakpoint {
+        Breakpoint {
+            condition: condition,
+            stop_at_hit: stop_at_hit,
+            hits: 0,
+        }
+    }
+
+    /// Called when the PC reaches the breakpoint. Returns whether to stop.
+    pub fn hit(&mut self, reg: &Registers, mem: &Memory) -> bool {
+        if let Some(condition) = &self.condition {
+            if !condition.is_true(reg, mem) {
+                return false;
+            }
+        }
+        self.hits += 1;
+        return self.hits >= self.stop_at_hit;
+    }
+}
+
+/// Watches the addresses `start..=end`, which may include the device registers.
+/// Only the memory accesses an instruction makes count, so fetching an
+/// instruction is not a read. With a `condition`, the machine only stops if it
+/// holds once the instruction has finished.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize, Tsify))]
+#[cfg_attr(feature = "serde", tsify(into_wasm_abi, from_wasm_abi))]
+pub struct Watchpoint {
+    pub start: u16,
+    pub end: u16,
+    pub on_read: bool,
+    pub on_write: bool,
+    pub condition: Option<Expr>,
+}
+
+impl Watchpoint {
+    pub fn new(start: u16, end: u16, on_read: bool, on_write: bool) -> Watchpoint {
+        Watchpoint {
+            start: start.min(end),
+            end: start.max(end),
+            on_read: on_read,
+            on_write: on_write,
+            condition: None,
+        }
+    }
+
+    pub fn matches(&self, address: u16, access: Access) -> bool {
+        let watched = match access {
+            Access::Read => self.on_read,
+            Access::Write => self.on_write,
+        };
+        return watched && (self.start..=self.end).contains(&address);
+    }
+}
+
+/// An access that triggered a watchpoint. `value` is the value that was read,
+/// or the value that was written.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize, Tsify))]
+pub struct WatchHit {
+    pub address: u16,
+    pub access: Access,
+    pub value: u16,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize, Tsify))]
+pub enum Exception {
+    PrivilegeMode,
+    IllegalOpcode,
+    AccessControlViolation,
+}
+
+/// Why `VM::run_until_event` stopped.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize, Tsify))]
+#[cfg_attr(feature = "serde", tsify(into_wasm_abi, from_wasm_abi))]
+pub enum StopReason {
+    /// The PC reached a breakpoint. The instruction there has not run yet.
+    Breakpoint(u16),
+    /// The last instruction accessed a watched location.
+    Watchpoint(WatchHit),
+    Halted,
+    /// The program is waiting for a key that hasn't been typed yet.
+    AwaitingInput,
+    /// The last instruction raised an exception. If the OS handles it, the PC
+    /// is already at its service routine.
+    Exception(Exception),
+    /// The step limit was reached first.
+    StepLimit,
+    /// The program used up one of the VM's `ResourceLimits`.
+    LimitExceeded(Limit),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_watchpoint_matches() {
+        let wp = Watchpoint::new(0x3010, 0x3000, false, true);
+
+        assert_eq!(wp.start, 0x3000);
+        assert!(wp.matches(0x3000, Access::Write));
+        assert!(wp.matches(0x3010, Access::Write));
+        assert!(!wp.matches(0x3011, Access::Write));
+        assert!(!wp.matches(0x3005, Access::Read));
+    }
+
+    #[test]
+    fn test_breakpoint_hit_count() {
+        let mut reg = Registers::new();
+        let mem = Memory::new();
+        let condition = Expr::parse("R0 > #1", &Default::default()).unwrap();
+        let mut bp = Breakpoint::with_condition(Some(condition), 2);
+
+        let stops: Vec<bool> = (0..5)
+            .map(|i| {
+                reg.r[0] = i;
+                bp.hit(&reg, &mem)
+            })
+            .collect();
+
+        // R0 is 2, 3 and 4 on the last three hits, and it stops from the second of those
+        assert_eq!(stops, vec![false, false, false, true, true]);
+        assert_eq!(bp.hits, 3);
+    }
+}
@@ -0,0 +1,301 @@
+use super::decode::Op;
+use std::collections::{BTreeMap, HashMap};
+
+/// A subroutine, trap or interrupt handler reached by a particular chain of
+/// calls. A recursive subroutine gets a node for every depth it reaches.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CallNode {
+    pub name: String,
+    pub parent: Option<usize>,
+    pub children: Vec<usize>,
+    /// How many times this chain of calls was entered.
+    pub calls: u64,
+    /// Instructions run in this frame itself, not counting the frames it called.
+    pub exclusive: u64,
+}
+
+/// One subroutine's counts, over every chain of calls it appears in.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct CallTotals {
+    pub calls: u64,
+    /// Instructions run inside the subroutine, counting the ones in recursive
+    /// calls once.
+    pub inclusive: u64,
+    /// Instructions run in the subroutine alone.
+    pub exclusive: u64,
+}
+
+/// A dynamic call tree, built while `VM::call_graph` is set. JSR, JSRR, TRAP,
+/// interrupts and exceptions enter a frame, and RET (`JMP R7`) and RTI leave
+/// it. Frames are named after the label at the address they start at, or the
+/// trap's name, or the address itself.
+///
+/// Programs that return some other way, or that leave a subroutine with RET
+/// after changing R7, still get a tree, but it follows what the instructions
+/// did rather than what the programmer meant.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CallGraph {
+    /// The root is always node 0, and is named when the first instruction runs.
+    pub nodes: Vec<CallNode>,
+    current: usize,
+    labels: HashMap<u16, String>,
+    /// Where the next instruction should come from. Anything else means an
+    /// interrupt was serviced in between.
+    expected_pc: Option<u16>,
+}
+
+impl CallGraph {
+    /// `symbols` are the program's labels, used to name the frames.
+    pub fn new(symbols: &HashMap<String, u16>) -> CallGraph {
+        let mut labels: HashMap<u16, String> = HashMap::new();
+        for (name, address) in symbols {
+            let label = labels.entry(*address).or_insert_with(|| name.clone());
+            // several labels can share an address, so pick one the same way every time
+            if name < label {
+                *label = name.clone();
+            }
+        }
+
+        CallGraph {
+            nodes: vec![root()],
+            current: 0,
+            labels: labels,
+            expected_pc: None,
+        }
+    }
+
+    /// Forgets the tree, keeping the labels, for when a program is loaded again.
+    pub fn restart(&mut self) {
+        self.nodes = vec![root()];
+        self.current = 0;
+        self.expected_pc = None;
+    }
+
+    /// Counts the instruction `word` fetched from `pc` in the current frame, then
+    /// follows the call or return it made. `next_pc` is the PC once it finished.
+    pub fn record(&mut self, pc: u16, word: u16, next_pc: u16, exception: bool) {
+        if self.nodes[0].name.is_empty() {
+            self.nodes[0].name = self.name_of(pc);
+        }
+        if self.expected_pc.is_some_and(|expected| expected != pc) {
+            let name = self.name_of(pc);
+            self.enter(name);
+        }
+        self.expected_pc = Some(next_pc);
+        self.nodes[self.current].exclusive += 1;
+
+        if exception {
+            let name = self.name_of(next_pc);
+            self.enter(name);
+            return;
+        }
+
+        match Op::decode(word) {
+            Op::Jsr { .. } | Op::Jsrr { .. } => {
+                let name = self.name_of(next_pc);
+                self.enter(name);
+            }
+            Op::Trap { vector } => {
+                let name = Op::Trap { vector }.disassemble(pc);
+                self.enter(name);
+                // a trap run as host code has already returned
+                if next_pc == pc.wrapping_add(1) {
+                    self.leave();
+                }
+            }
+            Op::Jmp { base: 7 } | Op::Rti => self.leave(),
+            _ => {}
+        }
+    }
+
+    fn name_of(&self, address: u16) -> String {
+        match self.labels.get(&address) {
+            Some(label) => return label.clone(),
+            None => return format!("x{address:04X}"),
+        }
+    }
+
+    fn enter(&mut self, name: String) {
+        let existing = self.nodes[self.current]
+            .children
+            .iter()
+            .copied()
+            .find(|child| self.nodes[*child].name == name);
+
+        let child = match existing {
+            Some(child) => child,
+            None => {
+                self.nodes.push(CallNode {
+                    name: name,
+                    parent: Some(self.current),
+                    children: vec![],
+                    calls: 0,
+                    exclusive: 0,
+                });
+                let child = self.nodes.len() - 1;
+                self.nodes[self.current].children.push(child);
+                child
+            }
+        };
+        self.nodes[child].calls += 1;
+        self.current = child;
+    }
+
+    fn leave(&mut self) {
+        if let Some(parent) = self.nodes[self.current].parent {
+            self.current = parent;
+        }
+    }
+
+    /// Instructions run in a node and everything it called.
+    pub fn inclusive(&self, node: usize) -> u64 {
+        let children: u64 = self.nodes[node]
+            .children
+            .iter()
+            .map(|child| self.inclusive(*child))
+            .sum();
+        return self.nodes[node].exclusive + children;
+    }
+
+    /// The names from the root down to `node`.
+    fn path(&self, node: usize) -> Vec<&str> {
+        let mut path = vec![];
+        let mut at = Some(node);
+        while let Some(node) = at {
+            path.push(self.nodes[node].name.as_str());
+            at = self.nodes[node].parent;
+        }
+        path.reverse();
+        return path;
+    }
+
+    /// The tree in the folded-stack format read by flamegraph tools: one line
+    /// per chain of calls, like `main;FACT;FACT 12`, with the number of
+    /// instructions run at the end of that chain.
+    pub fn folded(&self) -> String {
+        let mut lines: Vec<String> = (0..self.nodes.len())
+            .filter(|node| self.nodes[*node].exclusive > 0)
+            .map(|node| {
+                format!(
+                    "{} {}",
+                    self.path(node).join(";"),
+                    self.nodes[node].exclusive
+                )
+            })
+            .collect();
+        lines.sort();
+        return lines.iter().map(|line| format!("{line}\n")).collect();
+    }
+
+    /// The counts for each subroutine, by name.
+    pub fn totals(&self) -> BTreeMap<String, CallTotals> {
+        let mut totals: BTreeMap<String, CallTotals> = BTreeMap::new();
+        for node in 0..self.nodes.len() {
+            let path = self.path(node);
+            let name = self.nodes[node].name.clone();
+            let total = totals.entry(name.clone()).or_default();
+            total.calls += self.nodes[node].calls;
+            total.exclusive += self.nodes[node].exclusive;
+            // a recursive call's instructions are already inside the outermost call
+            if !path[..path.len() - 1].contains(&name.as_str()) {
+                total.inclusive += self.inclusive(node);
+            }
+        }
+        return totals;
+    }
+
+    /// The call tree as indented text, followed by the totals per subroutine.
+    pub fn report(&self) -> String {
+        let mut out = String::from("Call graph:\n");
+        out += "  inclusive  exclusive      calls  name\n";
+        self.report_node(0, 0, &mut out);
+
+        out += "\nSubroutines:\n";
+        out += "  inclusive  exclusive      calls  name\n";
+        let mut totals: Vec<(String, CallTotals)> = self.totals().into_iter().collect();
+        totals.sort_by(|a, b| b.1.inclusive.cmp(&a.1.inclusive).then(a.0.cmp(&b.0)));
+        for (name, total) in totals {
+            out += &format!(
+                "  {:>9}  {:>9}  {:>9}  {name}\n",
+                total.inclusive, total.exclusive, total.calls
+            );
+        }
+        return out;
+    }
+
+    fn report_node(&self, node: usize, depth: usize, out: &mut String) {
+        let n = &self.nodes[node];
+        *out += &format!(
+            "  {:>9}  {:>9}  {:>9}  {}{}\n",
+            self.inclusive(node),
+            n.exclusive,
+            n.calls,
+            "  ".repeat(depth),
+            n.name
+        );
+        for child in &n.children {
+            self.report_node(*child, depth + 1, out);
+        }
+    }
+}
+
+fn root() -> CallNode {
+    return CallNode {
+        name: String::new(),
+        parent: None,
+        children: vec![],
+        calls: 1,
+        exclusive: 0,
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const JSR: u16 = 0b0100_1_00000000000;
+    const RET: u16 = 0b1100_000_111_000000;
+    const ADD: u16 = 0x1261;
+
+    #[test]
+    fn test_recursive_calls() {
+        let symbols = HashMap::from([("MAIN".to_string(), 0x3000), ("F".to_string(), 0x3010)]);
+        let mut graph = CallGraph::new(&symbols);
+
+        // MAIN calls F, which calls itself once
+        graph.record(0x3000, JSR, 0x3010, false);
+        graph.record(0x3010, ADD, 0x3011, false);
+        graph.record(0x3011, JSR, 0x3010, false);
+        graph.record(0x3010, ADD, 0x3011, false);
+        graph.record(0x3011, RET, 0x3012, false);
+        graph.record(0x3012, RET, 0x3001, false);
+        graph.record(0x3001, 0xF025, 0x3002, false);
+
+        assert_eq!(graph.folded(), "MAIN 2\nMAIN;F 3\nMAIN;F;F 2\n");
+        assert_eq!(graph.inclusive(0), 7);
+
+        let totals = graph.totals();
+        assert_eq!(
+            totals["F"],
+            CallTotals {
+                calls: 2,
+                inclusive: 5,
+                exclusive: 5
+            }
+        );
+        assert_eq!(totals["HALT"].calls, 1);
+    }
+
+    #[test]
+    fn test_interrupt_enters_a_frame() {
+        let mut graph = CallGraph::new(&HashMap::new());
+
+        graph.record(0x3000, ADD, 0x3001, false);
+        // an interrupt was serviced before the next fetch
+        graph.record(0x1000, ADD, 0x1001, false);
+        graph.record(0x1001, 0x8000, 0x3001, false);
+        graph.record(0x3001, ADD, 0x3002, false);
+
+        assert_eq!(graph.folded(), "x3000 2\nx3000;x1000 2\n");
+    }
+}
@@ -0,0 +1,269 @@
+use super::decode::Op;
+use crate::asm::source_map::SourceMap;
+use crate::program::Program;
+use std::collections::{BTreeMap, HashMap};
+
+/// How often a conditional branch went each way.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct BranchCounts {
+    pub taken: u64,
+    pub not_taken: u64,
+}
+
+/// Which instructions ran, and which way each conditional branch went, while
+/// `VM::coverage` is set. Reports map addresses back to source lines with the
+/// `SourceMap` the assembler stores in the program's `debug_info`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Coverage {
+    /// Times each address was executed.
+    pub executed: HashMap<u16, u64>,
+    /// Conditional branches (not `BRnzp` or a `NOP`) by address.
+    pub branches: HashMap<u16, BranchCounts>,
+}
+
+/// Coverage of one source line.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct LineCoverage {
+    hits: u64,
+    /// Set for lines that hold a conditional branch.
+    branch: Option<BranchCounts>,
+}
+
+impl Coverage {
+    pub fn new() -> Coverage {
+        return Coverage::default();
+    }
+
+    /// Counts the instruction `word` fetched from `pc`. `next_pc` is the PC once
+    /// it finished. A branch counts as taken when it moved the PC somewhere
+    /// other than the next instruction, so a branch to the next instruction
+    /// always counts as not taken.
+    pub fn record(&mut self, pc: u16, word: u16, next_pc: u16) {
+        *self.executed.entry(pc).or_insert(0) += 1;
+
+        if is_conditional_branch(word) {
+            let counts = self.branches.entry(pc).or_default();
+            if next_pc == pc.wrapping_add(1) {
+                counts.not_taken += 1;
+            } else {
+                counts.taken += 1;
+            }
+        }
+    }
+
+    /// Coverage per source line, for the lines that hold instructions.
+    fn lines(&self, source_map: &SourceMap) -> BTreeMap<usize, LineCoverage> {
+        let mut lines: BTreeMap<usize, LineCoverage> = BTreeMap::new();
+        for (address, location) in source_map.iter() {
+            if !location.instruction {
+                continue;
+            }
+            let line = lines.entry(location.line).or_default();
+            line.hits += self.executed.get(&address).copied().unwrap_or(0);
+            if let Some(counts) = self.branches.get(&address) {
+                let branch = line.branch.get_or_insert_with(BranchCounts::default);
+                branch.taken += counts.taken;
+                branch.not_taken += counts.not_taken;
+            }
+        }
+        return lines;
+    }
+
+    /// The coverage of `program` in lcov's tracefile format, for `genhtml` and
+    /// coverage services. `source_path` is the assembly file it came from.
+    pub fn lcov(&self, program: &Program, source_path: &str) -> String {
+        let empty = SourceMap::new();
+        let source_map = program.debug_info.as_ref().unwrap_or(&empty);
+        let lines = self.lines(source_map);
+        let branch_lines = branch_lines(program, source_map);
+
+        let mut out = format!("TN:\nSF:{source_path}\n");
+        let mut found_branches = 0;
+        let mut hit_branches = 0;
+        for line in &branch_lines {
+            let coverage = lines.get(line).copied().unwrap_or_default();
+            let counts = coverage.branch.unwrap_or_default();
+            for (index, count) in [counts.taken, counts.not_taken].into_iter().enumerate() {
+                let count = if coverage.hits == 0 {
+                    "-".to_string()
+                } else {
+                    count.to_string()
+                };
+                out += &format!("BRDA:{line},0,{index},{count}\n");
+            }
+            found_branches += 2;
+            hit_branches += (counts.taken > 0) as u32 + (counts.not_taken > 0) as u32;
+        }
+        out += &format!("BRF:{found_branches}\nBRH:{hit_branches}\n");
+
+        for (line, coverage) in &lines {
+            out += &format!("DA:{line},{}\n", coverage.hits);
+        }
+        let hit_lines = lines.values().filter(|coverage| coverage.hits > 0).count();
+        out += &format!("LF:{}\nLH:{hit_lines}\nend_of_record\n", lines.len());
+        return out;
+    }
+
+    /// The `source` of `program` with every line prefixed by how often it ran,
+    /// `#####` for instructions that never ran, or `-` for lines without
+    /// instructions. Branches that only ever went one way are marked.
+    pub fn annotate(&self, program: &Program, source: &str) -> String {
+        let empty = SourceMap::new();
+        let source_map = program.debug_info.as_ref().unwrap_or(&empty);
+        let lines = self.lines(source_map);
+        let branch_lines = branch_lines(program, source_map);
+
+        let mut out = String::new();
+        for (index, text) in source.lines().enumerate() {
+            let number = index + 1;
+            let count = match lines.get(&number) {
+                Some(coverage) if coverage.hits > 0 => coverage.hits.to_string(),
+                Some(_) => "#####".to_string(),
+                None => "-".to_string(),
+            };
+            out += &format!("{count:>9}:{number:>5}: {text}");
+
+            if branch_lines.contains(&number) {
+                let coverage = lines.get(&number).copied().unwrap_or_default();
+                let counts = coverage.branch.unwrap_or_default();
+                if coverage.hits > 0 && counts.taken == 0 {
+                    out += "    <- branch never taken";
+                } else if coverage.hits > 0 && counts.not_taken == 0 {
+                    out += "    <- branch always taken";
+                }
+            }
+            out += "\n";
+        }
+        return out;
+    }
+
+    /// The share of instruction lines that ran, and of branch directions that
+    /// were followed, as percentages.
+    pub fn summary(&self, program: &Program) -> (f64, f64) {
+        let empty = SourceMap::new();
+        let source_map = program.debug_info.as_ref().unwrap_or(&empty);
+        let lines = self.lines(source_map);
+        let branch_lines = branch_lines(program, source_map);
+
+        let hit_lines = lines.values().filter(|coverage| coverage.hits > 0).count();
+        let hit_directions: u64 = branch_lines
+            .iter()
+            .map(|line| {
+                let counts = lines
+                    .get(line)
+                    .and_then(|coverage| coverage.branch)
+                    .unwrap_or_default();
+                (counts.taken > 0) as u64 + (counts.not_taken > 0) as u64
+            })
+            .sum();
+
+        return (
+            percent(hit_lines as u64, lines.len() as u64),
+            percent(hit_directions, branch_lines.len() as u64 * 2),
+        );
+    }
+}
+
+fn percent(part: u64, whole: u64) -> f64 {
+    if whole == 0 {
+        return 100.0;
+    }
+    return part as f64 * 100.0 / whole as f64;
+}
+
+/// The lines that hold a conditional branch, whether or not it ran. These come
+/// from the assembled words, since a branch that never ran was never recorded.
+fn branch_lines(program: &Program, source_map: &SourceMap) -> Vec<usize> {
+    let mut lines = vec![];
+    for (address, location) in source_map.iter() {
+        if !location.instruction || lines.contains(&location.line) {
+            continue;
+        }
+        if program.word_at(address).is_some_and(is_conditional_branch) {
+            lines.push(location.line);
+        }
+    }
+    lines.sort();
+    return lines;
+}
+
+/// Whether `word` is `BRn`, `BRzp` and so on, as opposed to `BR` or `BRnzp`,
+/// which always branch, or a `NOP`, which never does.
+fn is_conditional_branch(word: u16) -> bool {
+    let Op::Br { n, z, p, .. } = Op::decode(word) else {
+        return false;
+    };
+    return (n || z || p) && !(n && z && p);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::source_map::{SourceLocation, Span};
+
+    const SOURCE: &str = ".orig x3000
+loop    add r1, r1, #-1
+        brp loop
+        brz done
+brn     add r2, r2, #1
+done    halt
+.end";
+
+    fn program() -> Program {
+        let mut map = SourceMap::new();
+        for (i, line) in [2, 3, 4, 5, 6].into_iter().enumerate() {
+            map.insert(
+                0x3000 + i as u16,
+                SourceLocation {
+                    line: line,
+                    columns: Span::default(),
+                    offsets: Span::default(),
+                    label: None,
+                    instruction: true,
+                },
+            );
+        }
+
+        let mut program = Program::new(0x3000, vec![0x127F, 0x03FE, 0x0401, 0x14A1, 0xF025]);
+        program.debug_info = Some(map);
+        return program;
+    }
+
+    fn coverage() -> Coverage {
+        let mut coverage = Coverage::new();
+        // the loop runs twice, then falls through to BRz, which is taken
+        coverage.record(0x3000, 0x127F, 0x3001);
+        coverage.record(0x3001, 0x03FE, 0x3000);
+        coverage.record(0x3000, 0x127F, 0x3001);
+        coverage.record(0x3001, 0x03FE, 0x3002);
+        coverage.record(0x3002, 0x0401, 0x3004);
+        coverage.record(0x3004, 0xF025, 0x3005);
+        return coverage;
+    }
+
+    #[test]
+    fn test_lcov() {
+        let lcov = coverage().lcov(&program(), "loop.asm");
+
+        assert!(lcov.starts_with("TN:\nSF:loop.asm\n"));
+        assert!(lcov.contains("BRDA:3,0,0,1\nBRDA:3,0,1,1\nBRDA:4,0,0,1\nBRDA:4,0,1,0\n"));
+        assert!(lcov.contains("BRF:4\nBRH:3\n"));
+        assert!(lcov.contains("DA:2,2\nDA:3,2\nDA:4,1\nDA:5,0\nDA:6,1\n"));
+        assert!(lcov.ends_with("LF:5\nLH:4\nend_of_record\n"));
+    }
+
+    #[test]
+    fn test_annotate() {
+        let annotated = coverage().annotate(&program(), SOURCE);
+        let lines: Vec<&str> = annotated.lines().collect();
+
+        assert_eq!(lines[0], "        -:    1: .orig x3000");
+        assert_eq!(lines[1], "        2:    2: loop    add r1, r1, #-1");
+        assert_eq!(
+            lines[3],
+            "        1:    4:         brz done    <- branch always taken"
+        );
+        assert_eq!(lines[4], "    #####:    5: brn     add r2, r2, #1");
+        assert_eq!(coverage().summary(&program()), (80.0, 75.0));
+    }
+}
@@ -0,0 +1,353 @@
+/// An instruction word decoded into its fields. Decoding is done once per word
+/// (or once per address, with the decode cache in `Memory`), so executing an
+/// instruction is a single `match` with no further bit slicing.
+///
+/// Register fields are register numbers (0-7). Offsets and immediate values are
+/// already sign-extended to 16 bits.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Op {
+    Br {
+        n: bool,
+        z: bool,
+        p: bool,
+        offset: u16,
+    },
+    Add {
+        dr: u8,
+        sr1: u8,
+        src2: Operand,
+    },
+    Ld {
+        dr: u8,
+        offset: u16,
+    },
+    St {
+        sr: u8,
+        offset: u16,
+    },
+    Jsr {
+        offset: u16,
+    },
+    Jsrr {
+        base: u8,
+    },
+    And {
+        dr: u8,
+        sr1: u8,
+        src2: Operand,
+    },
+    Ldr {
+        dr: u8,
+        base: u8,
+        offset: u16,
+    },
+    Str {
+        sr: u8,
+        base: u8,
+        offset: u16,
+    },
+    Rti,
+    Not {
+        dr: u8,
+        sr: u8,
+    },
+    Ldi {
+        dr: u8,
+        offset: u16,
+    },
+    Sti {
+        sr: u8,
+        offset: u16,
+    },
+    /// Also RET, which is JMP R7.
+    Jmp {
+        base: u8,
+    },
+    Reserved,
+    Lea {
+        dr: u8,
+        offset: u16,
+    },
+    Trap {
+        vector: u16,
+    },
+}
+
+/// The second source operand of ADD and AND.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Operand {
+    Reg(u8),
+    Imm(u16),
+}
+
+impl Op {
+    /// Splits `word` into the fields listed by `layout`.
+    pub fn decode(word: u16) -> Op {
+        let opcode = word >> 12;
+        let r9 = ((word >> 9) & 0b111) as u8;
+        let r6 = ((word >> 6) & 0b111) as u8;
+
+        match opcode {
+            0 => Op::Br {
+                n: bit(word, 11),
+                z: bit(word, 10),
+                p: bit(word, 9),
+                offset: sign_extend(word, 9),
+            },
+            1 => Op::Add {
+                dr: r9,
+                sr1: r6,
+                src2: operand(word),
+            },
+            2 => Op::Ld {
+                dr: r9,
+                offset: sign_extend(word, 9),
+            },
+            3 => Op::St {
+                sr: r9,
+                offset: sign_extend(word, 9),
+            },
+            4 => {
+                if bit(word, 11) {
+                    Op::Jsr {
+                        offset: sign_extend(word, 11),
+                    }
+                } else {
+                    Op::Jsrr { base: r6 }
+                }
+            }
+            5 => Op::And {
+                dr: r9,
+                sr1: r6,
+                src2: operand(word),
+            },
+            6 => Op::Ldr {
+                dr: r9,
+                base: r6,
+                offset: sign_extend(word, 6),
+            },
+            7 => Op::Str {
+                sr: r9,
+                base: r6,
+                offset: sign_extend(word, 6),
+            },
+            8 => Op::Rti,
+            9 => Op::Not { dr: r9, sr: r6 },
+            10 => Op::Ldi {
+                dr: r9,
+                offset: sign_extend(word, 9),
+            },
+            11 => Op::Sti {
+                sr: r9,
+                offset: sign_extend(word, 9),
+            },
+            12 => Op::Jmp { base: r6 },
+            13 => Op::Reserved,
+            14 => Op::Lea {
+                dr: r9,
+                offset: sign_extend(word, 9),
+            },
+            15 => Op::Trap {
+                vector: word & 0xFF,
+            },
+            _ => unreachable!(),
+        }
+    }
+
+    /// The field names and widths of the instruction, from bit 15 down. These are
+    /// the fields `decode` reads, and `-` marks bits it ignores.
+    pub fn layout(&self) -> &'static [(&'static str, u32)] {
+        match self {
+            Op::Br { .. } => &[("op", 4), ("nzp", 3), ("pcoffset9", 9)],
+            Op::Add {
+                src2: Operand::Reg(_),
+                ..
+            }
+            | Op::And {
+                src2: Operand::Reg(_),
+                ..
+            } => &[
+                ("op", 4),
+                ("dr", 3),
+                ("sr1", 3),
+                ("c", 1),
+                ("-", 2),
+                ("sr2", 3),
+            ],
+            Op::Add { .. } | Op::And { .. } => {
+                &[("op", 4), ("dr", 3), ("sr1", 3), ("c", 1), ("imm5", 5)]
+            }
+            Op::Ld { .. } | Op::Ldi { .. } | Op::Lea { .. } => {
+                &[("op", 4), ("dr", 3), ("pcoffset9", 9)]
+            }
+            Op::St { .. } | Op::Sti { .. } => &[("op", 4), ("sr", 3), ("pcoffset9", 9)],
+            Op::Jsr { .. } => &[("op", 4), ("c", 1), ("pcoffset11", 11)],
+            Op::Jsrr { .. } => &[("op", 4), ("c", 1), ("-", 2), ("baser", 3), ("-", 6)],
+            Op::Ldr { .. } => &[("op", 4), ("dr", 3), ("baser", 3), ("offset6", 6)],
+            Op::Str { .. } => &[("op", 4), ("sr", 3), ("baser", 3), ("offset6", 6)],
+            Op::Not { .. } => &[("op", 4), ("dr", 3), ("sr", 3), ("-", 6)],
+            Op::Jmp { .. } => &[("op", 4), ("-", 3), ("baser", 3), ("-", 6)],
+            Op::Trap { .. } => &[("op", 4), ("-", 4), ("trapvect8", 8)],
+            Op::Rti | Op::Reserved => &[("op", 4), ("-", 12)],
+        }
+    }
+}
+
+impl Op {
+    /// The instruction in assembly syntax, e.g. `ADD R1, R1, #10` or `BRnz x3002`.
+    /// `address` is where the instruction is stored, and is used to turn
+    /// PC-relative offsets into the addresses they refer to. The standard traps
+    /// are shown by their names, like `HALT`.
+    pub fn disassemble(&self, address: u16) -> String {
+        let target = |offset: u16| address.wrapping_add(1).wrapping_add(offset);
+
+        match *self {
+            Op::Br { n, z, p, offset } => {
+                if !n && !z && !p {
+                    return "NOP".to_string();
+                }
+                let mut flags = String::new();
+                for (set, flag) in [(n, 'n'), (z, 'z'), (p, 'p')] {
+                    if set {
+                        flags.push(flag);
+                    }
+                }
+                return format!("BR{flags} x{:04X}", target(offset));
+            }
+            Op::Add { dr, sr1, src2 } => {
+                return format!("ADD R{dr}, R{sr1}, {}", operand_string(src2));
+            }
+            Op::And { dr, sr1, src2 } => {
+                return format!("AND R{dr}, R{sr1}, {}", operand_string(src2));
+            }
+            Op::Ld { dr, offset } => return format!("LD R{dr}, x{:04X}", target(offset)),
+            Op::St { sr, offset } => return format!("ST R{sr}, x{:04X}", target(offset)),
+            Op::Ldi { dr, offset } => return format!("LDI R{dr}, x{:04X}", target(offset)),
+            Op::Sti { sr, offset } => return format!("STI R{sr}, x{:04X}", target(offset)),
+            Op::Lea { dr, offset } => return format!("LEA R{dr}, x{:04X}", target(offset)),
+            Op::Jsr { offset } => return format!("JSR x{:04X}", target(offset)),
+            Op::Jsrr { base } => return format!("JSRR R{base}"),
+            Op::Ldr { dr, base, offset } => {
+                return format!("LDR R{dr}, R{base}, #{}", offset as i16);
+            }
+            Op::Str { sr, base, offset } => {
+                return format!("STR R{sr}, R{base}, #{}", offset as i16);
+            }
+            Op::Rti => return "RTI".to_string(),
+            Op::Not { dr, sr } => return format!("NOT R{dr}, R{sr}"),
+            Op::Jmp { base: 7 } => return "RET".to_string(),
+            Op::Jmp { base } => return format!("JMP R{base}"),
+            Op::Reserved => return "RESERVED".to_string(),
+            Op::Trap { vector } => {
+                let name = match vector {
+                    0x20 => "GETC",
+                    0x21 => "OUT",
+                    0x22 => "PUTS",
+                    0x23 => "IN",
+                    0x24 => "PUTSP",
+                    0x25 => "HALT",
+                    _ => return format!("TRAP x{vector:02X}"),
+                };
+                return name.to_string();
+            }
+        }
+    }
+}
+
+/// Sign-extends the low `bits` bits of `value`, which hold a 2's complement number.
+pub fn sign_extend(value: u16, bits: u32) -> u16 {
+    let shift = 16 - bits;
+    return (((value << shift) as i16) >> shift) as u16;
+}
+
+fn bit(word: u16, index: u32) -> bool {
+    return (word >> index) & 1 == 1;
+}
+
+fn operand_string(operand: Operand) -> String {
+    match operand {
+        Operand::Reg(r) => return format!("R{r}"),
+        Operand::Imm(imm) => return format!("#{}", imm as i16),
+    }
+}
+
+fn operand(word: u16) -> Operand {
+    if bit(word, 5) {
+        return Operand::Imm(sign_extend(word, 5));
+    }
+    return Operand::Reg((word & 0b111) as u8);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sign_extend() {
+        assert_eq!(sign_extend(0b01111, 5), 15);
+        assert_eq!(sign_extend(0b10000, 5), (-16i16) as u16);
+        assert_eq!(sign_extend(0b1111_1111_1111_1111, 9), u16::MAX);
+        assert_eq!(sign_extend(0b100_0000_0000, 11), (-1024i16) as u16);
+    }
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(
+            Op::decode(0b0001_010_001_1_11000),
+            Op::Add {
+                dr: 2,
+                sr1: 1,
+                src2: Operand::Imm((-8i16) as u16)
+            }
+        );
+        assert_eq!(
+            Op::decode(0b0101_011_100_0_00_101),
+            Op::And {
+                dr: 3,
+                sr1: 4,
+                src2: Operand::Reg(5)
+            }
+        );
+        assert_eq!(
+            Op::decode(0b0000_101_111111110),
+            Op::Br {
+                n: true,
+                z: false,
+                p: true,
+                offset: (-2i16) as u16
+            }
+        );
+        assert_eq!(Op::decode(0b0100_0_00_011_000000), Op::Jsrr { base: 3 });
+        assert_eq!(Op::decode(0b1100_000_111_000000), Op::Jmp { base: 7 });
+        assert_eq!(Op::decode(0xF025), Op::Trap { vector: 0x25 });
+        assert_eq!(Op::decode(0xD000), Op::Reserved);
+    }
+
+    #[test]
+    fn test_layout_covers_every_bit() {
+        for opcode in 0..16u16 {
+            // JSR and JSRR share an opcode, and so do ADD/AND with a register or an immediate
+            for mode in [0, 0b1000_0010_0000] {
+                let op = Op::decode((opcode << 12) | mode);
+                let width: u32 = op.layout().iter().map(|(_, width)| width).sum();
+                assert_eq!(width, 16, "{op:?}");
+                assert_eq!(op.layout()[0], ("op", 4));
+            }
+        }
+    }
+
+    #[test]
+    fn test_disassemble() {
+        let dis = |word: u16| Op::decode(word).disassemble(0x3000);
+
+        assert_eq!(dis(0b0001_001_001_1_01010), "ADD R1, R1, #10");
+        assert_eq!(dis(0b0101_011_100_0_00_101), "AND R3, R4, R5");
+        assert_eq!(dis(0b0000_110_111111110), "BRnz x2FFF");
+        assert_eq!(dis(0x0000), "NOP");
+        assert_eq!(dis(0b0010_000_000001111), "LD R0, x3010");
+        assert_eq!(dis(0b0110_010_110_111111), "LDR R2, R6, #-1");
+        assert_eq!(dis(0b1100_000_111_000000), "RET");
+        assert_eq!(dis(0xF025), "HALT");
+        assert_eq!(dis(0xF030), "TRAP x30");
+    }
+}
@@ -0,0 +1,157 @@
+use super::registers::Registers;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "serde")]
+use tsify::Tsify;
+
+/// A value before and after an instruction ran.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize, Tsify))]
+pub struct Change {
+    pub old: u16,
+    pub new: u16,
+}
+
+/// A general purpose register (R0-R7) that was written.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize, Tsify))]
+pub struct RegisterChange {
+    pub register: u8,
+    pub old: u16,
+    pub new: u16,
+}
+
+/// A memory location (or device register) that was written.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize, Tsify))]
+pub struct MemoryWrite {
+    pub address: u16,
+    pub old: u16,
+    pub new: u16,
+}
+
+/// A character that passed through the IO target.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize, Tsify))]
+pub enum IoEvent {
+    Input(char),
+    Output(char),
+}
+
+/// Everything a single step of the VM changed. Registers that were written
+/// with the value they already held are not reported.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize, Tsify))]
+#[cfg_attr(feature = "serde", tsify(into_wasm_abi, from_wasm_abi))]
+pub struct StateDelta {
+    /// The instruction that was executed, if one was fetched.
+    pub ir: Option<u16>,
+    pub pc: Change,
+    pub registers: Vec<RegisterChange>,
+    /// The whole PSR, if any part of it changed.
+    pub psr: Option<Change>,
+    /// The condition codes (PSR[2:0]), if they changed.
+    pub cc: Option<Change>,
+    pub saved_usp: Option<Change>,
+    pub saved_ssp: Option<Change>,
+    /// Memory writes, in the order they happened. A location written twice
+    /// appears twice.
+    pub memory: Vec<MemoryWrite>,
+    pub io: Vec<IoEvent>,
+}
+
+impl StateDelta {
+    /// A delta for a step that changed nothing.
+    pub fn new(reg: &Registers) -> StateDelta {
+        StateDelta {
+            ir: None,
+            pc: Change {
+                old: reg.pc,
+                new: reg.pc,
+            },
+            registers: vec![],
+            psr: None,
+            cc: None,
+            saved_usp: None,
+            saved_ssp: None,
+            memory: vec![],
+            io: vec![],
+        }
+    }
+
+    /// Compares the registers from before and after a step.
+    pub fn between(before: &Registers, after: &Registers) -> StateDelta {
+        let mut delta = StateDelta::new(before);
+        delta.pc.new = after.pc;
+
+        for i in 0..8 {
+            if before.r[i] != after.r[i] {
+                delta.registers.push(RegisterChange {
+                    register: i as u8,
+                    old: before.r[i],
+                    new: after.r[i],
+                });
+            }
+        }
+
+        delta.psr = changed(before.psr, after.psr);
+        delta.cc = changed(before.psr & 0b111, after.psr & 0b111);
+        delta.saved_usp = changed(before.saved_usp, after.saved_usp);
+        delta.saved_ssp = changed(before.saved_ssp, after.saved_ssp);
+
+        return delta;
+    }
+
+    /// Characters written to the display during the step.
+    pub fn output(&self) -> String {
+        return self
+            .io
+            .iter()
+            .filter_map(|event| match event {
+                IoEvent::Output(c) => Some(*c),
+                IoEvent::Input(_) => None,
+            })
+            .collect();
+    }
+}
+
+fn changed(old: u16, new: u16) -> Option<Change> {
+    if old == new {
+        return None;
+    }
+    return Some(Change { old, new });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_delta_between_registers() {
+        let before = Registers::new();
+        let mut after = before;
+        after.pc = 0x3001;
+        after.r[2] = 7;
+        after.set_cc(false, false, true);
+
+        let delta = StateDelta::between(&before, &after);
+
+        assert_eq!(
+            delta.pc,
+            Change {
+                old: 0,
+                new: 0x3001
+            }
+        );
+        assert_eq!(
+            delta.registers,
+            vec![RegisterChange {
+                register: 2,
+                old: 0,
+                new: 7
+            }]
+        );
+        assert_eq!(delta.cc, Some(Change { old: 0, new: 0b001 }));
+        assert_eq!(delta.saved_usp, None);
+    }
+}
@@ -0,0 +1,240 @@
+use crate::io::Lc3IO;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "serde")]
+use tsify::Tsify;
+
+/// Keyboard status register. Bit 15 is set when a new character is waiting in KBDR.
+pub const KBSR: u16 = 0xFE00;
+/// Keyboard data register. Bits [7:0] hold the last character typed.
+pub const KBDR: u16 = 0xFE02;
+/// Display status register. Bit 15 is set when the display is ready for another character.
+pub const DSR: u16 = 0xFE04;
+/// Display data register. Writing a character here prints it to the display.
+pub const DDR: u16 = 0xFE06;
+
+/// Machine control register. The clock runs for as long as bit 15 is set.
+pub const MCR: u16 = 0xFFFE;
+
+/// Start of the memory-mapped I/O page (xFE00-xFFFF).
+pub const DEVICE_SPACE_START: u16 = 0xFE00;
+
+const READY_BIT: u16 = 1 << 15;
+const INTERRUPT_ENABLE_BIT: u16 = 1 << 14;
+const CLOCK_ENABLE_BIT: u16 = 1 << 15;
+
+/// The device registers that live behind `Memory` in the xFE00-xFFFF page.
+///
+/// Reads and writes made by instructions go through `Devices::read` and
+/// `Devices::write`, which have side effects (polling the keyboard, printing a
+/// character). `peek` and `poke` access the registers directly, without any
+/// side effects, for loaders and debuggers.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize, Tsify))]
+#[cfg_attr(feature = "serde", tsify(into_wasm_abi, from_wasm_abi))]
+pub struct Devices {
+    pub kbsr: u16,
+    pub kbdr: u16,
+    pub dsr: u16,
+    pub ddr: u16,
+    pub mcr: u16,
+    /// Set when an instruction polled KBSR while no input was available.
+    pub keyboard_starved: bool,
+}
+
+impl Devices {
+    pub fn new() -> Devices {
+        Devices {
+            kbsr: 0,
+            kbdr: 0,
+            dsr: READY_BIT,
+            ddr: 0,
+            mcr: CLOCK_ENABLE_BIT,
+            keyboard_starved: false,
+        }
+    }
+
+    pub fn is_device_register(loc: u16) -> bool {
+        return matches!(loc, KBSR | KBDR | DSR | DDR | MCR);
+    }
+
+    /// Reads a device register as an instruction would. Reading KBSR polls the
+    /// keyboard, and reading KBDR clears the KBSR ready bit.
+    pub fn read(&mut self, loc: u16, io: &mut Lc3IO) -> Option<u16> {
+        match loc {
+            KBSR => {
+                self.poll_keyboard(io);
+                self.keyboard_starved = self.kbsr & READY_BIT == 0;
+                return Some(self.kbsr);
+            }
+            KBDR => {
+                self.kbsr &= !READY_BIT;
+                return Some(self.kbdr);
+            }
+            DSR | DDR | MCR => return self.peek(loc),
+            _ => return None,
+        }
+    }
+
+    /// Writes a device register as an instruction would. Returns `false` if `loc`
+    /// is not a device register.
+    ///
+    /// Only the interrupt enable bit (bit 14) of KBSR is writable. Writing DDR
+    /// clears the DSR ready bit until the character has been handed to the
+    /// display, which happens immediately.
+    pub fn write(&mut self, loc: u16, val: u16, io: &mut Lc3IO) -> bool {
+        match loc {
+            KBSR => {
+                self.kbsr = (self.kbsr & !INTERRUPT_ENABLE_BIT) | (val & INTERRUPT_ENABLE_BIT);
+            }
+            DDR => {
+                self.ddr = val;
+                self.dsr &= !READY_BIT;
+                io.put_char(val as u8 as char);
+                self.dsr |= READY_BIT;
+            }
+            MCR => self.mcr = val,
+            KBDR | DSR => {}
+            _ => return false,
+        }
+        return true;
+    }
+
+    /// Latches the next character from the IO target into KBDR, if the last
+    /// one has already been read.
+    pub fn poll_keyboard(&mut self, io: &mut Lc3IO) {
+        if self.kbsr & READY_BIT != 0 {
+            return;
+        }
+
+        if let Some(c) = io.poll_char() {
+            self.kbdr = c as u16;
+            self.kbsr |= READY_BIT;
+        }
+    }
+
+    /// KBSR[14], set by software to have the keyboard interrupt when a key is typed.
+    pub fn keyboard_interrupt_enabled(&self) -> bool {
+        return self.kbsr & INTERRUPT_ENABLE_BIT != 0;
+    }
+
+    /// The keyboard requests an interrupt for as long as it is both enabled and ready.
+    pub fn keyboard_interrupt_requested(&self) -> bool {
+        return self.keyboard_interrupt_enabled() && self.kbsr & READY_BIT != 0;
+    }
+
+    /// MCR[15]. Clearing it stops the clock, which halts the machine.
+    pub fn clock_enabled(&self) -> bool {
+        return self.mcr & CLOCK_ENABLE_BIT != 0;
+    }
+
+    /// Clears MCR[15], which halts the machine after the current instruction.
+    pub fn stop_clock(&mut self) {
+        self.mcr &= !CLOCK_ENABLE_BIT;
+    }
+
+    /// Waits for the IO target to produce a character, and latches it into KBDR.
+    /// This lets a front end block on a key instead of spinning on KBSR.
+    /// Returns `false` if the IO target has no more input to give.
+    pub fn wait_for_keyboard(&mut self, io: &mut Lc3IO) -> bool {
+        if self.kbsr & READY_BIT != 0 {
+            return true;
+        }
+
+        match io.get_char() {
+            Some(c) => {
+                self.kbdr = c as u16;
+                self.kbsr |= READY_BIT;
+                return true;
+            }
+            None => return false,
+        }
+    }
+
+    pub fn peek(&self, loc: u16) -> Option<u16> {
+        match loc {
+            KBSR => Some(self.kbsr),
+            KBDR => Some(self.kbdr),
+            DSR => Some(self.dsr),
+            DDR => Some(self.ddr),
+            MCR => Some(self.mcr),
+            _ => None,
+        }
+    }
+
+    pub fn poke(&mut self, loc: u16, val: u16) -> bool {
+        match loc {
+            KBSR => self.kbsr = val,
+            KBDR => self.kbdr = val,
+            DSR => self.dsr = val,
+            DDR => self.ddr = val,
+            MCR => self.mcr = val,
+            _ => return false,
+        }
+        return true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::*;
+
+    #[test]
+    fn test_keyboard_polling() {
+        let mut io = Lc3IO::new(Box::new(DebugIO::with_input("hi")));
+        let mut devices = Devices::new();
+
+        assert_eq!(devices.read(KBSR, &mut io), Some(READY_BIT));
+        // polling again before KBDR is read must not drop the first character
+        assert_eq!(devices.read(KBSR, &mut io), Some(READY_BIT));
+        assert_eq!(devices.read(KBDR, &mut io), Some('h' as u16));
+        assert_eq!(devices.peek(KBSR), Some(0));
+
+        devices.read(KBSR, &mut io);
+        assert_eq!(devices.read(KBDR, &mut io), Some('i' as u16));
+        assert_eq!(devices.read(KBSR, &mut io), Some(0));
+    }
+
+    #[test]
+    fn test_display_write() {
+        let mut io = Lc3IO::new(Box::new(DebugIO::new()));
+        let mut devices = Devices::new();
+
+        assert!(devices.write(DDR, 'a' as u16, &mut io));
+        assert_eq!(devices.peek(DDR), Some('a' as u16));
+        assert_eq!(devices.peek(DSR), Some(READY_BIT));
+
+        // only the interrupt enable bit of KBSR can be written
+        devices.write(KBSR, 0xFFFF, &mut io);
+        assert_eq!(devices.peek(KBSR), Some(1 << 14));
+
+        assert!(!devices.write(0x3000, 1, &mut io));
+    }
+
+    #[test]
+    fn test_machine_control_register() {
+        let mut io = Lc3IO::new(Box::new(DebugIO::new()));
+        let mut devices = Devices::new();
+        assert!(devices.clock_enabled());
+
+        let mcr = devices.read(MCR, &mut io).unwrap();
+        devices.write(MCR, mcr & 0x7FFF, &mut io);
+        assert!(!devices.clock_enabled());
+    }
+
+    #[test]
+    fn test_keyboard_starvation() {
+        let mut io = Lc3IO::new(Box::new(DebugIO::new()));
+        let mut devices = Devices::new();
+
+        devices.read(KBSR, &mut io);
+        assert!(devices.keyboard_starved);
+        assert!(!devices.wait_for_keyboard(&mut io));
+
+        let mut io = Lc3IO::new(Box::new(DebugIO::with_input("z")));
+        assert!(devices.wait_for_keyboard(&mut io));
+        assert_eq!(devices.read(KBSR, &mut io), Some(READY_BIT));
+        assert!(!devices.keyboard_starved);
+    }
+}
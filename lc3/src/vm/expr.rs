@@ -0,0 +1,419 @@
+use super::memory::Memory;
+use super::registers::Registers;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+#[cfg(feature = "serde")]
+use tsify::Tsify;
+
+/// A parsed condition or value, such as `R0 == x41 && M[COUNT] > #5`.
+///
+/// Operands are 16-bit words: numbers (`x41`, `#-5`, `65`), registers (`R0`-`R7`,
+/// `PC`, `IR`, `PSR`), condition codes (`N`, `Z`, `P`, which are 1 when set),
+/// memory (`M[addr]`) and labels from the assembler's symbol table, which stand
+/// for their address. Operators, loosest binding first, are `||`, `&&`, the
+/// comparisons `== != < <= > >=` (signed, like the LC-3's condition codes),
+/// `+ -`, and the unary `! -`. Comparisons and logic produce 1 or 0.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize, Tsify))]
+pub enum Expr {
+    Number(u16),
+    Register(u8),
+    Pc,
+    Ir,
+    Psr,
+    N,
+    Z,
+    P,
+    Memory(Box<Expr>),
+    Not(Box<Expr>),
+    Negate(Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize, Tsify))]
+pub enum BinaryOp {
+    Or,
+    And,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Add,
+    Sub,
+}
+
+impl Expr {
+    /// Parses `source`, resolving labels with `symbols`. A label is matched
+    /// exactly first, and then regardless of case.
+    pub fn parse(source: &str, symbols: &HashMap<String, u16>) -> Result<Expr, String> {
+        let tokens = tokenize(source)?;
+        let mut parser = Parser {
+            tokens,
+            pos: 0,
+            symbols,
+        };
+
+        let expr = parser.or()?;
+        if let Some(token) = parser.tokens.get(parser.pos) {
+            return Err(format!("Unexpected `{}` in expression", token.text()));
+        }
+        return Ok(expr);
+    }
+
+    /// Evaluates the expression without side effects: reading memory never
+    /// polls the keyboard.
+    pub fn eval(&self, reg: &Registers, mem: &Memory) -> u16 {
+        match self {
+            Expr::Number(n) => *n,
+            Expr::Register(r) => reg.get(*r as usize),
+            Expr::Pc => reg.pc,
+            Expr::Ir => reg.ir,
+            Expr::Psr => reg.psr,
+            Expr::N => reg.n() as u16,
+            Expr::Z => reg.z() as u16,
+            Expr::P => reg.p() as u16,
+            Expr::Memory(address) => mem.get(address.eval(reg, mem)),
+            Expr::Not(e) => (e.eval(reg, mem) == 0) as u16,
+            Expr::Negate(e) => e.eval(reg, mem).wrapping_neg(),
+            Expr::Binary(op, lhs, rhs) => {
+                let a = lhs.eval(reg, mem);
+                // || and && short-circuit, so `M[...]` on the right is only read if needed
+                match op {
+                    BinaryOp::Or => return (a != 0 || rhs.eval(reg, mem) != 0) as u16,
+                    BinaryOp::And => return (a != 0 && rhs.eval(reg, mem) != 0) as u16,
+                    _ => {}
+                }
+
+                let b = rhs.eval(reg, mem);
+                let (sa, sb) = (a as i16, b as i16);
+                match op {
+                    BinaryOp::Eq => (a == b) as u16,
+                    BinaryOp::Ne => (a != b) as u16,
+                    BinaryOp::Lt => (sa < sb) as u16,
+                    BinaryOp::Le => (sa <= sb) as u16,
+                    BinaryOp::Gt => (sa > sb) as u16,
+                    BinaryOp::Ge => (sa >= sb) as u16,
+                    BinaryOp::Add => a.wrapping_add(b),
+                    BinaryOp::Sub => a.wrapping_sub(b),
+                    BinaryOp::Or | BinaryOp::And => unreachable!(),
+                }
+            }
+        }
+    }
+
+    /// Whether the expression holds, i.e. evaluates to anything but 0.
+    pub fn is_true(&self, reg: &Registers, mem: &Memory) -> bool {
+        return self.eval(reg, mem) != 0;
+    }
+}
+
+impl fmt::Display for Expr {
+    /// Writes the expression back out, with labels replaced by their address
+    /// and every binary operation in parentheses.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Expr::Number(n) => write!(f, "x{n:04X}"),
+            Expr::Register(r) => write!(f, "R{r}"),
+            Expr::Pc => write!(f, "PC"),
+            Expr::Ir => write!(f, "IR"),
+            Expr::Psr => write!(f, "PSR"),
+            Expr::N => write!(f, "N"),
+            Expr::Z => write!(f, "Z"),
+            Expr::P => write!(f, "P"),
+            Expr::Memory(address) => write!(f, "M[{address}]"),
+            Expr::Not(e) => write!(f, "!{e}"),
+            Expr::Negate(e) => write!(f, "-{e}"),
+            Expr::Binary(op, lhs, rhs) => {
+                let op = match op {
+                    BinaryOp::Or => "||",
+                    BinaryOp::And => "&&",
+                    BinaryOp::Eq => "==",
+                    BinaryOp::Ne => "!=",
+                    BinaryOp::Lt => "<",
+                    BinaryOp::Le => "<=",
+                    BinaryOp::Gt => ">",
+                    BinaryOp::Ge => ">=",
+                    BinaryOp::Add => "+",
+                    BinaryOp::Sub => "-",
+                };
+                write!(f, "({lhs} {op} {rhs})")
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Number(u16),
+    Ident(String),
+    Op(&'static str),
+}
+
+impl Token {
+    fn text(&self) -> String {
+        match self {
+            Token::Number(n) => format!("{n}"),
+            Token::Ident(name) => name.clone(),
+            Token::Op(op) => op.to_string(),
+        }
+    }
+}
+
+/// Operators, longest first so that `<=` is not read as `<` then `=`.
+const OPERATORS: [&str; 15] = [
+    "==", "!=", "<=", ">=", "&&", "||", "<", ">", "+", "-", "!", "(", ")", "[", "]",
+];
+
+fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = vec![];
+    let mut rest = source.trim_start();
+
+    while !rest.is_empty() {
+        if let Some(op) = OPERATORS.iter().find(|op| rest.starts_with(*op)) {
+            tokens.push(Token::Op(op));
+            rest = &rest[op.len()..];
+        } else {
+            // `#-5` is one number, not `#` followed by `-5`
+            let sign = if rest.starts_with("#-") { 2 } else { 0 };
+            let len = rest[sign..]
+                .find(|c: char| !(c.is_alphanumeric() || c == '_' || c == '#'))
+                .map_or(rest.len(), |len| len + sign);
+            if len == 0 {
+                let c = rest.chars().next().unwrap_or_default();
+                return Err(format!("Unexpected `{c}` in expression"));
+            }
+
+            let word = &rest[..len];
+            tokens.push(match parse_number(word) {
+                Some(n) => Token::Number(n),
+                None if word.starts_with('#') => {
+                    return Err(format!("`{word}` is not a valid number"));
+                }
+                None => Token::Ident(word.to_string()),
+            });
+            rest = &rest[len..];
+        }
+        rest = rest.trim_start();
+    }
+
+    return Ok(tokens);
+}
+
+/// Numbers are written the way the assembler accepts them (`x41`, `#65`), or
+/// as plain decimal.
+fn parse_number(word: &str) -> Option<u16> {
+    if let Some(dec) = word.strip_prefix('#') {
+        return dec.parse::<i32>().ok().and_then(to_word);
+    }
+    if let Some(hex) = word.strip_prefix('x').or(word.strip_prefix('X')) {
+        return u16::from_str_radix(hex, 16).ok();
+    }
+    if word.starts_with(|c: char| c.is_ascii_digit()) {
+        return word.parse::<i32>().ok().and_then(to_word);
+    }
+    return None;
+}
+
+fn to_word(n: i32) -> Option<u16> {
+    if n < i16::MIN as i32 || n > u16::MAX as i32 {
+        return None;
+    }
+    return Some(n as u16);
+}
+
+struct Parser<'a> {
+    tokens: Vec<Token>,
+    pos: usize,
+    symbols: &'a HashMap<String, u16>,
+}
+
+impl<'a> Parser<'a> {
+    fn peek_op(&self) -> Option<&'static str> {
+        match self.tokens.get(self.pos) {
+            Some(Token::Op(op)) => Some(op),
+            _ => None,
+        }
+    }
+
+    fn eat(&mut self, op: &str) -> bool {
+        if self.peek_op() == Some(op) {
+            self.pos += 1;
+            return true;
+        }
+        return false;
+    }
+
+    fn expect(&mut self, op: &str) -> Result<(), String> {
+        if self.eat(op) {
+            return Ok(());
+        }
+        return Err(format!("Expected `{op}` in expression"));
+    }
+
+    fn or(&mut self) -> Result<Expr, String> {
+        let mut lhs = self.and()?;
+        while self.eat("||") {
+            lhs = Expr::Binary(BinaryOp::Or, Box::new(lhs), Box::new(self.and()?));
+        }
+        return Ok(lhs);
+    }
+
+    fn and(&mut self) -> Result<Expr, String> {
+        let mut lhs = self.comparison()?;
+        while self.eat("&&") {
+            lhs = Expr::Binary(BinaryOp::And, Box::new(lhs), Box::new(self.comparison()?));
+        }
+        return Ok(lhs);
+    }
+
+    fn comparison(&mut self) -> Result<Expr, String> {
+        let lhs = self.sum()?;
+        let op = match self.peek_op() {
+            Some("==") => BinaryOp::Eq,
+            Some("!=") => BinaryOp::Ne,
+            Some("<") => BinaryOp::Lt,
+            Some("<=") => BinaryOp::Le,
+            Some(">") => BinaryOp::Gt,
+            Some(">=") => BinaryOp::Ge,
+            _ => return Ok(lhs),
+        };
+        self.pos += 1;
+        return Ok(Expr::Binary(op, Box::new(lhs), Box::new(self.sum()?)));
+    }
+
+    fn sum(&mut self) -> Result<Expr, String> {
+        let mut lhs = self.unary()?;
+        loop {
+            let op = match self.peek_op() {
+                Some("+") => BinaryOp::Add,
+                Some("-") => BinaryOp::Sub,
+                _ => return Ok(lhs),
+            };
+            self.pos += 1;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(self.unary()?));
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        if self.eat("!") {
+            return Ok(Expr::Not(Box::new(self.unary()?)));
+        }
+        if self.eat("-") {
+            return Ok(Expr::Negate(Box::new(self.unary()?)));
+        }
+        return self.primary();
+    }
+
+    fn primary(&mut self) -> Result<Expr, String> {
+        let Some(token) = self.tokens.get(self.pos).cloned() else {
+            return Err("The expression ends early".to_string());
+        };
+        self.pos += 1;
+
+        match token {
+            Token::Number(n) => return Ok(Expr::Number(n)),
+            Token::Op("(") => {
+                let expr = self.or()?;
+                self.expect(")")?;
+                return Ok(expr);
+            }
+            Token::Op(op) => return Err(format!("Unexpected `{op}` in expression")),
+            Token::Ident(name) => {
+                if (name == "M" || name == "m") && self.eat("[") {
+                    let address = self.or()?;
+                    self.expect("]")?;
+                    return Ok(Expr::Memory(Box::new(address)));
+                }
+                return self.identifier(&name);
+            }
+        }
+    }
+
+    fn identifier(&self, name: &str) -> Result<Expr, String> {
+        if let Some(address) = self.symbols.get(name) {
+            return Ok(Expr::Number(*address));
+        }
+
+        let upper = name.to_ascii_uppercase();
+        match upper.as_str() {
+            "PC" => return Ok(Expr::Pc),
+            "IR" => return Ok(Expr::Ir),
+            "PSR" => return Ok(Expr::Psr),
+            "N" => return Ok(Expr::N),
+            "Z" => return Ok(Expr::Z),
+            "P" => return Ok(Expr::P),
+            _ => {}
+        }
+        if let [b'R', digit @ b'0'..=b'7'] = upper.as_bytes() {
+            return Ok(Expr::Register(digit - b'0'));
+        }
+
+        let mut matches = self
+            .symbols
+            .iter()
+            .filter(|(label, _)| label.eq_ignore_ascii_case(name));
+        if let (Some((_, address)), None) = (matches.next(), matches.next()) {
+            return Ok(Expr::Number(*address));
+        }
+
+        return Err(format!("Unknown name `{name}` in expression"));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(source: &str, reg: &Registers, mem: &Memory) -> u16 {
+        let symbols = HashMap::from([("COUNT".to_string(), 0x3010)]);
+        return Expr::parse(source, &symbols).unwrap().eval(reg, mem);
+    }
+
+    #[test]
+    fn test_expression_values() {
+        let mut reg = Registers::new();
+        let mut mem = Memory::new();
+        reg.r[0] = 0x41;
+        reg.r[1] = (-3i16) as u16;
+        reg.pc = 0x3005;
+        reg.set_cc(true, false, false);
+        mem.set(0x3010, 6);
+
+        assert_eq!(eval("R0 == x41 && M[COUNT] > #5", &reg, &mem), 1);
+        assert_eq!(eval("r0 == 65 && m[count] > 6", &reg, &mem), 0);
+        assert_eq!(eval("R1 < #0 && N && !Z", &reg, &mem), 1);
+        assert_eq!(eval("R1 == #-3", &reg, &mem), 1);
+        assert_eq!(eval("M[COUNT + 1 - 1] + -R1", &reg, &mem), 9);
+        assert_eq!(eval("PC - x3000 >= 5 || M[x0000 - 1]", &reg, &mem), 1);
+        assert_eq!(eval("(R0 + 1) == x42", &reg, &mem), 1);
+    }
+
+    #[test]
+    fn test_expression_display() {
+        let symbols = HashMap::from([("COUNT".to_string(), 0x3010)]);
+        let expr = Expr::parse("R0 == x41 && M[COUNT] > #5 || !z", &symbols).unwrap();
+
+        assert_eq!(
+            expr.to_string(),
+            "(((R0 == x0041) && (M[x3010] > x0005)) || !Z)"
+        );
+    }
+
+    #[test]
+    fn test_expression_errors() {
+        let symbols = HashMap::new();
+
+        assert!(Expr::parse("R8 == 1", &symbols).unwrap_err().contains("R8"));
+        assert!(Expr::parse("R0 ==", &symbols).is_err());
+        assert!(Expr::parse("M[R0", &symbols).is_err());
+        assert!(Expr::parse("R0 R1", &symbols).is_err());
+        assert!(Expr::parse("R0 $ 1", &symbols).is_err());
+        assert!(Expr::parse("#abc", &symbols).is_err());
+        assert!(Expr::parse("R0 → 1", &symbols).unwrap_err().contains('→'));
+    }
+}
@@ -0,0 +1,84 @@
+use super::delta::MemoryWrite;
+use super::device::Devices;
+use super::interrupt::InterruptController;
+use super::registers::Registers;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+#[cfg(feature = "serde")]
+use tsify::Tsify;
+
+/// How many steps the debuggers remember.
+pub const DEFAULT_HISTORY_LIMIT: usize = 10_000;
+
+/// What is needed to undo one step: the machine state the step started from
+/// (registers, devices and pending interrupts are small enough to copy), the
+/// memory writes it made, and the input it consumed.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize, Tsify))]
+pub struct HistoryEntry {
+    pub registers: Registers,
+    pub devices: Devices,
+    pub interrupts: InterruptController,
+    pub memory: Vec<MemoryWrite>,
+    pub input: Vec<char>,
+}
+
+/// A bounded undo history. Once it is full, the oldest step is forgotten.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize, Tsify))]
+#[cfg_attr(feature = "serde", tsify(into_wasm_abi, from_wasm_abi))]
+pub struct History {
+    entries: VecDeque<HistoryEntry>,
+    limit: usize,
+}
+
+impl History {
+    pub fn new(limit: usize) -> History {
+        History {
+            entries: VecDeque::new(),
+            limit: limit,
+        }
+    }
+
+    pub fn limit(&self) -> usize {
+        return self.limit;
+    }
+
+    /// Changes how many steps are kept. A limit of 0 turns recording off.
+    pub fn set_limit(&mut self, limit: usize) {
+        self.limit = limit;
+        while self.entries.len() > limit {
+            self.entries.pop_front();
+        }
+    }
+
+    pub fn is_recording(&self) -> bool {
+        return self.limit > 0;
+    }
+
+    pub fn push(&mut self, entry: HistoryEntry) {
+        if !self.is_recording() {
+            return;
+        }
+        if self.entries.len() == self.limit {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(entry);
+    }
+
+    pub fn pop(&mut self) -> Option<HistoryEntry> {
+        return self.entries.pop_back();
+    }
+
+    pub fn len(&self) -> usize {
+        return self.entries.len();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.entries.is_empty();
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
@@ -1,9 +1,11 @@
+use super::decode::{Op, Operand};
+use super::interrupt::{self, pop_stack};
 use super::memory::Memory;
 use super::registers::Registers;
 use super::trap::Trap;
 use crate::asm::asm_ins::{GETC_VAL, HALT_VAL, IN_VAL, OUT_VAL, PUTS_VAL, PUTSP_VAL};
 use crate::io::Lc3IO;
-use crate::vm::vm::InstructionResult;
+use crate::vm::vm::{InstructionResult, OPCODE_DELTA, TrapMode};
 #[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
 #[cfg(feature = "serde")]
@@ -12,7 +14,10 @@ use tsify::Tsify;
 use typetag;
 
 /*
-Uses the command pattern to execute functions dynamically
+Uses the command pattern to execute functions dynamically.
+
+The VM runs decoded `Op`s through `execute` by default. These structs stay as an
+extension point: inserting one into `VM::instructions` replaces that opcode.
 */
 
 #[cfg_attr(feature = "serde", typetag::serde(tag = "type"))]
@@ -63,6 +68,12 @@ pub struct Lea;
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize, Tsify))]
 pub struct Not;
 
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize, Tsify))]
+pub struct OsTrap;
+
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize, Tsify))]
+pub struct Reserved;
+
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize, Tsify))]
 pub struct Rti;
 
@@ -118,41 +129,11 @@ impl Instruction for Add {
         &self,
         value: u16,
         reg: &mut Registers,
-        _mem: &mut Memory,
-        _io: &mut Lc3IO,
+        mem: &mut Memory,
+        io: &mut Lc3IO,
     ) -> InstructionResult {
-        let mut i = value;
-
-        let dr = i >> 9;
-        i -= dr << 9;
-
-        let sr1 = i >> 6;
-        i -= sr1 << 6;
-
-        let new_value: u16;
-        let code = get_bit_index(value, 5);
-
-        match code {
-            0 => {
-                let sr2 = i;
-
-                let v1 = reg.get(sr1 as usize);
-                let v2 = reg.get(sr2 as usize);
-
-                new_value = (v1 as i16 + v2 as i16) as u16;
-            }
-            1 => {
-                let reg_val = reg.get(sr1 as usize);
-                let imm_val = get_offset(value, 5); // TODO: rename get_offset to something more useful, like get_last_bits
-                new_value = (reg_val as i16 + imm_val as i16) as u16;
-            }
-            _ => unreachable!(),
-        }
-
-        reg.set(dr as usize, new_value);
-
-        set_nzp(reg, new_value);
-        return InstructionResult::Ready;
+        let op = Op::decode((1 << OPCODE_DELTA) | value);
+        return execute(op, TrapMode::FastPath, reg, mem, io);
     }
 }
 
@@ -200,46 +181,11 @@ impl Instruction for And {
         &self,
         value: u16,
         reg: &mut Registers,
-        _mem: &mut Memory,
-        _io: &mut Lc3IO,
+        mem: &mut Memory,
+        io: &mut Lc3IO,
     ) -> InstructionResult {
-        let mut i = value;
-
-        let dr = i >> 9;
-        i -= dr << 9;
-
-        let sr1 = i >> 6;
-        let x = sr1 << 6;
-        i -= x;
-
-        let code = get_bit_index(value, 5);
-
-        let new_value: u16;
-
-        match code {
-            0 => {
-                let sr2 = i;
-
-                let v1 = reg.get(sr1 as usize);
-                let v2 = reg.get(sr2 as usize);
-                new_value = v1 & v2;
-                // println!("\nand reg: {} & {} = {}\n", v1, v2, new_value);
-            }
-            1 => {
-                // i -= code >> 5;
-                let reg_val = reg.get(sr1 as usize);
-                let imm_val = get_offset(value, 5);
-                new_value = reg_val & imm_val;
-                // println!("\nand imm: {} & {} = {}\n", reg_val, imm_val, new_value);
-            }
-            _ => {
-                unreachable!();
-            }
-        }
-        reg.set(dr as usize, new_value);
-
-        set_nzp(reg, new_value);
-        return InstructionResult::Ready;
+        let op = Op::decode((5 << OPCODE_DELTA) | value);
+        return execute(op, TrapMode::FastPath, reg, mem, io);
     }
 }
 
@@ -249,30 +195,11 @@ impl Instruction for Br {
         &self,
         value: u16,
         reg: &mut Registers,
-        _mem: &mut Memory,
-        _io: &mut Lc3IO,
+        mem: &mut Memory,
+        io: &mut Lc3IO,
     ) -> InstructionResult {
-        /*
-        BR  - | 0000 000 000000000 |
-              | ---- --- --------- |
-              | op   nzp pcoffset9 |
-        */
-        // print!("IN BR");
-        // print!(" - {:#018b}\n", value);
-        let n = get_bit_index(value, 11);
-        let z = get_bit_index(value, 10);
-        let p = get_bit_index(value, 9);
-
-        if (n == 1 && z == 1 && p == 1)
-            || (n == 1 && reg.n)
-            || (z == 1 && reg.z)
-            || (p == 1 && reg.p)
-        {
-            let pcoffset9 = get_offset(value, 9);
-            let target_location = get_pcoffset_location(&reg, pcoffset9);
-            reg.pc = target_location;
-        }
-        return InstructionResult::Ready;
+        let op = Op::decode((0 << OPCODE_DELTA) | value);
+        return execute(op, TrapMode::FastPath, reg, mem, io);
     }
 }
 
@@ -282,23 +209,11 @@ impl Instruction for JmpRet {
         &self,
         value: u16,
         reg: &mut Registers,
-        _mem: &mut Memory,
-        _io: &mut Lc3IO,
+        mem: &mut Memory,
+        io: &mut Lc3IO,
     ) -> InstructionResult {
-        /*
-        JMP - | 1100 000 000 000000 |
-              | ---- --- --- ------ |
-              | op       baser      |
-              +---------------------+
-        RET - | 1100 000 111 000000 |
-              | ---- --- --- ------ |
-              | op       r7         |
-        */
-        let base_reg = value >> 6;
-
-        reg.pc = reg.get(base_reg as usize);
-
-        return InstructionResult::Ready;
+        let op = Op::decode((12 << OPCODE_DELTA) | value);
+        return execute(op, TrapMode::FastPath, reg, mem, io);
     }
 }
 
@@ -308,40 +223,11 @@ impl Instruction for Jsr {
         &self,
         value: u16,
         reg: &mut Registers,
-        _mem: &mut Memory,
-        _io: &mut Lc3IO,
+        mem: &mut Memory,
+        io: &mut Lc3IO,
     ) -> InstructionResult {
-        /*
-        JSR - | 0100 1 00000000000   |
-              | ---- - -----------   |
-              | op   c pcoffset11    |
-              +----------------------+
-        JSRR- | 0100 0 00 000 000000 |
-              | ---- - -- --- ------ |
-              | op   c -- br  ------ |
-        */
-        // let code = get_bit_index(value, 12);
-        let code = value >> 11;
-        let inc_pc = reg.pc;
-        let offset: u16;
-
-        match code {
-            0 => {
-                let offset_reg = value >> 6;
-                offset = reg.r[offset_reg as usize];
-            }
-            1 => {
-                offset = get_offset(value, 11);
-            }
-            _ => unreachable!(),
-        }
-
-        let target_location = get_pcoffset_location(&reg, offset);
-        reg.pc = target_location;
-
-        // link back to the instruction after Jsr by putting PC in R7
-        reg.r[7] = inc_pc;
-        return InstructionResult::Ready;
+        let op = Op::decode((4 << OPCODE_DELTA) | value);
+        return execute(op, TrapMode::FastPath, reg, mem, io);
     }
 }
 
@@ -352,21 +238,10 @@ impl Instruction for Ld {
         value: u16,
         reg: &mut Registers,
         mem: &mut Memory,
-        _io: &mut Lc3IO,
+        io: &mut Lc3IO,
     ) -> InstructionResult {
-        /*
-        LD  - | 0010 000 000000000 |
-              | ---- --- --------- |
-              | op   dr  pcoffset9 |
-        */
-        let dr = value >> 9;
-        let pcoffset9 = get_offset(value, 9);
-        let relative_pc_address = get_pcoffset_location(reg, pcoffset9);
-
-        let new_value = mem.get(relative_pc_address);
-        set_nzp(reg, new_value);
-        reg.set(dr as usize, new_value);
-        return InstructionResult::Ready;
+        let op = Op::decode((2 << OPCODE_DELTA) | value);
+        return execute(op, TrapMode::FastPath, reg, mem, io);
     }
 }
 
@@ -377,22 +252,10 @@ impl Instruction for Ldi {
         value: u16,
         reg: &mut Registers,
         mem: &mut Memory,
-        _io: &mut Lc3IO,
+        io: &mut Lc3IO,
     ) -> InstructionResult {
-        /*
-        LDI - | 1010 000 000000000 |
-              | ---- --- --------- |
-              | op   dr  pcoffset9 |
-        */
-        let dr = value >> 9;
-        let pcoffset9 = get_offset(value, 9);
-        let relative_pc_address = get_pcoffset_location(reg, pcoffset9);
-
-        let ptr = mem.get(relative_pc_address);
-        let new_value = mem.get(ptr);
-        set_nzp(reg, new_value);
-        reg.set(dr as usize, new_value);
-        return InstructionResult::Ready;
+        let op = Op::decode((10 << OPCODE_DELTA) | value);
+        return execute(op, TrapMode::FastPath, reg, mem, io);
     }
 }
 
@@ -403,27 +266,10 @@ impl Instruction for Ldr {
         value: u16,
         reg: &mut Registers,
         mem: &mut Memory,
-        _io: &mut Lc3IO,
+        io: &mut Lc3IO,
     ) -> InstructionResult {
-        /*
-        LDR - | 0110 000 000 000000 |
-              | ---- --- --- ------ |
-              | op   dr  br  offset6|
-        */
-        let mut buffer = value;
-        let dr = buffer >> 9;
-        buffer -= dr << 9;
-        let base_r = buffer >> 6;
-
-        let offset = get_offset(value, 6);
-        let address = reg.get(base_r as usize);
-
-        let target_location = calculate_relative_offset(address, offset);
-
-        let new_value = mem.get(target_location);
-        set_nzp(reg, new_value);
-        reg.set(dr as usize, new_value);
-        return InstructionResult::Ready;
+        let op = Op::decode((6 << OPCODE_DELTA) | value);
+        return execute(op, TrapMode::FastPath, reg, mem, io);
     }
 }
 
@@ -434,23 +280,11 @@ impl Instruction for Lea {
         &self,
         value: u16,
         reg: &mut Registers,
-        _mem: &mut Memory,
-        _io: &mut Lc3IO,
+        mem: &mut Memory,
+        io: &mut Lc3IO,
     ) -> InstructionResult {
-        /*
-        LEA - | 1110 000 000000000 |
-              | ---- --- --------- |
-              | op   dr  label     |
-
-        Loads memory location of the label into memory
-        */
-        let dr = value >> 9;
-        let ptr = get_offset(value, 9);
-
-        let address = get_pcoffset_location(reg, ptr);
-        set_nzp(reg, address);
-        reg.set(dr as usize, address);
-        return InstructionResult::Ready;
+        let op = Op::decode((14 << OPCODE_DELTA) | value);
+        return execute(op, TrapMode::FastPath, reg, mem, io);
     }
 }
 
@@ -460,45 +294,59 @@ impl Instruction for Not {
         &self,
         value: u16,
         reg: &mut Registers,
-        _mem: &mut Memory,
-        _io: &mut Lc3IO,
+        mem: &mut Memory,
+        io: &mut Lc3IO,
     ) -> InstructionResult {
-        /*
-        NOT - | 1001 000 000 111111 |
-              | ---- --- --- ------ |
-              | op   dr  sr         |
-        */
-        let mut i = value;
-        let dr = i >> 9;
-        i -= dr << 9;
-        let sr = i >> 6;
-
-        let old_val = reg.get(sr as usize);
-        let not_val = !old_val;
-
-        reg.set(dr as usize, not_val);
-
-        set_nzp(reg, not_val);
-        return InstructionResult::Ready;
+        let op = Op::decode((9 << OPCODE_DELTA) | value);
+        return execute(op, TrapMode::FastPath, reg, mem, io);
+    }
+}
+
+#[cfg_attr(feature = "serde", typetag::serde)]
+impl Instruction for Reserved {
+    /// Opcode 13 is reserved by the ISA. Executing it raises an illegal opcode exception.
+    /// ```text
+    ///        --- - | 1101 000000000000 |
+    ///              | ---- ------------ |
+    ///              | op                |
+    /// ```
+    /// -------------------------------------------------------------------------------
+    fn exe(
+        &self,
+        value: u16,
+        reg: &mut Registers,
+        mem: &mut Memory,
+        io: &mut Lc3IO,
+    ) -> InstructionResult {
+        let op = Op::decode((13 << OPCODE_DELTA) | value);
+        return execute(op, TrapMode::FastPath, reg, mem, io);
     }
 }
 
-// TODO: Impl rti
 #[cfg_attr(feature = "serde", typetag::serde)]
 impl Instruction for Rti {
+    /// Returns from an interrupt or exception service routine. The PC and then
+    /// the PSR are popped off of the supervisor stack (pointed to by R6). If the
+    /// restored PSR puts the machine back into user mode, the supervisor stack
+    /// pointer is stashed in `saved_ssp`, and R6 is restored from `saved_usp`.
+    ///
+    /// RTI is a privileged instruction. If it is executed in user mode, a
+    /// privilege mode exception is raised instead.
+    /// ```text
+    ///        RTI - | 1000 000000000000 |
+    ///              | ---- ------------ |
+    ///              | op                |
+    /// ```
+    /// -------------------------------------------------------------------------------
     fn exe(
         &self,
-        _value: u16,
-        _reg: &mut Registers,
-        _mem: &mut Memory,
-        _io: &mut Lc3IO,
+        value: u16,
+        reg: &mut Registers,
+        mem: &mut Memory,
+        io: &mut Lc3IO,
     ) -> InstructionResult {
-        /*
-        RTI - | 1000 000000000000 |
-              | ---- ------------ |
-              | op                |
-        */
-        todo!();
+        let op = Op::decode((8 << OPCODE_DELTA) | value);
+        return execute(op, TrapMode::FastPath, reg, mem, io);
     }
 }
 
@@ -509,19 +357,10 @@ impl Instruction for St {
         value: u16,
         reg: &mut Registers,
         mem: &mut Memory,
-        _io: &mut Lc3IO,
+        io: &mut Lc3IO,
     ) -> InstructionResult {
-        /*
-        ST  - | 0011 000 000000000 |
-              | ---- --- --------- |
-              | op   sr  pcoffset9 |
-        */
-        let sr = value >> 9;
-        let pcoffset9 = get_offset(value, 9);
-        let location = get_pcoffset_location(&reg, pcoffset9);
-
-        mem.set(location, reg.get(sr as usize));
-        return InstructionResult::Ready;
+        let op = Op::decode((3 << OPCODE_DELTA) | value);
+        return execute(op, TrapMode::FastPath, reg, mem, io);
     }
 }
 
@@ -532,20 +371,10 @@ impl Instruction for Sti {
         value: u16,
         reg: &mut Registers,
         mem: &mut Memory,
-        _io: &mut Lc3IO,
+        io: &mut Lc3IO,
     ) -> InstructionResult {
-        /*
-        STI - | 1011 000 000000000 |
-              | ---- --- --------- |
-              | op   sr  pcoffset9 |
-        */
-        let sr = value >> 9;
-        let pcoffset9 = get_offset(value, 9);
-        let location = get_pcoffset_location(&reg, pcoffset9);
-        let indirect = mem.get(location);
-
-        mem.set(indirect, reg.get(sr as usize));
-        return InstructionResult::Ready;
+        let op = Op::decode((11 << OPCODE_DELTA) | value);
+        return execute(op, TrapMode::FastPath, reg, mem, io);
     }
 }
 
@@ -556,24 +385,10 @@ impl Instruction for Str {
         value: u16,
         reg: &mut Registers,
         mem: &mut Memory,
-        _io: &mut Lc3IO,
+        io: &mut Lc3IO,
     ) -> InstructionResult {
-        /*
-        STR - | 0111 000 000 000000  |
-              | ---- --- --- ------  |
-              | op   sr      offset6 |
-                         base_r
-        */
-        let mut buffer = value;
-        let sr = buffer >> 9;
-        buffer -= sr << 9;
-        let base_r = buffer >> 6;
-        let offset6 = get_offset(buffer, 6);
-
-        let relative_offeset = calculate_relative_offset(reg.get(base_r as usize), offset6);
-
-        mem.set(relative_offeset, reg.get(sr as usize));
-        return InstructionResult::Ready;
+        let op = Op::decode((7 << OPCODE_DELTA) | value);
+        return execute(op, TrapMode::FastPath, reg, mem, io);
     }
 }
 
@@ -586,93 +401,217 @@ impl Instruction for Trap {
         mem: &mut Memory,
         io: &mut Lc3IO,
     ) -> InstructionResult {
-        /*
-        TRAP - | 1111 0000 00000000 |
-               | ---- ---- -------- |
-               | op        trapvec8 |
-        */
-        reg.set(7, reg.pc);
-        let code = get_offset(value, 8);
-
-        return match code {
-            GETC_VAL => self.get_c(reg, io),
-            OUT_VAL => self.out(reg, io),
-            PUTS_VAL => self.put_s(reg, mem, io),
-            IN_VAL => self.r#in(reg, mem, io),
-            PUTSP_VAL => self.put_sp(reg, mem, io),
-            HALT_VAL => self.halt(reg),
-            _ => unreachable!(),
-        };
+        let op = Op::decode((15 << OPCODE_DELTA) | value);
+        return execute(op, TrapMode::FastPath, reg, mem, io);
     }
 }
 
-fn get_offset(mut value: u16, num_bits: i32) -> u16 {
-    /*
-    Every number passed here is a 2's complement signed integer.
-    Therefore, we need to check if the right-most bit is a `1`.
-    if true, exend entire number with ones.
-    */
-    let mut pos: u32 = 1;
-    let mut buf: u16 = 0;
-    let mut bit = 0;
-
-    for _ in 0..num_bits {
-        bit = (value % 2) * pos as u16;
-        buf += bit;
-        pos *= 2;
-        value = value >> 1;
+/// TRAP as the hardware does it. Every vector, including the standard ones, is
+/// dispatched through the trap vector table to a service routine in the OS.
+#[cfg_attr(feature = "serde", typetag::serde)]
+impl Instruction for OsTrap {
+    fn exe(
+        &self,
+        value: u16,
+        reg: &mut Registers,
+        mem: &mut Memory,
+        io: &mut Lc3IO,
+    ) -> InstructionResult {
+        let op = Op::decode((15 << OPCODE_DELTA) | value);
+        return execute(op, TrapMode::Os, reg, mem, io);
     }
+}
+
+/// Executes a decoded instruction. This is the VM's fast path, and the
+/// `Instruction` impls above decode their word and call into it as well.
+pub fn execute(
+    op: Op,
+    trap_mode: TrapMode,
+    reg: &mut Registers,
+    mem: &mut Memory,
+    io: &mut Lc3IO,
+) -> InstructionResult {
+    match op {
+        Op::Br { n, z, p, offset } => {
+            if (n && z && p) || (n && reg.n()) || (z && reg.z()) || (p && reg.p()) {
+                reg.pc = reg.pc.wrapping_add(offset);
+            }
+        }
+        Op::Add { dr, sr1, src2 } => {
+            let value = reg.r[sr1 as usize].wrapping_add(operand_value(reg, src2));
+            set_register(reg, dr, value);
+        }
+        Op::And { dr, sr1, src2 } => {
+            let value = reg.r[sr1 as usize] & operand_value(reg, src2);
+            set_register(reg, dr, value);
+        }
+        Op::Not { dr, sr } => {
+            let value = !reg.r[sr as usize];
+            set_register(reg, dr, value);
+        }
+        Op::Lea { dr, offset } => {
+            let address = reg.pc.wrapping_add(offset);
+            set_register(reg, dr, address);
+        }
+        Op::Ld { dr, offset } => {
+            let address = reg.pc.wrapping_add(offset);
+            if is_access_violation(reg, address) {
+                return InstructionResult::AccessControlViolation;
+            }
+            let value = mem.read(address, io);
+            set_register(reg, dr, value);
+        }
+        Op::Ldi { dr, offset } => {
+            let address = reg.pc.wrapping_add(offset);
+            if is_access_violation(reg, address) {
+                return InstructionResult::AccessControlViolation;
+            }
+            let ptr = mem.read(address, io);
+            if is_access_violation(reg, ptr) {
+                return InstructionResult::AccessControlViolation;
+            }
+            let value = mem.read(ptr, io);
+            set_register(reg, dr, value);
+        }
+        Op::Ldr { dr, base, offset } => {
+            let address = reg.r[base as usize].wrapping_add(offset);
+            if is_access_violation(reg, address) {
+                return InstructionResult::AccessControlViolation;
+            }
+            let value = mem.read(address, io);
+            set_register(reg, dr, value);
+        }
+        Op::St { sr, offset } => {
+            let address = reg.pc.wrapping_add(offset);
+            if is_access_violation(reg, address) {
+                return InstructionResult::AccessControlViolation;
+            }
+            mem.write(address, reg.r[sr as usize], io);
+        }
+        Op::Sti { sr, offset } => {
+            let address = reg.pc.wrapping_add(offset);
+            if is_access_violation(reg, address) {
+                return InstructionResult::AccessControlViolation;
+            }
+            let ptr = mem.read(address, io);
+            if is_access_violation(reg, ptr) {
+                return InstructionResult::AccessControlViolation;
+            }
+            mem.write(ptr, reg.r[sr as usize], io);
+        }
+        Op::Str { sr, base, offset } => {
+            let address = reg.r[base as usize].wrapping_add(offset);
+            if is_access_violation(reg, address) {
+                return InstructionResult::AccessControlViolation;
+            }
+            mem.write(address, reg.r[sr as usize], io);
+        }
+        Op::Jmp { base } => reg.pc = reg.r[base as usize],
+        Op::Jsr { offset } => {
+            // link back to the instruction after JSR by putting PC in R7
+            reg.r[7] = reg.pc;
+            reg.pc = reg.pc.wrapping_add(offset);
+        }
+        Op::Jsrr { base } => {
+            // read the base register first, since it may be R7
+            let target = reg.r[base as usize];
+            reg.r[7] = reg.pc;
+            reg.pc = target;
+        }
+        Op::Rti => {
+            if reg.is_user_mode() {
+                return InstructionResult::PrivilegeModeException;
+            }
 
-    // value is negative if the last bit was not zero
-    if bit > 1 {
-        let remaining_bits = 16 - num_bits;
+            reg.pc = pop_stack(reg, mem);
+            let psr = pop_stack(reg, mem);
+            reg.psr = psr;
 
-        for _ in 0..remaining_bits {
-            buf += pos as u16;
-            pos *= 2;
+            if reg.is_user_mode() {
+                reg.saved_ssp = reg.get(6);
+                reg.set(6, reg.saved_usp);
+            }
         }
+        Op::Reserved => return InstructionResult::IllegalOpcodeException,
+        Op::Trap { vector } => match trap_mode {
+            TrapMode::Os => return trap_through_table(vector, reg, mem, io),
+            TrapMode::FastPath => return fast_trap(vector, reg, mem, io),
+        },
     }
-
-    return buf;
+    return InstructionResult::Ready;
 }
 
-fn get_bit_index(value: u16, index: i32) -> u16 {
-    return value >> index & 1;
+/// Services GETC, OUT, PUTS, IN, PUTSP and HALT as host Rust code. Any other
+/// vector is dispatched through the trap vector table, so the service routine
+/// runs in supervisor mode and ends with RTI.
+fn fast_trap(
+    vector: u16,
+    reg: &mut Registers,
+    mem: &mut Memory,
+    io: &mut Lc3IO,
+) -> InstructionResult {
+    let trap = Trap {};
+
+    match vector {
+        GETC_VAL | OUT_VAL | PUTS_VAL | IN_VAL | PUTSP_VAL | HALT_VAL => {}
+        _ => return trap_through_table(vector, reg, mem, io),
+    }
+
+    reg.set(7, reg.pc);
+
+    return match vector {
+        GETC_VAL => trap.get_c(reg, io),
+        OUT_VAL => trap.out(reg, io),
+        PUTS_VAL => trap.put_s(reg, mem, io),
+        IN_VAL => trap.r#in(reg, mem, io),
+        PUTSP_VAL => trap.put_sp(reg, mem, io),
+        HALT_VAL => trap.halt(mem),
+        _ => unreachable!(),
+    };
 }
 
-fn set_nzp(reg: &mut Registers, value: u16) {
-    reg.n = false;
-    reg.z = false;
-    reg.p = false;
+/// Enters the service routine for `vector` from the trap vector table. If the
+/// table has no routine for it, the error is reported and the machine halts.
+fn trap_through_table(
+    vector: u16,
+    reg: &mut Registers,
+    mem: &mut Memory,
+    io: &mut Lc3IO,
+) -> InstructionResult {
+    if mem.get(vector) == 0 {
+        io.print_error(format!(
+            "Unknown Trap Vector: No service routine is installed for trap x{:02X} at {:#06x}",
+            vector,
+            reg.pc.wrapping_sub(1)
+        ));
+        mem.devices.stop_clock();
+        return InstructionResult::RuntimePanic;
+    }
 
-    let signed = value as i16;
+    interrupt::initiate(reg, mem, vector, None);
+    return InstructionResult::Ready;
+}
 
-    if signed < 0 {
-        reg.n = true;
-    }
-    if signed == 0 {
-        reg.z = true;
-    }
-    if signed > 0 {
-        reg.p = true;
+fn operand_value(reg: &Registers, operand: Operand) -> u16 {
+    match operand {
+        Operand::Reg(sr2) => reg.r[sr2 as usize],
+        Operand::Imm(imm) => imm,
     }
 }
 
-fn calculate_relative_offset(relative_location: u16, offset: u16) -> u16 {
-    let value = relative_location as i16 + offset as i16;
-    return value as u16;
+fn set_register(reg: &mut Registers, dr: u8, value: u16) {
+    reg.r[dr as usize] = value;
+    set_nzp(reg, value);
 }
 
-fn get_pcoffset_location(reg: &Registers, value: u16) -> u16 {
-    if value as i16 >= 0 {
-        return reg.pc + value;
-    }
-    // if Rust ignored unused carried "overflow" bits, this wouldn't be necessary.
-    // but it doesn't, so it is.
-    let signed_value = value as i16;
-    let negated_value = !signed_value + 1;
+fn set_nzp(reg: &mut Registers, value: u16) {
+    let signed = value as i16;
+    reg.set_cc(signed < 0, signed == 0, signed > 0);
+}
 
-    return reg.pc - negated_value as u16;
+/// User mode may not touch system space or the device registers.
+fn is_access_violation(reg: &Registers, location: u16) -> bool {
+    return reg.is_user_mode() && Memory::is_privileged(location);
 }
 
 #[cfg(test)]
@@ -701,27 +640,27 @@ mod test {
         assert!(reg.get(2) == 11);
         // TODO: Account for NZP bits
 
-        assert!(reg.n == false);
-        assert!(reg.z == false);
-        assert!(reg.p == true);
+        assert!(reg.n() == false);
+        assert!(reg.z() == false);
+        assert!(reg.p() == true);
 
         let ins: u16 = 0b0000_010_001_1_11000; // -8
         add.exe(ins, &mut reg, &mut mem, &mut io);
 
         assert!(reg.get(2) == 0);
 
-        assert!(reg.n == false);
-        assert!(reg.z == true);
-        assert!(reg.p == false);
+        assert!(reg.n() == false);
+        assert!(reg.z() == true);
+        assert!(reg.p() == false);
 
         let ins: u16 = 0b0000_010_000_1_11000; // R0 with -8
         add.exe(ins, &mut reg, &mut mem, &mut io);
 
         assert!(reg.get(2) as i16 == -6);
 
-        assert!(reg.n == true);
-        assert!(reg.z == false);
-        assert!(reg.p == false);
+        assert!(reg.n() == true);
+        assert!(reg.z() == false);
+        assert!(reg.p() == false);
     }
 
     #[test]
@@ -739,9 +678,9 @@ mod test {
 
         assert!(reg.get(2) == 1);
 
-        assert!(reg.n == false);
-        assert!(reg.z == false);
-        assert!(reg.p == true);
+        assert!(reg.n() == false);
+        assert!(reg.z() == false);
+        assert!(reg.p() == true);
 
         ins = 0b0000_010_001_1_11001;
         and.exe(ins, &mut reg, &mut mem, &mut io);
@@ -751,9 +690,9 @@ mod test {
         ins = 0b0000_010_001_1_00000;
         reg.set(1, 13048);
         and.exe(ins, &mut reg, &mut mem, &mut io);
-        assert!(reg.n == false);
-        assert!(reg.z == true);
-        assert!(reg.p == false);
+        assert!(reg.n() == false);
+        assert!(reg.z() == true);
+        assert!(reg.p() == false);
         assert!(reg.get(2) == 0);
 
         let val1 = 0b0010_0110_1111_0101;
@@ -843,28 +782,28 @@ mod test {
         mem.set(2999, val as u16);
 
         assert!(reg.get(1) != val as u16);
-        assert!(reg.n != true);
+        assert!(reg.n() != true);
         ld.exe(ins, &mut reg, &mut mem, &mut io);
         assert!(reg.get(1) == val as u16);
-        assert!(reg.n == true);
+        assert!(reg.n() == true);
 
         let val: i16 = 32;
         mem.set(2999, val as u16);
 
         assert!(reg.get(1) != val as u16);
-        assert!(reg.p != true);
+        assert!(reg.p() != true);
         ld.exe(ins, &mut reg, &mut mem, &mut io);
         assert!(reg.get(1) == val as u16);
-        assert!(reg.p == true);
+        assert!(reg.p() == true);
 
         let val: i16 = 0;
         mem.set(2999, val as u16);
 
         assert!(reg.get(1) != val as u16);
-        assert!(reg.z != true);
+        assert!(reg.z() != true);
         ld.exe(ins, &mut reg, &mut mem, &mut io);
         assert!(reg.get(1) == val as u16);
-        assert!(reg.z == true);
+        assert!(reg.z() == true);
     }
 
     #[test]
@@ -883,28 +822,28 @@ mod test {
         mem.set(ptr, val as u16);
 
         assert!(reg.get(1) != val as u16);
-        assert!(reg.n != true);
+        assert!(reg.n() != true);
         ldi.exe(ins, &mut reg, &mut mem, &mut io);
         assert!(reg.get(1) == val as u16);
-        assert!(reg.n == true);
+        assert!(reg.n() == true);
 
         let val: i16 = 32;
         mem.set(ptr, val as u16);
 
         assert!(reg.get(1) != val as u16);
-        assert!(reg.p != true);
+        assert!(reg.p() != true);
         ldi.exe(ins, &mut reg, &mut mem, &mut io);
         assert!(reg.get(1) == val as u16);
-        assert!(reg.p == true);
+        assert!(reg.p() == true);
 
         let val: i16 = 0;
         mem.set(ptr, val as u16);
 
         assert!(reg.get(1) != val as u16);
-        assert!(reg.z != true);
+        assert!(reg.z() != true);
         ldi.exe(ins, &mut reg, &mut mem, &mut io);
         assert!(reg.get(1) == val as u16);
-        assert!(reg.z == true);
+        assert!(reg.z() == true);
     }
 
     #[test]
@@ -922,28 +861,28 @@ mod test {
         mem.set(location, val as u16);
 
         assert!(reg.get(1) != val as u16);
-        assert!(reg.n != true);
+        assert!(reg.n() != true);
         ldr.exe(ins, &mut reg, &mut mem, &mut io);
         assert!(reg.get(1) == val as u16);
-        assert!(reg.n == true);
+        assert!(reg.n() == true);
 
         let val: i16 = 32;
         mem.set(location, val as u16);
 
         assert!(reg.get(1) != val as u16);
-        assert!(reg.p != true);
+        assert!(reg.p() != true);
         ldr.exe(ins, &mut reg, &mut mem, &mut io);
         assert!(reg.get(1) == val as u16);
-        assert!(reg.p == true);
+        assert!(reg.p() == true);
 
         let val: i16 = 0;
         mem.set(location, val as u16);
 
         assert!(reg.get(1) != val as u16);
-        assert!(reg.z != true);
+        assert!(reg.z() != true);
         ldr.exe(ins, &mut reg, &mut mem, &mut io);
         assert!(reg.get(1) == val as u16);
-        assert!(reg.z == true);
+        assert!(reg.z() == true);
     }
 
     #[test]
@@ -957,18 +896,18 @@ mod test {
         let ins: u16 = 0b0000_001_111111111;
 
         assert!(reg.get(1) != 2999);
-        assert!(reg.p != true);
+        assert!(reg.p() != true);
         lea.exe(ins, &mut reg, &mut mem, &mut io);
         assert!(reg.get(1) == 2999);
-        assert!(reg.p == true);
+        assert!(reg.p() == true);
 
         reg.pc = 1;
 
         assert!(reg.get(1) != 0);
-        assert!(reg.z != true);
+        assert!(reg.z() != true);
         lea.exe(ins, &mut reg, &mut mem, &mut io);
         assert!(reg.get(1) == 0);
-        assert!(reg.z == true);
+        assert!(reg.z() == true);
     }
 
     #[test]
@@ -986,9 +925,9 @@ mod test {
         assert!(reg.get(0) != reg.get(1));
         assert!(reg.get(0) == !reg.get(1));
 
-        assert!(reg.n == true);
-        assert!(reg.z == false);
-        assert!(reg.p == false);
+        assert!(reg.n() == true);
+        assert!(reg.z() == false);
+        assert!(reg.p() == false);
 
         reg.set(1, 0b0000_1111_0101_1010);
 
@@ -998,9 +937,9 @@ mod test {
         assert!(reg.get(0) != reg.get(1));
         assert!(reg.get(0) == !reg.get(1));
 
-        assert!(reg.n == true);
-        assert!(reg.z == false);
-        assert!(reg.p == false);
+        assert!(reg.n() == true);
+        assert!(reg.z() == false);
+        assert!(reg.p() == false);
 
         reg.set(1, 0b1101_1011_1111_1110);
         not.exe(ins, &mut reg, &mut mem, &mut io);
@@ -1008,9 +947,9 @@ mod test {
         assert!(reg.get(0) != reg.get(1));
         assert!(reg.get(0) == !reg.get(1));
 
-        assert!(reg.n == false);
-        assert!(reg.z == false);
-        assert!(reg.p == true);
+        assert!(reg.n() == false);
+        assert!(reg.z() == false);
+        assert!(reg.p() == true);
 
         reg.set(1, 0b1111_1111_1111_1111);
         not.exe(ins, &mut reg, &mut mem, &mut io);
@@ -1018,15 +957,60 @@ mod test {
         assert!(reg.get(0) != reg.get(1));
         assert!(reg.get(0) == !reg.get(1));
 
-        assert!(reg.n == false);
-        assert!(reg.z == true);
-        assert!(reg.p == false);
+        assert!(reg.n() == false);
+        assert!(reg.z() == true);
+        assert!(reg.p() == false);
     }
 
-    // #[test]
-    // fn test_rti() {
-    //     // unimplemented!(); going to implement in later version.
-    // }
+    #[test]
+    fn test_rti() {
+        let mut io = super::Lc3IO::new(Box::new(DebugIO::new()));
+        let mut mem = super::Memory::new();
+        let mut reg = super::Registers::new();
+        let rti = super::Rti {};
+
+        reg.set(6, 0x2FFE);
+        reg.saved_usp = 0xFDF0;
+        mem.set(0x2FFE, 0x3042); // PC
+        mem.set(0x2FFF, 0x8001); // PSR: user mode, p
+
+        rti.exe(0, &mut reg, &mut mem, &mut io);
+
+        assert_eq!(reg.pc, 0x3042);
+        assert_eq!(reg.psr, 0x8001);
+        assert!(reg.is_user_mode());
+        assert!(reg.p());
+        assert_eq!(reg.get(6), 0xFDF0);
+        assert_eq!(reg.saved_ssp, 0x3000);
+
+        // returning to supervisor mode leaves R6 on the supervisor stack
+        reg.psr = 0;
+        reg.set(6, 0x2FFE);
+        mem.set(0x2FFF, 0x0004);
+        rti.exe(0, &mut reg, &mut mem, &mut io);
+
+        assert!(!reg.is_user_mode());
+        assert!(reg.n());
+        assert_eq!(reg.get(6), 0x3000);
+    }
+
+    #[test]
+    fn test_rti_in_user_mode() {
+        let mut io = super::Lc3IO::new(Box::new(DebugIO::new()));
+        let mut mem = super::Memory::new();
+        let mut reg = super::Registers::new();
+        let rti = super::Rti {};
+
+        reg.psr = 0x8002;
+        reg.pc = 0x3001;
+        reg.set(6, 0x4000);
+
+        let result = rti.exe(0, &mut reg, &mut mem, &mut io);
+
+        assert!(matches!(result, InstructionResult::PrivilegeModeException));
+        assert_eq!(reg.pc, 0x3001);
+        assert_eq!(reg.get(6), 0x4000);
+    }
 
     #[test]
     fn test_st() {
@@ -1123,19 +1107,19 @@ mod test {
         let mut reg = Registers::new();
 
         set_nzp(&mut reg, u16::MAX);
-        assert!(reg.n);
-        assert!(!reg.z);
-        assert!(!reg.p);
+        assert!(reg.n());
+        assert!(!reg.z());
+        assert!(!reg.p());
 
         set_nzp(&mut reg, 0);
-        assert!(!reg.n);
-        assert!(reg.z);
-        assert!(!reg.p);
+        assert!(!reg.n());
+        assert!(reg.z());
+        assert!(!reg.p());
 
         set_nzp(&mut reg, 1);
-        assert!(!reg.n);
-        assert!(!reg.z);
-        assert!(reg.p);
+        assert!(!reg.n());
+        assert!(!reg.z());
+        assert!(reg.p());
     }
 
     #[test]
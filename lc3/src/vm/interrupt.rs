@@ -0,0 +1,193 @@
+use super::memory::Memory;
+use super::registers::Registers;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "serde")]
+use tsify::Tsify;
+
+/// Base of the interrupt vector table (x0100-x01FF). The address of the service
+/// routine for vector `v` is stored at `INTERRUPT_VECTOR_TABLE + v`.
+pub const INTERRUPT_VECTOR_TABLE: u16 = 0x0100;
+
+/// Exception vectors, which share the interrupt vector table.
+pub const PRIVILEGE_MODE_VECTOR: u8 = 0x00;
+pub const ILLEGAL_OPCODE_VECTOR: u8 = 0x01;
+pub const ACCESS_CONTROL_VECTOR: u8 = 0x02;
+
+pub const KEYBOARD_VECTOR: u8 = 0x80;
+pub const KEYBOARD_PRIORITY: u8 = 4;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize, Tsify))]
+#[cfg_attr(feature = "serde", tsify(into_wasm_abi, from_wasm_abi))]
+pub struct InterruptRequest {
+    pub vector: u8,
+    pub priority: u8,
+}
+
+impl InterruptRequest {
+    pub fn new(vector: u8, priority: u8) -> InterruptRequest {
+        InterruptRequest {
+            vector: vector,
+            priority: priority & 0b111,
+        }
+    }
+
+    pub fn keyboard() -> InterruptRequest {
+        InterruptRequest::new(KEYBOARD_VECTOR, KEYBOARD_PRIORITY)
+    }
+}
+
+/// Holds interrupt requests raised by devices until the processor is running
+/// at a low enough priority to service them.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize, Tsify))]
+#[cfg_attr(feature = "serde", tsify(into_wasm_abi, from_wasm_abi))]
+pub struct InterruptController {
+    pending: Vec<InterruptRequest>,
+}
+
+impl InterruptController {
+    pub fn new() -> InterruptController {
+        InterruptController { pending: vec![] }
+    }
+
+    /// Latches an interrupt request. It stays pending until it is serviced.
+    pub fn raise(&mut self, vector: u8, priority: u8) {
+        self.pending.push(InterruptRequest::new(vector, priority));
+    }
+
+    pub fn pending(&self) -> &[InterruptRequest] {
+        return &self.pending;
+    }
+
+    pub fn clear(&mut self) {
+        self.pending.clear();
+    }
+
+    /// Picks the highest priority request that can preempt a program running at
+    /// `current_priority`. `level` is a request from a level-sensitive device (such
+    /// as the keyboard), which is not latched, so it is never removed.
+    pub fn take_next(
+        &mut self,
+        current_priority: u8,
+        level: Option<InterruptRequest>,
+    ) -> Option<InterruptRequest> {
+        let mut best: Option<(Option<usize>, InterruptRequest)> = None;
+
+        for (i, request) in self.pending.iter().enumerate() {
+            if best.is_none_or(|(_, b)| request.priority > b.priority) {
+                best = Some((Some(i), *request));
+            }
+        }
+
+        if let Some(request) = level {
+            if best.is_none_or(|(_, b)| request.priority > b.priority) {
+                best = Some((None, request));
+            }
+        }
+
+        let (index, request) = best?;
+        if request.priority <= current_priority {
+            return None;
+        }
+        if let Some(i) = index {
+            self.pending.remove(i);
+        }
+        return Some(request);
+    }
+}
+
+/// Enters a service routine the way the LC-3 does for traps, interrupts and
+/// exceptions. If the machine is in user mode, R6 is swapped onto the supervisor
+/// stack. The PSR and then the PC are pushed, the machine enters supervisor mode
+/// (at `priority`, if given), and the PC is loaded from `vector_address`.
+pub fn initiate(reg: &mut Registers, mem: &mut Memory, vector_address: u16, priority: Option<u8>) {
+    let psr = reg.psr;
+
+    if reg.is_user_mode() {
+        reg.saved_usp = reg.get(6);
+        reg.set(6, reg.saved_ssp);
+    }
+
+    push_stack(reg, mem, psr);
+    push_stack(reg, mem, reg.pc);
+
+    reg.set_user_mode(false);
+    if let Some(priority) = priority {
+        reg.set_priority(priority);
+    }
+
+    reg.pc = mem.get(vector_address);
+}
+
+/// The address in the interrupt vector table that holds the service routine for `vector`.
+pub fn vector_address(vector: u8) -> u16 {
+    return INTERRUPT_VECTOR_TABLE + vector as u16;
+}
+
+pub fn push_stack(reg: &mut Registers, mem: &mut Memory, val: u16) {
+    let sp = reg.get(6).wrapping_sub(1);
+    reg.set(6, sp);
+    mem.set(sp, val);
+}
+
+pub fn pop_stack(reg: &mut Registers, mem: &Memory) -> u16 {
+    let sp = reg.get(6);
+    reg.set(6, sp.wrapping_add(1));
+    return mem.get(sp);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_take_next_respects_priority() {
+        let mut controller = InterruptController::new();
+        controller.raise(0x81, 2);
+        controller.raise(0x82, 6);
+
+        assert_eq!(controller.take_next(6, None), None);
+        assert_eq!(
+            controller.take_next(3, Some(InterruptRequest::keyboard())),
+            Some(InterruptRequest::new(0x82, 6))
+        );
+        assert_eq!(
+            controller.take_next(3, Some(InterruptRequest::keyboard())),
+            Some(InterruptRequest::keyboard())
+        );
+        assert_eq!(controller.pending().len(), 1);
+        assert_eq!(
+            controller.take_next(0, None),
+            Some(InterruptRequest::new(0x81, 2))
+        );
+        assert!(controller.pending().is_empty());
+    }
+
+    #[test]
+    fn test_initiate_from_user_mode() {
+        let mut reg = Registers::new();
+        let mut mem = Memory::new();
+
+        reg.psr = 0x8001;
+        reg.pc = 0x3005;
+        reg.set(6, 0xF000);
+        mem.set(0x0180, 0x1000);
+
+        initiate(
+            &mut reg,
+            &mut mem,
+            vector_address(KEYBOARD_VECTOR),
+            Some(KEYBOARD_PRIORITY),
+        );
+
+        assert_eq!(reg.pc, 0x1000);
+        assert!(!reg.is_user_mode());
+        assert_eq!(reg.priority(), 4);
+        assert_eq!(reg.saved_usp, 0xF000);
+        assert_eq!(reg.get(6), 0x2FFE);
+        assert_eq!(mem.get(0x2FFE), 0x3005);
+        assert_eq!(mem.get(0x2FFF), 0x8001);
+    }
+}
@@ -0,0 +1,154 @@
+use crate::vm::delta::IoEvent;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Instant;
+#[cfg(feature = "serde")]
+use tsify::Tsify;
+
+/// How often, in instructions, the wall clock is read when there is a time limit.
+#[cfg(not(target_arch = "wasm32"))]
+const WALL_CLOCK_INTERVAL: u64 = 1024;
+
+/// Caps on what a program may use, for running programs that might never halt.
+/// Every limit is off (`None`) by default. Limits are checked between
+/// instructions, so a program is stopped as soon as it reaches one. Only a step
+/// that moves several characters at once, like PUTS with fast traps, can take
+/// a program past its output limit.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize, Tsify))]
+#[cfg_attr(feature = "serde", tsify(into_wasm_abi, from_wasm_abi))]
+pub struct ResourceLimits {
+    pub max_instructions: Option<u64>,
+    /// Characters written to the display.
+    pub max_output_chars: Option<u64>,
+    /// Characters read from the keyboard.
+    pub max_input_reads: Option<u64>,
+    /// Wall-clock time since the first instruction after the program was
+    /// loaded. It is not available on the web, which has no clock to read.
+    pub max_wall_time_ms: Option<u64>,
+}
+
+impl ResourceLimits {
+    pub fn new() -> ResourceLimits {
+        ResourceLimits {
+            max_instructions: None,
+            max_output_chars: None,
+            max_input_reads: None,
+            max_wall_time_ms: None,
+        }
+    }
+}
+
+/// The limit a program ran into.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize, Tsify))]
+pub enum Limit {
+    Instructions,
+    OutputChars,
+    InputReads,
+    WallTime,
+}
+
+/// What a program has used since it was loaded.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize, Tsify))]
+pub struct ResourceUsage {
+    pub instructions: u64,
+    pub output_chars: u64,
+    pub input_reads: u64,
+    #[cfg(not(target_arch = "wasm32"))]
+    #[cfg_attr(feature = "serde", serde(skip))]
+    started: Option<Instant>,
+}
+
+impl ResourceUsage {
+    pub fn new() -> ResourceUsage {
+        ResourceUsage {
+            instructions: 0,
+            output_chars: 0,
+            input_reads: 0,
+            #[cfg(not(target_arch = "wasm32"))]
+            started: None,
+        }
+    }
+
+    /// Counts one instruction, and the characters it read and wrote.
+    pub fn record(&mut self, io: &[IoEvent]) {
+        self.instructions += 1;
+        for event in io {
+            match event {
+                IoEvent::Input(_) => self.input_reads += 1,
+                IoEvent::Output(_) => self.output_chars += 1,
+            }
+        }
+    }
+
+    /// The first limit that stops the next instruction from running, if any.
+    /// Each limit counts as reached once usage is at its maximum.
+    pub fn exceeded(&mut self, limits: &ResourceLimits) -> Option<Limit> {
+        if limits
+            .max_instructions
+            .is_some_and(|max| self.instructions >= max)
+        {
+            return Some(Limit::Instructions);
+        }
+        if limits
+            .max_output_chars
+            .is_some_and(|max| self.output_chars >= max)
+        {
+            return Some(Limit::OutputChars);
+        }
+        if limits
+            .max_input_reads
+            .is_some_and(|max| self.input_reads >= max)
+        {
+            return Some(Limit::InputReads);
+        }
+
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(max) = limits.max_wall_time_ms {
+            let started = *self.started.get_or_insert_with(Instant::now);
+            if self.instructions % WALL_CLOCK_INTERVAL == 0
+                && started.elapsed().as_millis() >= max as u128
+            {
+                return Some(Limit::WallTime);
+            }
+        }
+
+        return None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_limits_exceeded() {
+        let mut limits = ResourceLimits::new();
+        let mut usage = ResourceUsage::new();
+        assert_eq!(usage.exceeded(&limits), None);
+
+        limits.max_output_chars = Some(2);
+        usage.record(&[IoEvent::Output('a')]);
+        assert_eq!(usage.exceeded(&limits), None);
+        usage.record(&[IoEvent::Input('b'), IoEvent::Output('b')]);
+        assert_eq!(usage.exceeded(&limits), Some(Limit::OutputChars));
+
+        limits.max_instructions = Some(2);
+        assert_eq!(usage.exceeded(&limits), Some(Limit::Instructions));
+        assert_eq!(usage.input_reads, 1);
+    }
+
+    #[test]
+    fn test_wall_time_limit() {
+        let mut limits = ResourceLimits::new();
+        limits.max_wall_time_ms = Some(0);
+
+        assert_eq!(
+            ResourceUsage::new().exceeded(&limits),
+            Some(Limit::WallTime)
+        );
+    }
+}
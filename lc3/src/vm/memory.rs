@@ -3,8 +3,19 @@ use serde::{Deserialize, Deserializer, Serialize, Serializer};
 #[cfg(feature = "serde")]
 use tsify::Tsify;
 
+use super::breakpoint::{Access, WatchHit, Watchpoint};
+use super::decode::Op;
+use super::delta::MemoryWrite;
+use super::device::{DEVICE_SPACE_START, Devices};
+use crate::io::Lc3IO;
+use crate::program::Segment;
+
 const POW_2_16: usize = 2_usize.pow(16);
 
+/// x0000-x2FFF is system space. Together with the device page (xFE00-xFFFF), it
+/// can only be accessed while the machine is in supervisor mode.
+pub const USER_SPACE_START: u16 = 0x3000;
+
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize, Tsify))]
 #[cfg_attr(feature = "serde", tsify(into_wasm_abi, from_wasm_abi))]
 pub struct Memory {
@@ -16,6 +27,24 @@ pub struct Memory {
         )
     )]
     inner: [u16; POW_2_16],
+    pub devices: Devices,
+    /// Decoded instructions, per address. Only allocated once caching is enabled,
+    /// and an entry is dropped whenever its address is written.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    decoded: Option<Vec<Option<Op>>>,
+    /// Writes made between `start_journal` and `stop_journal`. The buffer is
+    /// reused from step to step, so recording does not allocate.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    journal: Vec<MemoryWrite>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    journaling: bool,
+    /// Addresses read by instructions between `start_journal` and `stop_journal`.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    reads: Vec<u16>,
+    pub watchpoints: Vec<Watchpoint>,
+    /// Watched accesses since the last `start_journal`.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    watch_hits: Vec<WatchHit>,
 }
 
 #[allow(dead_code)]
@@ -23,33 +52,190 @@ impl Memory {
     pub fn new() -> Memory {
         Memory {
             inner: [0; POW_2_16],
+            devices: Devices::new(),
+            decoded: None,
+            journal: vec![],
+            journaling: false,
+            reads: vec![],
+            watchpoints: vec![],
+            watch_hits: vec![],
         }
     }
 
-    pub fn load_file(&mut self, file: Vec<u16>) {
-        let mut mem_i = file[0] as usize; // origin
-        let mut vec_i = 1;
+    /// Forgets the last journal, and records every write until `stop_journal`.
+    pub fn start_journal(&mut self) {
+        self.journal.clear();
+        self.reads.clear();
+        self.watch_hits.clear();
+        self.journaling = true;
+    }
+
+    pub fn stop_journal(&mut self) {
+        self.journaling = false;
+    }
+
+    /// The writes recorded by the last `start_journal`, in order.
+    pub fn journal(&self) -> &[MemoryWrite] {
+        return &self.journal;
+    }
 
-        while vec_i < file.len() {
-            self.inner[mem_i] = file[vec_i];
+    /// The addresses read by the last `start_journal`, in order. Fetching an
+    /// instruction is not a read.
+    pub fn reads(&self) -> &[u16] {
+        return &self.reads;
+    }
 
-            vec_i += 1;
-            mem_i += 1;
+    /// Watched accesses recorded since the last `start_journal`, in order.
+    pub fn watch_hits(&self) -> &[WatchHit] {
+        return &self.watch_hits;
+    }
+
+    fn check_watchpoints(&mut self, address: u16, access: Access, value: u16) {
+        if self
+            .watchpoints
+            .iter()
+            .any(|wp| wp.matches(address, access))
+        {
+            self.watch_hits.push(WatchHit {
+                address,
+                access,
+                value,
+            });
+        }
+    }
+
+    fn record_write(&mut self, address: u16, new: u16) {
+        if self.journaling {
+            let old = self.get(address);
+            self.journal.push(MemoryWrite { address, old, new });
+        }
+        if !self.watchpoints.is_empty() {
+            self.check_watchpoints(address, Access::Write, new);
+        }
+    }
+
+    /// Turns the per-address decode cache on or off. Turning it off drops the cache.
+    pub fn set_decode_cache(&mut self, enabled: bool) {
+        if !enabled {
+            self.decoded = None;
+        } else if self.decoded.is_none() {
+            self.decoded = Some(vec![None; POW_2_16]);
+        }
+    }
+
+    pub fn has_decode_cache(&self) -> bool {
+        return self.decoded.is_some();
+    }
+
+    /// Decodes the instruction at `loc`, using the decode cache if it is enabled.
+    /// Device registers change underneath the cache, so they are never cached.
+    pub fn decode(&mut self, loc: u16) -> Op {
+        let Some(cache) = &mut self.decoded else {
+            return Op::decode(self.get(loc));
+        };
+        if loc >= DEVICE_SPACE_START {
+            return Op::decode(self.get(loc));
+        }
+
+        if let Some(op) = cache[loc as usize] {
+            return op;
+        }
+        let op = Op::decode(self.inner[loc as usize]);
+        cache[loc as usize] = Some(op);
+        return op;
+    }
+
+    fn invalidate(&mut self, loc: u16) {
+        if let Some(cache) = &mut self.decoded {
+            cache[loc as usize] = None;
+        }
+    }
+
+    /// Whether `loc` is in system space or device space, which user mode may not access.
+    pub fn is_privileged(loc: u16) -> bool {
+        return loc < USER_SPACE_START || loc >= DEVICE_SPACE_START;
+    }
+
+    /// Copies `segment` into memory. Fails, without writing anything, if it runs
+    /// past xFFFF.
+    pub fn load_segment(&mut self, segment: &Segment) -> Result<(), String> {
+        segment.verify_fits_in_memory()?;
+        let range = segment.origin as usize..segment.end();
+        self.inner[range.clone()].copy_from_slice(&segment.words);
+        if let Some(cache) = &mut self.decoded {
+            cache[range].fill(None);
+        }
+        return Ok(());
+    }
+
+    /// Every word of memory, without the device registers.
+    pub fn words(&self) -> &[u16] {
+        return &self.inner;
+    }
+
+    /// Overwrites all of memory (but not the device registers) with `words`,
+    /// which must hold exactly 2^16 words.
+    pub fn set_words(&mut self, words: &[u16]) {
+        self.inner.copy_from_slice(words);
+        if let Some(cache) = &mut self.decoded {
+            cache.fill(None);
         }
     }
 
     pub fn clear(&mut self) {
-        for i in self.inner {
-            self.inner[i as usize] = 0;
+        self.inner.fill(0);
+        if let Some(cache) = &mut self.decoded {
+            cache.fill(None);
         }
     }
 
+    /// Reads memory without any side effects. Device registers report their
+    /// current value, but the keyboard is not polled.
     pub fn get(&self, loc: u16) -> u16 {
+        if loc >= DEVICE_SPACE_START {
+            if let Some(val) = self.devices.peek(loc) {
+                return val;
+            }
+        }
         return self.inner[loc as usize];
     }
 
+    /// Writes memory without any side effects. Device registers are overwritten
+    /// directly, and nothing is printed.
     pub fn set(&mut self, loc: u16, val: u16) {
+        self.record_write(loc, val);
+        if loc >= DEVICE_SPACE_START && self.devices.poke(loc, val) {
+            return;
+        }
+        self.inner[loc as usize] = val;
+        self.invalidate(loc);
+    }
+
+    /// Reads memory the way an instruction does, so device registers are polled.
+    pub fn read(&mut self, loc: u16, io: &mut Lc3IO) -> u16 {
+        let mut val = self.inner[loc as usize];
+        if loc >= DEVICE_SPACE_START {
+            if let Some(device_val) = self.devices.read(loc, io) {
+                val = device_val;
+            }
+        }
+        if self.journaling {
+            self.reads.push(loc);
+        }
+        if !self.watchpoints.is_empty() {
+            self.check_watchpoints(loc, Access::Read, val);
+        }
+        return val;
+    }
+
+    /// Writes memory the way an instruction does, so writing DDR prints a character.
+    pub fn write(&mut self, loc: u16, val: u16, io: &mut Lc3IO) {
+        self.record_write(loc, val);
+        if loc >= DEVICE_SPACE_START && self.devices.write(loc, val, io) {
+            return;
+        }
         self.inner[loc as usize] = val;
+        self.invalidate(loc);
     }
 }
 
@@ -79,3 +265,32 @@ where
     array.copy_from_slice(&vec);
     Ok(array)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::*;
+
+    #[test]
+    fn test_decode_cache_invalidated_by_writes() {
+        let mut io = Lc3IO::new(Box::new(DebugIO::new()));
+        let mut mem = Memory::new();
+        mem.set_decode_cache(true);
+
+        mem.set(0x3000, 0xF025);
+        assert_eq!(mem.decode(0x3000), Op::Trap { vector: 0x25 });
+
+        mem.write(0x3000, 0xD000, &mut io);
+        assert_eq!(mem.decode(0x3000), Op::Reserved);
+
+        mem.load_segment(&Segment::new(0x3000, vec![0x8000]))
+            .unwrap();
+        assert_eq!(mem.decode(0x3000), Op::Rti);
+
+        mem.set_words(&[0x1000; POW_2_16]);
+        assert_eq!(mem.decode(0x3000), Op::decode(0x1000));
+
+        mem.clear();
+        assert_eq!(mem.decode(0x3000), Op::decode(0));
+    }
+}
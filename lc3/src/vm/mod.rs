@@ -1,5 +1,20 @@
+pub mod breakpoint;
+pub mod callgraph;
+pub mod coverage;
+pub mod decode;
+pub mod delta;
+pub mod device;
+pub mod expr;
+pub mod history;
 pub mod instructions;
+pub mod interrupt;
+pub mod limits;
 pub mod memory;
+pub mod narrate;
+pub mod os;
+pub mod profile;
 pub mod registers;
+pub mod snapshot;
+pub mod trace;
 pub mod trap;
 pub mod vm;
@@ -0,0 +1,346 @@
+use super::breakpoint::Exception;
+use super::decode::{Op, Operand};
+use super::delta::StateDelta;
+use super::memory::Memory;
+use super::registers::Registers;
+use super::trace::cc_str;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::fmt;
+#[cfg(feature = "serde")]
+use tsify::Tsify;
+
+/// A plain-English account of one executed instruction, for students stepping
+/// through a program. Displays as the instruction followed by its effects, e.g.
+/// `ADD R1, R1, #10: R1 ← x0000 + 10 = x000A; condition codes set to P`.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize, Tsify))]
+#[cfg_attr(feature = "serde", tsify(into_wasm_abi, from_wasm_abi))]
+pub struct Narration {
+    /// The address the instruction was fetched from.
+    pub pc: u16,
+    pub word: u16,
+    pub instruction: String,
+    /// The instruction word split into the fields of its layout, from the
+    /// opcode down to bit 0.
+    pub fields: Vec<BitField>,
+    /// What the instruction did, in the order it did it.
+    pub effects: Vec<String>,
+}
+
+/// One field of an instruction word, e.g. `dr` = `001`.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize, Tsify))]
+pub struct BitField {
+    /// The field's name in `Op::layout`, or `-` for bits the instruction ignores.
+    pub name: String,
+    /// The field's bits, most significant first.
+    pub bits: String,
+    pub value: u16,
+}
+
+impl fmt::Display for Narration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return write!(f, "{}: {}", self.instruction, self.effects.join("; "));
+    }
+}
+
+impl Narration {
+    /// The fields as two aligned lines: the bits, and the field names under them.
+    pub fn layout(&self) -> String {
+        let mut bits = vec![];
+        let mut names = vec![];
+        for field in &self.fields {
+            let width = field.bits.len().max(field.name.len());
+            bits.push(format!("{:<width$}", field.bits));
+            names.push(format!("{:<width$}", field.name));
+        }
+        return format!(
+            "{}\n{}",
+            bits.join(" ").trim_end(),
+            names.join(" ").trim_end()
+        );
+    }
+}
+
+/// What the last step did, worked out from the instruction and the `before`
+/// and `after` registers. `memory` is memory after the step.
+pub struct Step<'a> {
+    pub pc: u16,
+    pub word: u16,
+    pub before: &'a Registers,
+    pub after: &'a Registers,
+    pub memory: &'a Memory,
+    pub delta: &'a StateDelta,
+    pub exception: Option<Exception>,
+}
+
+pub fn narrate(step: &Step) -> Narration {
+    let op = Op::decode(step.word);
+    let mut effects = vec![];
+
+    if let Some(exception) = step.exception {
+        let kind = match exception {
+            Exception::PrivilegeMode => "privilege mode",
+            Exception::IllegalOpcode => "illegal opcode",
+            Exception::AccessControlViolation => "access control violation",
+        };
+        effects.push(format!("raises a {kind} exception"));
+        effects.extend(changes(step));
+    } else {
+        effects.extend(explain(&op, step));
+        if sets_cc(&op) {
+            effects.push(format!("condition codes set to {}", cc_str(step.after.psr)));
+        }
+    }
+
+    return Narration {
+        pc: step.pc,
+        word: step.word,
+        instruction: op.disassemble(step.pc),
+        fields: fields(&op, step.word),
+        effects: effects,
+    };
+}
+
+fn explain(op: &Op, step: &Step) -> Vec<String> {
+    let before = step.before;
+    let after = step.after;
+    let next = step.pc.wrapping_add(1);
+    let target = |offset: u16| next.wrapping_add(offset);
+    let reg = |r: u8| before.r[r as usize];
+
+    match *op {
+        Op::Add { dr, sr1, src2 } | Op::And { dr, sr1, src2 } => {
+            let symbol = if let Op::Add { .. } = op { "+" } else { "AND" };
+            let operand = match src2 {
+                Operand::Reg(sr2) => format!("x{:04X}", reg(sr2)),
+                Operand::Imm(imm) => format!("{}", imm as i16),
+            };
+            return vec![format!(
+                "R{dr} ← x{:04X} {symbol} {operand} = x{:04X}",
+                reg(sr1),
+                after.r[dr as usize]
+            )];
+        }
+        Op::Not { dr, sr } => {
+            return vec![format!(
+                "R{dr} ← NOT x{:04X} = x{:04X}",
+                reg(sr),
+                after.r[dr as usize]
+            )];
+        }
+        Op::Lea { dr, offset } => {
+            return vec![format!(
+                "R{dr} ← x{next:04X} + {} = x{:04X}",
+                offset as i16,
+                target(offset)
+            )];
+        }
+        Op::Ld { dr, offset } => {
+            return vec![format!(
+                "R{dr} ← M[x{:04X}] = x{:04X}",
+                target(offset),
+                after.r[dr as usize]
+            )];
+        }
+        Op::Ldi { dr, offset } => {
+            let pointer = step.memory.get(target(offset));
+            return vec![format!(
+                "R{dr} ← M[M[x{:04X}]] = M[x{pointer:04X}] = x{:04X}",
+                target(offset),
+                after.r[dr as usize]
+            )];
+        }
+        Op::Ldr { dr, base, offset } => {
+            return vec![format!(
+                "R{dr} ← M[R{base} + {}] = M[x{:04X}] = x{:04X}",
+                offset as i16,
+                reg(base).wrapping_add(offset),
+                after.r[dr as usize]
+            )];
+        }
+        Op::St { sr, offset } => {
+            return vec![format!(
+                "M[x{:04X}] ← R{sr} = x{:04X}",
+                target(offset),
+                reg(sr)
+            )];
+        }
+        Op::Sti { sr, offset } => {
+            let pointer = step.memory.get(target(offset));
+            return vec![format!(
+                "M[M[x{:04X}]] = M[x{pointer:04X}] ← R{sr} = x{:04X}",
+                target(offset),
+                reg(sr)
+            )];
+        }
+        Op::Str { sr, base, offset } => {
+            return vec![format!(
+                "M[R{base} + {}] = M[x{:04X}] ← R{sr} = x{:04X}",
+                offset as i16,
+                reg(base).wrapping_add(offset),
+                reg(sr)
+            )];
+        }
+        Op::Br { n, z, p, offset } => {
+            if !n && !z && !p {
+                return vec!["does nothing".to_string()];
+            }
+            if n && z && p {
+                return vec![format!("PC ← x{:04X} (always taken)", target(offset))];
+            }
+            let cc = cc_str(before.psr);
+            if after.pc == target(offset) && target(offset) != next {
+                return vec![
+                    format!("branch taken, since the condition codes are {cc}"),
+                    format!("PC ← x{:04X}", target(offset)),
+                ];
+            }
+            return vec![format!(
+                "branch not taken, since the condition codes are {cc}"
+            )];
+        }
+        Op::Jmp { base } => return vec![format!("PC ← R{base} = x{:04X}", reg(base))],
+        Op::Jsr { offset } => {
+            return vec![
+                format!("R7 ← x{next:04X}, the return address"),
+                format!("PC ← x{:04X}", target(offset)),
+            ];
+        }
+        Op::Jsrr { base } => {
+            return vec![
+                format!("R7 ← x{next:04X}, the return address"),
+                format!("PC ← R{base} = x{:04X}", reg(base)),
+            ];
+        }
+        Op::Trap { vector } => {
+            let mut effects = vec![format!("calls the service routine for trap x{vector:02X}")];
+            effects.extend(changes(step));
+            return effects;
+        }
+        Op::Rti => {
+            let mut effects = vec!["returns from the service routine".to_string()];
+            effects.extend(changes(step));
+            return effects;
+        }
+        Op::Reserved => return vec!["this opcode is reserved".to_string()],
+    }
+}
+
+/// Everything the step changed, straight from its delta, for instructions like
+/// TRAP and RTI whose effects depend on the OS and the privilege mode.
+fn changes(step: &Step) -> Vec<String> {
+    let delta = step.delta;
+    let mut effects = vec![];
+    for change in &delta.registers {
+        effects.push(format!("R{} ← x{:04X}", change.register, change.new));
+    }
+    for write in &delta.memory {
+        effects.push(format!("M[x{:04X}] ← x{:04X}", write.address, write.new));
+    }
+    if let Some(psr) = delta.psr {
+        effects.push(format!("PSR ← x{:04X}", psr.new));
+    }
+    if delta.pc.new != step.pc.wrapping_add(1) {
+        effects.push(format!("PC ← x{:04X}", delta.pc.new));
+    }
+    let output = delta.output();
+    if !output.is_empty() {
+        effects.push(format!("prints {output:?}"));
+    }
+    return effects;
+}
+
+fn sets_cc(op: &Op) -> bool {
+    return matches!(
+        op,
+        Op::Add { .. }
+            | Op::And { .. }
+            | Op::Not { .. }
+            | Op::Lea { .. }
+            | Op::Ld { .. }
+            | Op::Ldi { .. }
+            | Op::Ldr { .. }
+    );
+}
+
+fn fields(op: &Op, word: u16) -> Vec<BitField> {
+    let mut fields = vec![];
+    let mut shift = 16;
+    for (name, width) in op.layout() {
+        shift -= width;
+        let value = (word >> shift) & ((1 << width) - 1);
+        fields.push(BitField {
+            name: name.to_string(),
+            bits: format!("{value:0width$b}", width = *width as usize),
+            value: value,
+        });
+    }
+    return fields;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn narrate_word(word: u16, before: &Registers, after: &Registers) -> Narration {
+        let memory = Memory::new();
+        let delta = StateDelta::between(before, after);
+        return narrate(&Step {
+            pc: 0x3000,
+            word: word,
+            before: before,
+            after: after,
+            memory: &memory,
+            delta: &delta,
+            exception: None,
+        });
+    }
+
+    #[test]
+    fn test_narrate_add() {
+        let before = Registers::new();
+        let mut after = before;
+        after.r[1] = 10;
+        after.pc = 0x3001;
+        after.set_cc(false, false, true);
+
+        let narration = narrate_word(0b0001_001_001_1_01010, &before, &after);
+
+        assert_eq!(
+            narration.to_string(),
+            "ADD R1, R1, #10: R1 ← x0000 + 10 = x000A; condition codes set to P"
+        );
+        assert_eq!(
+            narration.layout(),
+            "0001 001 001 1 01010\nop   dr  sr1 c imm5"
+        );
+    }
+
+    #[test]
+    fn test_narrate_branch() {
+        let mut before = Registers::new();
+        before.set_cc(false, true, false);
+        let mut after = before;
+        after.pc = 0x3001;
+
+        let narration = narrate_word(0b0000_101_000000100, &before, &after);
+        assert_eq!(
+            narration.to_string(),
+            "BRnp x3005: branch not taken, since the condition codes are Z"
+        );
+
+        after.pc = 0x3005;
+        let narration = narrate_word(0b0000_010_000000100, &before, &after);
+        assert_eq!(
+            narration.effects,
+            vec![
+                "branch taken, since the condition codes are Z",
+                "PC ← x3005"
+            ]
+        );
+        let names: Vec<&str> = narration.fields.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, vec!["op", "nzp", "pcoffset9"]);
+    }
+}
@@ -0,0 +1,39 @@
+use crate::asm::asm::Asm;
+use crate::io::{DebugIO, Lc3IO};
+use crate::program::Program;
+use std::sync::OnceLock;
+
+/// Source of the operating system the VM boots with. It provides the trap
+/// vector table, the exception handlers and the trap service routines.
+pub const OS_SOURCE: &str = include_str!("os/bin/os.asm");
+
+/// The assembled OS image. It is assembled once, the first time it is needed.
+pub fn image() -> &'static Program {
+    static IMAGE: OnceLock<Program> = OnceLock::new();
+
+    return IMAGE.get_or_init(|| {
+        let mut asm = Asm::new(Lc3IO::new(Box::new(DebugIO::new())));
+        return asm
+            .run(OS_SOURCE.to_string())
+            .expect("The bundled operating system failed to assemble");
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::asm_ins::{HALT_VAL, PUTS_VAL};
+    use crate::vm::interrupt::{ACCESS_CONTROL_VECTOR, INTERRUPT_VECTOR_TABLE};
+
+    #[test]
+    fn test_os_image_layout() {
+        let image = image();
+        let word = |addr: u16| image.word_at(addr).unwrap();
+
+        assert_eq!(image.entry, 0x0000);
+        assert_ne!(word(PUTS_VAL), 0);
+        assert_ne!(word(HALT_VAL), 0);
+        assert_eq!(word(0x26), 0);
+        assert!(word(INTERRUPT_VECTOR_TABLE + ACCESS_CONTROL_VECTOR as u16) >= 0x0200);
+    }
+}
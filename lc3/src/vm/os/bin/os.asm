@@ -1,28 +1,172 @@
-;; The operating system is an LC-3 program that runs in priveledged
-;; mode in the LC-3.
+;; The operating system is an LC-3 program that runs in privileged
+;; mode in the LC-3. It is assembled by the crate's own assembler and
+;; loaded at x0000 before the user program.
+;;
+;; Every service routine is entered through the trap vector table or the
+;; interrupt vector table, so it runs on the supervisor stack with the
+;; caller's PSR and PC saved there, and returns to the caller with RTI.
+;; All I/O goes through the memory-mapped device registers.
 
-.ORIG                       x0020
+.ORIG                       x0000
 
-;;;     TRAP VECTOR TABLE
-;;; -------------------------
-                            BR      GETC_FN
-                            BR      OUT_FN
-                            BR      PUTS_FN
-                            BR      IN_FN
-                            BR      PUTSP_FN
-                            BR      HALT_FN
+;;;     TRAP VECTOR TABLE (x0000 - x00FF)
+;;; -------------------------------------
+                            .BLKW   #32             ; x00 - x1F are unused
+                            .FILL   GETC_FN         ; x20
+                            .FILL   OUT_FN          ; x21
+                            .FILL   PUTS_FN         ; x22
+                            .FILL   IN_FN           ; x23
+                            .FILL   PUTSP_FN        ; x24
+                            .FILL   HALT_FN         ; x25
+                            .BLKW   #218            ; x26 - xFF are unused
 
-;;;    STATUS REGISTER LOCATIONS
-;;; -------------------------------
-KEYBOARD_STATUS_REGISTER    .FILL   xFE00
-KEYBOARD_DATA_REGISTER      .FILL   xFE02
-DISPLAY_STATUS_REGISTER     .FILL   xFE04
-DISPLAY_DATA_REGISTER       .FILL   xFE06
-MACHINE_CONTROL_REGISTER    .FILL   xFFFE
+;;;     INTERRUPT VECTOR TABLE (x0100 - x01FF)
+;;; ------------------------------------------
+                            .FILL   PRIVILEGE_EXC   ; x00
+                            .FILL   ILLEGAL_EXC     ; x01
+                            .FILL   ACCESS_EXC      ; x02
+                            .BLKW   #253            ; x03 - xFF are unused
 
-HALT_FN                     LDI     R5, MACHINE_CONTROL_REGISTER
+;;;     DEVICE REGISTER LOCATIONS
+;;; -----------------------------
+KBSR_ADDR                   .FILL   xFE00
+KBDR_ADDR                   .FILL   xFE02
+DSR_ADDR                    .FILL   xFE04
+DDR_ADDR                    .FILL   xFE06
+MCR_ADDR                    .FILL   xFFFE
 
+LOW_BYTE_MASK               .FILL   x00FF
+CLOCK_OFF_MASK              .FILL   x7FFF
 
-                            RET
+SAVE_R0                     .FILL   #0
+SAVE_R1                     .FILL   #0
+SAVE_R2                     .FILL   #0
+SAVE_R3                     .FILL   #0
+SAVE_R4                     .FILL   #0
+
+;;;     GETC - reads a single character from the keyboard into R0
+;;; -------------------------------------------------------------
+GETC_FN                     LDI     R0, KBSR_ADDR
+                            BRzp    GETC_FN
+                            LDI     R0, KBDR_ADDR
+                            RTI
+
+;;;     OUT - writes the character in R0 to the display
+;;; ---------------------------------------------------
+OUT_FN                      ST      R1, SAVE_R1
+OUT_WAIT                    LDI     R1, DSR_ADDR
+                            BRzp    OUT_WAIT
+                            STI     R0, DDR_ADDR
+                            LD      R1, SAVE_R1
+                            RTI
+
+;;;     PUTS - writes the null terminated string pointed to by R0
+;;; -------------------------------------------------------------
+PUTS_FN                     ST      R0, SAVE_R0
+                            ST      R1, SAVE_R1
+                            ST      R2, SAVE_R2
+                            ADD     R1, R0, #0
+PUTS_LOOP                   LDR     R0, R1, #0
+                            BRz     PUTS_DONE
+PUTS_WAIT                   LDI     R2, DSR_ADDR
+                            BRzp    PUTS_WAIT
+                            STI     R0, DDR_ADDR
+                            ADD     R1, R1, #1
+                            BR      PUTS_LOOP
+PUTS_DONE                   LD      R0, SAVE_R0
+                            LD      R1, SAVE_R1
+                            LD      R2, SAVE_R2
+                            RTI
+
+;;;     IN - writes the prompt pointed to by R0, then reads a
+;;;     single character from the keyboard into R0
+;;; ---------------------------------------------------------
+IN_FN                       ST      R1, SAVE_R1
+                            ST      R2, SAVE_R2
+                            ADD     R1, R0, #0
+IN_PROMPT                   LDR     R0, R1, #0
+                            BRz     IN_READ
+IN_WAIT                     LDI     R2, DSR_ADDR
+                            BRzp    IN_WAIT
+                            STI     R0, DDR_ADDR
+                            ADD     R1, R1, #1
+                            BR      IN_PROMPT
+IN_READ                     LDI     R0, KBSR_ADDR
+                            BRzp    IN_READ
+                            LDI     R0, KBDR_ADDR
+                            LD      R1, SAVE_R1
+                            LD      R2, SAVE_R2
+                            RTI
+
+;;;     PUTSP - writes the string of packed characters pointed to by
+;;;     R0. Each word holds two characters, low byte first.
+;;; ----------------------------------------------------------------
+PUTSP_FN                    ST      R0, SAVE_R0
+                            ST      R1, SAVE_R1
+                            ST      R2, SAVE_R2
+                            ST      R3, SAVE_R3
+                            ST      R4, SAVE_R4
+                            ADD     R1, R0, #0
+                            LD      R4, LOW_BYTE_MASK
+PUTSP_LOOP                  LDR     R2, R1, #0
+                            AND     R0, R2, R4
+                            BRz     PUTSP_DONE
+PUTSP_LOW                   LDI     R3, DSR_ADDR
+                            BRzp    PUTSP_LOW
+                            STI     R0, DDR_ADDR
+                            AND     R3, R3, #0      ; rotate the word left 8 times,
+                            ADD     R3, R3, #8      ; moving the high byte into the low byte
+PUTSP_ROTATE                ADD     R2, R2, #0
+                            BRzp    PUTSP_SHIFT
+                            ADD     R2, R2, R2
+                            ADD     R2, R2, #1
+                            BR      PUTSP_COUNT
+PUTSP_SHIFT                 ADD     R2, R2, R2
+PUTSP_COUNT                 ADD     R3, R3, #-1
+                            BRp     PUTSP_ROTATE
+                            AND     R0, R2, R4
+                            BRz     PUTSP_DONE
+PUTSP_HIGH                  LDI     R3, DSR_ADDR
+                            BRzp    PUTSP_HIGH
+                            STI     R0, DDR_ADDR
+                            ADD     R1, R1, #1
+                            BR      PUTSP_LOOP
+PUTSP_DONE                  LD      R0, SAVE_R0
+                            LD      R1, SAVE_R1
+                            LD      R2, SAVE_R2
+                            LD      R3, SAVE_R3
+                            LD      R4, SAVE_R4
+                            RTI
+
+;;;     HALT - stops the clock by clearing bit 15 of the MCR
+;;; --------------------------------------------------------
+HALT_FN                     ST      R0, SAVE_R0
+                            ST      R1, SAVE_R1
+                            LDI     R0, MCR_ADDR
+                            LD      R1, CLOCK_OFF_MASK
+                            AND     R0, R0, R1
+                            STI     R0, MCR_ADDR
+                            LD      R0, SAVE_R0     ; only reached if the clock is restarted
+                            LD      R1, SAVE_R1
+                            RTI
+
+;;;     EXCEPTION HANDLERS - report the exception, then halt
+;;; --------------------------------------------------------
+PRIVILEGE_EXC               LEA     R0, PRIVILEGE_MSG
+                            BR      EXC_REPORT
+ILLEGAL_EXC                 LEA     R0, ILLEGAL_MSG
+                            BR      EXC_REPORT
+ACCESS_EXC                  LEA     R0, ACCESS_MSG
+EXC_REPORT                  LDR     R1, R0, #0
+                            BRz     HALT_FN
+EXC_WAIT                    LDI     R2, DSR_ADDR
+                            BRzp    EXC_WAIT
+                            STI     R1, DDR_ADDR
+                            ADD     R0, R0, #1
+                            BR      EXC_REPORT
+
+PRIVILEGE_MSG               .STRINGZ "\nPrivilege mode exception: a privileged instruction was executed in user mode\n"
+ILLEGAL_MSG                 .STRINGZ "\nIllegal opcode exception: the reserved opcode was executed\n"
+ACCESS_MSG                  .STRINGZ "\nAccess control violation: user mode accessed system or device memory\n"
 
 .END
@@ -0,0 +1,223 @@
+use super::decode::Op;
+use super::delta::MemoryWrite;
+use super::device::{DDR, DEVICE_SPACE_START, DSR, KBDR, KBSR, MCR};
+use super::memory::Memory;
+use std::collections::HashMap;
+
+/// How many rows each table of `Profile::report` shows.
+pub const REPORT_ROWS: usize = 10;
+
+/// Addresses further than this past the nearest label are left unlabelled,
+/// since they most likely belong to something else.
+const MAX_LABEL_OFFSET: u16 = 0x100;
+
+const OPCODE_NAMES: [&str; 16] = [
+    "BR", "ADD", "LD", "ST", "JSR", "AND", "LDR", "STR", "RTI", "NOT", "LDI", "STI", "JMP",
+    "RESERVED", "LEA", "TRAP",
+];
+
+/// Counts of what a program did, collected while `VM::profile` is set.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Profile {
+    pub instructions: u64,
+    /// Instructions executed, per address.
+    pub executed: HashMap<u16, u64>,
+    /// Instructions executed, per opcode.
+    pub opcodes: [u64; 16],
+    /// Memory reads and writes made by instructions, per address.
+    pub reads: HashMap<u16, u64>,
+    pub writes: HashMap<u16, u64>,
+    /// TRAP instructions executed, per trap vector.
+    pub traps: HashMap<u16, u64>,
+}
+
+impl Profile {
+    pub fn new() -> Profile {
+        Profile {
+            instructions: 0,
+            executed: HashMap::new(),
+            opcodes: [0; 16],
+            reads: HashMap::new(),
+            writes: HashMap::new(),
+            traps: HashMap::new(),
+        }
+    }
+
+    /// Counts the instruction `word` fetched from `pc`, and the memory it read and wrote.
+    pub fn record(&mut self, pc: u16, word: u16, reads: &[u16], writes: &[MemoryWrite]) {
+        self.instructions += 1;
+        *self.executed.entry(pc).or_insert(0) += 1;
+        self.opcodes[(word >> 12) as usize] += 1;
+        if let Op::Trap { vector } = Op::decode(word) {
+            *self.traps.entry(vector).or_insert(0) += 1;
+        }
+        for address in reads {
+            *self.reads.entry(*address).or_insert(0) += 1;
+        }
+        for write in writes {
+            *self.writes.entry(write.address).or_insert(0) += 1;
+        }
+    }
+
+    /// The `count` most executed addresses, most executed first.
+    pub fn hotspots(&self, count: usize) -> Vec<(u16, u64)> {
+        return top(&self.executed, count);
+    }
+
+    /// A summary of the hotspots, the instruction mix, the busiest memory and the
+    /// traps, with addresses annotated by the nearest label at or before them
+    /// (e.g. `LOOP+2`). `memory` is used to disassemble the hotspots.
+    pub fn report(&self, memory: &Memory, symbols: &HashMap<String, u16>) -> String {
+        let labels = Labels::new(symbols);
+        let mut out = format!("Profile: {} instructions executed\n", self.instructions);
+
+        out += "\nHotspots:\n";
+        out += "      count       %  address  label           instruction\n";
+        for (address, count) in self.hotspots(REPORT_ROWS) {
+            out += &format!(
+                "  {count:>9}  {:>5.1}%  x{address:04X}    {:<14}  {}\n",
+                self.percent(count),
+                labels.describe(address),
+                Op::decode(memory.get(address)).disassemble(address)
+            );
+        }
+
+        out += "\nInstruction mix:\n";
+        let mut mix: Vec<(usize, u64)> = self
+            .opcodes
+            .iter()
+            .copied()
+            .enumerate()
+            .filter(|(_, count)| *count > 0)
+            .collect();
+        mix.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        for (opcode, count) in mix {
+            out += &format!(
+                "  {:<8}  {count:>9}  {:>5.1}%\n",
+                OPCODE_NAMES[opcode],
+                self.percent(count)
+            );
+        }
+
+        for (title, counts) in [
+            ("Memory reads", &self.reads),
+            ("Memory writes", &self.writes),
+        ] {
+            if counts.is_empty() {
+                continue;
+            }
+            out += &format!("\n{title}:\n");
+            for (address, count) in top(counts, REPORT_ROWS) {
+                out += &format!(
+                    "  x{address:04X}  {:<14}  {count:>9}\n",
+                    labels.describe(address)
+                );
+            }
+        }
+
+        if !self.traps.is_empty() {
+            out += "\nTraps:\n";
+            for (vector, count) in top(&self.traps, self.traps.len()) {
+                let name = Op::Trap { vector }.disassemble(0);
+                out += &format!("  x{vector:02X}  {name:<8}  {count:>9}\n");
+            }
+        }
+
+        return out;
+    }
+
+    fn percent(&self, count: u64) -> f64 {
+        if self.instructions == 0 {
+            return 0.0;
+        }
+        return count as f64 * 100.0 / self.instructions as f64;
+    }
+}
+
+/// The `count` largest entries, largest first, ties broken by the lower key.
+fn top(counts: &HashMap<u16, u64>, count: usize) -> Vec<(u16, u64)> {
+    let mut entries: Vec<(u16, u64)> = counts.iter().map(|(k, v)| (*k, *v)).collect();
+    entries.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    entries.truncate(count);
+    return entries;
+}
+
+/// Looks up the label an address belongs to.
+struct Labels<'a> {
+    /// Sorted by address.
+    labels: Vec<(u16, &'a str)>,
+}
+
+impl<'a> Labels<'a> {
+    fn new(symbols: &'a HashMap<String, u16>) -> Labels<'a> {
+        let mut labels: Vec<(u16, &str)> = symbols
+            .iter()
+            .map(|(name, address)| (*address, name.as_str()))
+            .collect();
+        labels.sort();
+        return Labels { labels: labels };
+    }
+
+    /// `LOOP` for the address of `LOOP`, `LOOP+2` two words after it, the name of
+    /// a device register, or an empty string if no label comes shortly before
+    /// the address.
+    fn describe(&self, address: u16) -> String {
+        if address >= DEVICE_SPACE_START {
+            let name = match address {
+                KBSR => "KBSR",
+                KBDR => "KBDR",
+                DSR => "DSR",
+                DDR => "DDR",
+                MCR => "MCR",
+                _ => "",
+            };
+            return name.to_string();
+        }
+
+        let index = self.labels.partition_point(|(a, _)| *a <= address);
+        if index == 0 {
+            return String::new();
+        }
+        let (start, name) = self.labels[index - 1];
+        if start == address {
+            return name.to_string();
+        }
+        if address - start > MAX_LABEL_OFFSET {
+            return String::new();
+        }
+        return format!("{name}+{}", address - start);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_profile_record() {
+        let mut profile = Profile::new();
+        profile.record(0x3000, 0x1261, &[], &[]);
+        profile.record(0x3001, 0x2005, &[0x3007], &[]);
+        profile.record(0x3000, 0x1261, &[], &[]);
+        profile.record(0x3002, 0xF025, &[], &[]);
+
+        assert_eq!(profile.instructions, 4);
+        assert_eq!(profile.hotspots(2), vec![(0x3000, 2), (0x3001, 1)]);
+        assert_eq!(profile.opcodes[1], 2);
+        assert_eq!(profile.reads.get(&0x3007), Some(&1));
+        assert_eq!(profile.traps.get(&0x25), Some(&1));
+    }
+
+    #[test]
+    fn test_labels() {
+        let symbols = HashMap::from([("MAIN".to_string(), 0x3000), ("LOOP".to_string(), 0x3004)]);
+        let labels = Labels::new(&symbols);
+
+        assert_eq!(labels.describe(0x2FFF), "");
+        assert_eq!(labels.describe(0x3000), "MAIN");
+        assert_eq!(labels.describe(0x3003), "MAIN+3");
+        assert_eq!(labels.describe(0x3006), "LOOP+2");
+        assert_eq!(labels.describe(0x4000), "");
+        assert_eq!(labels.describe(0xFE04), "DSR");
+    }
+}
@@ -3,31 +3,47 @@ use serde::{Deserialize, Serialize};
 #[cfg(feature = "serde")]
 use tsify::Tsify;
 
+#[derive(Clone, Copy, Debug, PartialEq)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize, Tsify))]
 #[cfg_attr(feature = "serde", tsify(into_wasm_abi, from_wasm_abi))]
 pub struct Registers {
     pub r: [u16; 8],
     pub pc: u16,
-    pub n: bool,
-    pub z: bool,
-    pub p: bool,
     pub ir: u16,
     pub psr: u16,
-    pub halt: bool,
+    pub saved_usp: u16,
+    pub saved_ssp: u16,
 }
 
+/// The Processor Status Register is laid out as follows:
+/// ```text
+///        PSR - | 0 0000 000 00000 000 |
+///              | - ---- --- ----- --- |
+///              | u      pri       nzp |
+/// ```
+/// Where `u` is the privilege mode (1 == user), `pri` is the priority level the
+/// machine is currently running at, and `nzp` are the condition codes.
+const PSR_PRIVILEGE_BIT: u16 = 15;
+const PSR_PRIORITY_SHIFT: u16 = 8;
+const PSR_PRIORITY_MASK: u16 = 0b111 << PSR_PRIORITY_SHIFT;
+const PSR_CC_MASK: u16 = 0b111;
+
+/// Default top of the supervisor stack. It grows down into the system space,
+/// directly below where user programs conventionally begin.
+pub const DEFAULT_SSP: u16 = 0x3000;
+/// Default top of the user stack, directly below the device registers.
+pub const DEFAULT_USP: u16 = 0xFE00;
+
 #[allow(dead_code)]
 impl Registers {
     pub fn new() -> Registers {
         Registers {
             r: [0; 8],
             pc: 0,
-            n: false,
-            z: false,
-            p: false,
             ir: 0,
             psr: 0,
-            halt: false,
+            saved_usp: DEFAULT_USP,
+            saved_ssp: DEFAULT_SSP,
         }
     }
 
@@ -58,6 +74,62 @@ impl Registers {
 
         self.r[reg_value] = new_value;
     }
+
+    /// PSR[15] is 1 when the machine is running in user mode, and 0 in supervisor mode.
+    pub fn is_user_mode(&self) -> bool {
+        return self.psr >> PSR_PRIVILEGE_BIT & 1 == 1;
+    }
+
+    pub fn set_user_mode(&mut self, user_mode: bool) {
+        self.psr &= !(1 << PSR_PRIVILEGE_BIT);
+        self.psr |= (user_mode as u16) << PSR_PRIVILEGE_BIT;
+    }
+
+    /// PSR[10:8], the priority level (0-7) of the currently running program.
+    pub fn priority(&self) -> u8 {
+        return ((self.psr & PSR_PRIORITY_MASK) >> PSR_PRIORITY_SHIFT) as u8;
+    }
+
+    pub fn set_priority(&mut self, priority: u8) {
+        self.psr &= !PSR_PRIORITY_MASK;
+        self.psr |= ((priority as u16) << PSR_PRIORITY_SHIFT) & PSR_PRIORITY_MASK;
+    }
+
+    pub fn n(&self) -> bool {
+        return self.psr >> 2 & 1 == 1;
+    }
+
+    pub fn z(&self) -> bool {
+        return self.psr >> 1 & 1 == 1;
+    }
+
+    pub fn p(&self) -> bool {
+        return self.psr & 1 == 1;
+    }
+
+    /// Overwrites PSR[2:0] with the given condition codes.
+    pub fn set_cc(&mut self, n: bool, z: bool, p: bool) {
+        self.psr &= !PSR_CC_MASK;
+        self.psr |= (n as u16) << 2 | (z as u16) << 1 | p as u16;
+    }
+
+    /// The condition codes as a string (e.g. `"N"`, `"Z"`, `"P"`), or `"-"` if none are set.
+    pub fn cc_str(&self) -> String {
+        let mut cc = String::new();
+        if self.n() {
+            cc.push('N');
+        }
+        if self.z() {
+            cc.push('Z');
+        }
+        if self.p() {
+            cc.push('P');
+        }
+        if cc.is_empty() {
+            cc.push('-');
+        }
+        return cc;
+    }
 }
 
 #[cfg(test)]
@@ -87,4 +159,23 @@ mod test {
         reg.r[3] = 712;
         assert!(reg.get(3) == 712);
     }
+
+    #[test]
+    fn test_psr_fields() {
+        let mut reg = Registers::new();
+
+        reg.set_user_mode(true);
+        reg.set_priority(4);
+        reg.set_cc(false, true, false);
+        assert_eq!(reg.psr, 0b1000_0100_0000_0010);
+        assert!(reg.is_user_mode());
+        assert_eq!(reg.priority(), 4);
+        assert!(!reg.n() && reg.z() && !reg.p());
+
+        reg.set_cc(true, false, false);
+        reg.set_priority(7);
+        reg.set_user_mode(false);
+        assert_eq!(reg.psr, 0b0000_0111_0000_0100);
+        assert_eq!(reg.cc_str(), "N");
+    }
 }
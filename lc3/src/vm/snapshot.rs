@@ -0,0 +1,385 @@
+use super::device::Devices;
+use super::interrupt::InterruptRequest;
+use super::registers::Registers;
+use super::vm::{StartState, TrapMode};
+use crate::program::Segment;
+
+/// Every snapshot file starts with these bytes.
+pub const SNAPSHOT_MAGIC: [u8; 8] = *b"LC3SNAP\0";
+
+/// Bumped whenever the layout below changes. Older versions are rejected
+/// rather than guessed at.
+pub const SNAPSHOT_VERSION: u16 = 2;
+
+const MEMORY_WORDS: usize = 1 << 16;
+
+/// Everything needed to carry on running a machine exactly where it stopped.
+///
+/// On disk, after the magic bytes and the version, every field is stored
+/// big-endian in this order:
+///
+/// | field      | layout                                                     |
+/// | ---------- | ---------------------------------------------------------- |
+/// | trap mode  | u16: 0 for `Os`, 1 for `FastPath`                          |
+/// | start      | u16: 1 if a PSR is given, then u16 each: PSR, USP, SSP     |
+/// | registers  | u16 each: R0-R7, PC, IR, PSR, saved USP, saved SSP         |
+/// | devices    | u16 each: KBSR, KBDR, DSR, DDR, MCR, 1 if keyboard starved |
+/// | OS         | u16: 1 for a user-assembled OS, then u16 segment count,    |
+/// |            | then per segment: u16 origin, u32 length, words            |
+/// | interrupts | u16 count, then a (u8 vector, u8 priority) pair per request |
+/// | input      | u32 count, then a u32 code point per character             |
+/// | memory     | u32 run count, then per run: u16 start, u32 length, words  |
+///
+/// Memory is stored as runs of non-zero words, so a program and the OS take a
+/// few kilobytes instead of the full 128 KiB address space.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Snapshot {
+    pub trap_mode: TrapMode,
+    /// What the machine goes back to when it is reset.
+    pub start: StartState,
+    pub registers: Registers,
+    pub devices: Devices,
+    /// The segments of a user-assembled OS, or `None` for the bundled one.
+    pub os_segments: Option<Vec<Segment>>,
+    pub interrupts: Vec<InterruptRequest>,
+    /// Input that had arrived but not been read, in the order it will be read.
+    pub input: Vec<char>,
+    /// All 2^16 words of memory, not including the device registers.
+    pub memory: Vec<u16>,
+}
+
+impl Snapshot {
+    /// The snapshot as a file. Fails if it holds more interrupts, OS segments
+    /// or input than the file's counts can describe.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, String> {
+        let mut out = SNAPSHOT_MAGIC.to_vec();
+        put_u16(&mut out, SNAPSHOT_VERSION);
+
+        put_u16(
+            &mut out,
+            match self.trap_mode {
+                TrapMode::Os => 0,
+                TrapMode::FastPath => 1,
+            },
+        );
+
+        let start = &self.start;
+        put_u16(&mut out, start.psr.is_some() as u16);
+        for word in [start.psr.unwrap_or(0), start.usp, start.ssp] {
+            put_u16(&mut out, word);
+        }
+
+        let reg = &self.registers;
+        for r in reg.r {
+            put_u16(&mut out, r);
+        }
+        for word in [reg.pc, reg.ir, reg.psr, reg.saved_usp, reg.saved_ssp] {
+            put_u16(&mut out, word);
+        }
+
+        let dev = &self.devices;
+        for word in [dev.kbsr, dev.kbdr, dev.dsr, dev.ddr, dev.mcr] {
+            put_u16(&mut out, word);
+        }
+        put_u16(&mut out, dev.keyboard_starved as u16);
+
+        put_u16(&mut out, self.os_segments.is_some() as u16);
+        let os_segments = self.os_segments.as_deref().unwrap_or(&[]);
+        put_u16(&mut out, count(os_segments.len(), "OS segments")?);
+        for segment in os_segments {
+            put_u16(&mut out, segment.origin);
+            put_u32(&mut out, segment.words.len() as u32);
+            for word in &segment.words {
+                put_u16(&mut out, *word);
+            }
+        }
+
+        put_u16(
+            &mut out,
+            count(self.interrupts.len(), "pending interrupts")?,
+        );
+        for request in &self.interrupts {
+            out.push(request.vector);
+            out.push(request.priority);
+        }
+
+        let Ok(input_len) = u32::try_from(self.input.len()) else {
+            return Err("There is too much pending input to save in a snapshot".to_string());
+        };
+        put_u32(&mut out, input_len);
+        for c in &self.input {
+            put_u32(&mut out, *c as u32);
+        }
+
+        let runs = memory_runs(&self.memory);
+        put_u32(&mut out, runs.len() as u32);
+        for (start, end) in runs {
+            put_u16(&mut out, start as u16);
+            put_u32(&mut out, (end - start) as u32);
+            for word in &self.memory[start..end] {
+                put_u16(&mut out, *word);
+            }
+        }
+
+        return Ok(out);
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Snapshot, String> {
+        let mut reader = Reader { bytes, pos: 0 };
+
+        if reader.take(SNAPSHOT_MAGIC.len())? != SNAPSHOT_MAGIC {
+            return Err("This is not an LC-3 snapshot file".to_string());
+        }
+        let version = reader.u16()?;
+        if version != SNAPSHOT_VERSION {
+            return Err(format!(
+                "Snapshot version {version} is not supported (expected version {SNAPSHOT_VERSION})"
+            ));
+        }
+
+        let trap_mode = match reader.u16()? {
+            0 => TrapMode::Os,
+            1 => TrapMode::FastPath,
+            mode => return Err(format!("Unknown trap mode {mode} in snapshot")),
+        };
+
+        let psr_given = reader.u16()? != 0;
+        let psr = reader.u16()?;
+        let start = StartState {
+            psr: psr_given.then_some(psr),
+            usp: reader.u16()?,
+            ssp: reader.u16()?,
+        };
+
+        let mut registers = Registers::new();
+        for i in 0..8 {
+            registers.r[i] = reader.u16()?;
+        }
+        registers.pc = reader.u16()?;
+        registers.ir = reader.u16()?;
+        registers.psr = reader.u16()?;
+        registers.saved_usp = reader.u16()?;
+        registers.saved_ssp = reader.u16()?;
+
+        let mut devices = Devices::new();
+        devices.kbsr = reader.u16()?;
+        devices.kbdr = reader.u16()?;
+        devices.dsr = reader.u16()?;
+        devices.ddr = reader.u16()?;
+        devices.mcr = reader.u16()?;
+        devices.keyboard_starved = reader.u16()? != 0;
+
+        let custom_os = reader.u16()? != 0;
+        let mut os_segments = vec![];
+        for _ in 0..reader.u16()? {
+            let origin = reader.u16()?;
+            let length = reader.u32()? as usize;
+            if origin as usize + length > MEMORY_WORDS {
+                return Err(format!(
+                    "OS segment at x{origin:04X} runs past the end of memory"
+                ));
+            }
+            let mut words = vec![];
+            for _ in 0..length {
+                words.push(reader.u16()?);
+            }
+            os_segments.push(Segment::new(origin, words));
+        }
+
+        let mut interrupts = vec![];
+        for _ in 0..reader.u16()? {
+            let vector = reader.u8()?;
+            let priority = reader.u8()?;
+            interrupts.push(InterruptRequest::new(vector, priority));
+        }
+
+        let mut input = vec![];
+        for _ in 0..reader.u32()? {
+            let code = reader.u32()?;
+            let Some(c) = char::from_u32(code) else {
+                return Err(format!("Invalid input character U+{code:04X} in snapshot"));
+            };
+            input.push(c);
+        }
+
+        let mut memory = vec![0; MEMORY_WORDS];
+        for _ in 0..reader.u32()? {
+            let start = reader.u16()? as usize;
+            let length = reader.u32()? as usize;
+            if start + length > MEMORY_WORDS {
+                return Err(format!(
+                    "Memory run at x{start:04X} runs past the end of memory"
+                ));
+            }
+            for word in &mut memory[start..start + length] {
+                *word = reader.u16()?;
+            }
+        }
+
+        if reader.pos != bytes.len() {
+            return Err("Unexpected data after the end of the snapshot".to_string());
+        }
+
+        return Ok(Snapshot {
+            trap_mode,
+            start,
+            registers,
+            devices,
+            os_segments: custom_os.then_some(os_segments),
+            interrupts,
+            input,
+            memory,
+        });
+    }
+}
+
+/// The `[start, end)` ranges of memory that hold non-zero words.
+fn memory_runs(memory: &[u16]) -> Vec<(usize, usize)> {
+    let mut runs = vec![];
+    let mut start = None;
+
+    for (i, word) in memory.iter().enumerate() {
+        match (start, *word != 0) {
+            (None, true) => start = Some(i),
+            (Some(s), false) => {
+                runs.push((s, i));
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    if let Some(s) = start {
+        runs.push((s, memory.len()));
+    }
+
+    return runs;
+}
+
+/// `len` as a u16 count of `what`, if it fits.
+fn count(len: usize, what: &str) -> Result<u16, String> {
+    return u16::try_from(len)
+        .map_err(|_| format!("There are too many {what} ({len}) to save in a snapshot"));
+}
+
+fn put_u16(out: &mut Vec<u8>, value: u16) {
+    out.extend_from_slice(&value.to_be_bytes());
+}
+
+fn put_u32(out: &mut Vec<u8>, value: u32) {
+    out.extend_from_slice(&value.to_be_bytes());
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, count: usize) -> Result<&'a [u8], String> {
+        if self.pos + count > self.bytes.len() {
+            return Err("The snapshot file ends early".to_string());
+        }
+        let slice = &self.bytes[self.pos..self.pos + count];
+        self.pos += count;
+        return Ok(slice);
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        return Ok(self.take(1)?[0]);
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        let bytes = self.take(2)?;
+        return Ok(u16::from_be_bytes([bytes[0], bytes[1]]));
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        let bytes = self.take(4)?;
+        return Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::interrupt::KEYBOARD_VECTOR;
+
+    fn sample() -> Snapshot {
+        let mut registers = Registers::new();
+        registers.r[3] = 0xBEEF;
+        registers.pc = 0x3005;
+        registers.set_cc(true, false, false);
+
+        let mut memory = vec![0; MEMORY_WORDS];
+        memory[0x3000] = 0x1234;
+        memory[0x3001] = 0x5678;
+        memory[0xFFFF] = 0x0001;
+
+        let mut devices = Devices::new();
+        devices.keyboard_starved = true;
+
+        return Snapshot {
+            trap_mode: TrapMode::FastPath,
+            start: StartState {
+                psr: Some(0x8002),
+                usp: 0xF000,
+                ssp: 0x2FFF,
+            },
+            registers,
+            devices: devices,
+            os_segments: Some(vec![Segment::new(0x0025, vec![0x0400])]),
+            interrupts: vec![InterruptRequest::new(KEYBOARD_VECTOR, 4)],
+            input: vec!['a', 'é', '\n'],
+            memory,
+        };
+    }
+
+    #[test]
+    fn test_snapshot_round_trip() {
+        let snapshot = sample();
+        let bytes = snapshot.to_bytes().unwrap();
+
+        assert_eq!(&bytes[..8], &SNAPSHOT_MAGIC);
+        assert_eq!(Snapshot::from_bytes(&bytes), Ok(snapshot));
+
+        let mut bundled_os = sample();
+        bundled_os.os_segments = None;
+        bundled_os.start.psr = None;
+        let bytes = bundled_os.to_bytes().unwrap();
+        assert_eq!(Snapshot::from_bytes(&bytes), Ok(bundled_os));
+    }
+
+    #[test]
+    fn test_snapshot_rejects_overflowing_counts() {
+        let mut snapshot = sample();
+        snapshot.interrupts = vec![InterruptRequest::new(KEYBOARD_VECTOR, 4); 1 << 16];
+
+        assert!(snapshot.to_bytes().unwrap_err().contains("interrupts"));
+    }
+
+    #[test]
+    fn test_snapshot_rejects_bad_files() {
+        let bytes = sample().to_bytes().unwrap();
+
+        assert!(Snapshot::from_bytes(b"not a snapshot").is_err());
+        assert!(Snapshot::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+
+        let mut newer = bytes.clone();
+        newer[9] = SNAPSHOT_VERSION as u8 + 1;
+        assert!(
+            Snapshot::from_bytes(&newer)
+                .unwrap_err()
+                .contains("version")
+        );
+    }
+
+    #[test]
+    fn test_memory_runs() {
+        let mut memory = vec![0; 8];
+        memory[1] = 1;
+        memory[2] = 2;
+        memory[7] = 7;
+
+        assert_eq!(memory_runs(&memory), vec![(1, 3), (7, 8)]);
+    }
+}
@@ -0,0 +1,309 @@
+use super::delta::{Change, MemoryWrite, RegisterChange};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+#[cfg(feature = "serde")]
+use tsify::Tsify;
+
+/// One executed instruction, and what it changed.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize, Tsify))]
+pub struct TraceRecord {
+    /// The address the instruction was fetched from.
+    pub pc: u16,
+    /// The raw instruction word.
+    pub word: u16,
+    pub disassembly: String,
+    pub registers: Vec<RegisterChange>,
+    /// The condition codes (PSR[2:0]), if they changed.
+    pub cc: Option<Change>,
+    /// Memory writes, in the order they happened.
+    pub memory: Vec<MemoryWrite>,
+}
+
+/// How trace records are written out, one line per instruction.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize, Tsify))]
+pub enum TraceFormat {
+    /// `x3000  x1261  ADD R1, R1, #1    R1 x0000 -> x0001, CC Z -> P`
+    Text,
+    /// Comma-separated values, with a header line.
+    Csv,
+    /// One JSON object per line, with every value as a number.
+    JsonLines,
+}
+
+impl TraceFormat {
+    /// Parses a format name: `text`, `csv` or `jsonl`.
+    pub fn parse(name: &str) -> Result<TraceFormat, String> {
+        match name.to_ascii_lowercase().as_str() {
+            "text" => return Ok(TraceFormat::Text),
+            "csv" => return Ok(TraceFormat::Csv),
+            "jsonl" | "json" => return Ok(TraceFormat::JsonLines),
+            _ => {
+                return Err(format!(
+                    "Unknown trace format `{name}` (expected text, csv or jsonl)"
+                ));
+            }
+        }
+    }
+
+    /// The line that goes before the first record, if the format has one.
+    pub fn header(&self) -> Option<&'static str> {
+        match self {
+            TraceFormat::Csv => return Some("pc,word,instruction,registers,memory"),
+            TraceFormat::Text | TraceFormat::JsonLines => return None,
+        }
+    }
+
+    /// Formats a record as a single line, without the line break.
+    pub fn format(&self, record: &TraceRecord) -> String {
+        match self {
+            TraceFormat::Text => return format_text(record),
+            TraceFormat::Csv => return format_csv(record),
+            TraceFormat::JsonLines => return format_json(record),
+        }
+    }
+}
+
+/// Receives a record for every instruction the VM runs while it is set as the
+/// VM's `tracer`.
+pub trait TraceSink {
+    fn record(&mut self, record: &TraceRecord);
+
+    /// Called once the trace is over. Sinks that can fail report the first
+    /// error they ran into here.
+    fn finish(&mut self) -> Result<(), String> {
+        return Ok(());
+    }
+}
+
+/// Writes records to a file, or anything else that implements `Write`, in the
+/// given format.
+pub struct TraceWriter<W: Write> {
+    out: W,
+    format: TraceFormat,
+    started: bool,
+    error: Option<String>,
+}
+
+impl<W: Write> TraceWriter<W> {
+    pub fn new(out: W, format: TraceFormat) -> TraceWriter<W> {
+        TraceWriter {
+            out: out,
+            format: format,
+            started: false,
+            error: None,
+        }
+    }
+
+    fn write_line(&mut self, line: &str) {
+        if self.error.is_some() {
+            return;
+        }
+        if let Err(e) = writeln!(self.out, "{line}") {
+            self.error = Some(format!("Could not write the trace: {e}"));
+        }
+    }
+}
+
+impl<W: Write> TraceSink for TraceWriter<W> {
+    fn record(&mut self, record: &TraceRecord) {
+        if !self.started {
+            self.started = true;
+            if let Some(header) = self.format.header() {
+                self.write_line(header);
+            }
+        }
+        let line = self.format.format(record);
+        self.write_line(&line);
+    }
+
+    fn finish(&mut self) -> Result<(), String> {
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+        return self
+            .out
+            .flush()
+            .map_err(|e| format!("Could not write the trace: {e}"));
+    }
+}
+
+/// The condition codes in PSR[2:0] as a letter, or `-` if none are set.
+pub fn cc_str(cc: u16) -> &'static str {
+    match cc & 0b111 {
+        0b100 => "N",
+        0b010 => "Z",
+        0b001 => "P",
+        _ => "-",
+    }
+}
+
+/// The register and condition code changes, e.g. `R1 x0000 -> x0001, CC Z -> P`.
+fn register_changes(record: &TraceRecord) -> String {
+    let mut changes: Vec<String> = record
+        .registers
+        .iter()
+        .map(|c| format!("R{} x{:04X} -> x{:04X}", c.register, c.old, c.new))
+        .collect();
+    if let Some(cc) = record.cc {
+        changes.push(format!("CC {} -> {}", cc_str(cc.old), cc_str(cc.new)));
+    }
+    return changes.join(", ");
+}
+
+/// The memory writes, e.g. `M[x4000] x0000 -> x0001`.
+fn memory_changes(record: &TraceRecord) -> String {
+    let changes: Vec<String> = record
+        .memory
+        .iter()
+        .map(|w| format!("M[x{:04X}] x{:04X} -> x{:04X}", w.address, w.old, w.new))
+        .collect();
+    return changes.join(", ");
+}
+
+fn format_text(record: &TraceRecord) -> String {
+    let changes: Vec<String> = [register_changes(record), memory_changes(record)]
+        .into_iter()
+        .filter(|s| !s.is_empty())
+        .collect();
+    let line = format!(
+        "x{:04X}  x{:04X}  {:<20}  {}",
+        record.pc,
+        record.word,
+        record.disassembly,
+        changes.join(", ")
+    );
+    return line.trim_end().to_string();
+}
+
+fn format_csv(record: &TraceRecord) -> String {
+    return [
+        format!("x{:04X}", record.pc),
+        format!("x{:04X}", record.word),
+        csv_field(&record.disassembly),
+        csv_field(&register_changes(record)),
+        csv_field(&memory_changes(record)),
+    ]
+    .join(",");
+}
+
+/// Quotes a field if it holds a comma or a quote.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        return format!("\"{}\"", field.replace('"', "\"\""));
+    }
+    return field.to_string();
+}
+
+fn format_json(record: &TraceRecord) -> String {
+    let registers: Vec<String> = record
+        .registers
+        .iter()
+        .map(|c| {
+            format!(
+                "{{\"register\":{},\"old\":{},\"new\":{}}}",
+                c.register, c.old, c.new
+            )
+        })
+        .collect();
+    let cc = match record.cc {
+        Some(cc) => format!("{{\"old\":{},\"new\":{}}}", cc.old, cc.new),
+        None => "null".to_string(),
+    };
+    let memory: Vec<String> = record
+        .memory
+        .iter()
+        .map(|w| {
+            format!(
+                "{{\"address\":{},\"old\":{},\"new\":{}}}",
+                w.address, w.old, w.new
+            )
+        })
+        .collect();
+
+    return format!(
+        "{{\"pc\":{},\"word\":{},\"disassembly\":{},\"registers\":[{}],\"cc\":{},\"memory\":[{}]}}",
+        record.pc,
+        record.word,
+        json_string(&record.disassembly),
+        registers.join(","),
+        cc,
+        memory.join(",")
+    );
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    return out;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> TraceRecord {
+        return TraceRecord {
+            pc: 0x3000,
+            word: 0x1261,
+            disassembly: "ADD R1, R1, #1".to_string(),
+            registers: vec![RegisterChange {
+                register: 1,
+                old: 0,
+                new: 1,
+            }],
+            cc: Some(Change {
+                old: 0b010,
+                new: 0b001,
+            }),
+            memory: vec![MemoryWrite {
+                address: 0x4000,
+                old: 0,
+                new: 0x41,
+            }],
+        };
+    }
+
+    #[test]
+    fn test_trace_formats() {
+        let record = sample();
+
+        assert_eq!(
+            TraceFormat::Text.format(&record),
+            "x3000  x1261  ADD R1, R1, #1        R1 x0000 -> x0001, CC Z -> P, M[x4000] x0000 -> x0041"
+        );
+        assert_eq!(
+            TraceFormat::Csv.format(&record),
+            "x3000,x1261,\"ADD R1, R1, #1\",\"R1 x0000 -> x0001, CC Z -> P\",M[x4000] x0000 -> x0041"
+        );
+        assert_eq!(
+            TraceFormat::JsonLines.format(&record),
+            "{\"pc\":12288,\"word\":4705,\"disassembly\":\"ADD R1, R1, #1\",\
+             \"registers\":[{\"register\":1,\"old\":0,\"new\":1}],\"cc\":{\"old\":2,\"new\":1},\
+             \"memory\":[{\"address\":16384,\"old\":0,\"new\":65}]}"
+        );
+    }
+
+    #[test]
+    fn test_trace_writer() {
+        let mut writer = TraceWriter::new(vec![], TraceFormat::Csv);
+        writer.record(&sample());
+        writer.record(&sample());
+        assert_eq!(writer.finish(), Ok(()));
+
+        let text = String::from_utf8(writer.out).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], "pc,word,instruction,registers,memory");
+    }
+}
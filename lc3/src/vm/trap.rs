@@ -49,13 +49,26 @@ impl Trap {
         return InstructionResult::Ready;
     }
 
+    /// prints a string of packed characters pointed to by R0. Each word holds
+    /// two characters, the first in bits [7:0] and the second in bits [15:8]
     pub fn put_sp(
         &self,
-        _reg: &mut Registers,
-        _mem: &mut Memory,
-        _io: &mut Lc3IO,
+        reg: &mut Registers,
+        mem: &mut Memory,
+        io: &mut Lc3IO,
     ) -> InstructionResult {
-        unimplemented!();
+        let mut i = reg.get(0);
+
+        loop {
+            let word = mem.get(i);
+            for c in [word & 0xFF, word >> 8] {
+                if c == 0 {
+                    return InstructionResult::Ready;
+                }
+                io.put_char(c as u8 as char);
+            }
+            i += 1;
+        }
     }
 
     /// Prints a prompt string pointed to by R0,
@@ -82,10 +95,10 @@ impl Trap {
         }
     }
 
-    /// Triggers the halt register to signal to the VM to end the program
-    pub fn halt(&self, reg: &mut Registers) -> InstructionResult {
-        // println!("\nHALT TRIGGERED!");
-        reg.halt = true;
+    /// Stops the clock by clearing the clock enable bit of the MCR, like the OS
+    /// HALT routine does, which ends the program
+    pub fn halt(&self, mem: &mut Memory) -> InstructionResult {
+        mem.devices.stop_clock();
         return InstructionResult::Halted;
     }
 }
@@ -5,13 +5,24 @@ use tsify::Tsify;
 #[cfg(feature = "serde")]
 use wasm_bindgen::prelude::*;
 
-use super::instructions::{
-    Add, And, Br, Instruction, JmpRet, Jsr, Ld, Ldi, Ldr, Lea, Not, Rti, St, Sti, Str,
-};
-use super::memory::Memory;
-use super::registers::Registers;
-use super::trap::Trap;
+use super::breakpoint::{Breakpoint, Exception, StopReason, WatchHit};
+use super::callgraph::CallGraph;
+use super::coverage::Coverage;
+use super::decode::Op;
+use super::delta::{IoEvent, StateDelta};
+use super::history::{History, HistoryEntry};
+use super::instructions::{Instruction, execute};
+use super::interrupt::{self, InterruptController, InterruptRequest};
+use super::limits::{Limit, ResourceLimits, ResourceUsage};
+use super::memory::{Memory, USER_SPACE_START};
+use super::narrate::{self, Narration, Step};
+use super::os;
+use super::profile::Profile;
+use super::registers::{DEFAULT_SSP, DEFAULT_USP, Registers};
+use super::snapshot::Snapshot;
+use super::trace::{TraceRecord, TraceSink};
 use crate::io::Lc3IO;
+use crate::program::Program;
 use std::collections::HashMap;
 
 const CMD_SIZE: u8 = 16;
@@ -23,65 +34,579 @@ pub enum InstructionResult {
     AwaitingInput,
     Halted,
     PrivilegeModeException,
+    IllegalOpcodeException,
+    AccessControlViolation,
     RuntimePanic,
+    /// The instruction was not run, because the program used up one of its
+    /// `ResourceLimits`.
+    LimitExceeded(Limit),
+}
+
+/// How the VM services TRAP instructions.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize, Tsify))]
+#[cfg_attr(feature = "serde", tsify(into_wasm_abi, from_wasm_abi))]
+pub enum TrapMode {
+    /// The OS image is loaded at x0000, and every trap is dispatched through its
+    /// trap vector table to an assembly service routine.
+    Os,
+    /// No OS is loaded. GETC, OUT, PUTS, IN, PUTSP and HALT are run directly as
+    /// Rust code, and any other trap still goes through the trap vector table.
+    FastPath,
+}
+
+/// The processor state a program is started in when it is loaded.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize, Tsify))]
+#[cfg_attr(feature = "serde", tsify(into_wasm_abi, from_wasm_abi))]
+pub struct StartState {
+    /// The PSR to start with. If `None`, the program runs in user mode when it
+    /// is loaded in user space, and in supervisor mode otherwise.
+    pub psr: Option<u16>,
+    /// Initial user stack pointer, which is R6 whenever the program starts in user mode.
+    pub usp: u16,
+    /// Initial supervisor stack pointer, used for traps, interrupts and exceptions.
+    pub ssp: u16,
+}
+
+impl StartState {
+    pub fn new() -> StartState {
+        StartState {
+            psr: None,
+            usp: DEFAULT_USP,
+            ssp: DEFAULT_SSP,
+        }
+    }
 }
 
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize, Tsify))]
 #[cfg_attr(feature = "serde", tsify(into_wasm_abi, from_wasm_abi))]
 pub struct VM {
+    /// Replacements for built-in instructions, keyed by opcode. Any opcode not
+    /// in here runs through the decoded instruction path, which is the default.
     pub instructions: HashMap<u8, Box<dyn Instruction>>,
     pub registers: Registers,
     pub memory: Memory,
+    pub interrupts: InterruptController,
+    pub trap_mode: TrapMode,
+    /// Parts of a user-supplied OS that replaces the bundled one.
+    pub os_segments: Option<Vec<Program>>,
+    pub start: StartState,
     pub io: Lc3IO,
+    /// Undo history for `step_back`. Off until a limit is set, since recording
+    /// every step costs time that a batch run doesn't need.
+    pub history: History,
+    /// Caps on instructions, output, input and time. Off by default.
+    pub limits: ResourceLimits,
+    /// What the program has used since it was loaded, counted against `limits`.
+    pub usage: ResourceUsage,
+    /// Addresses `run_until_event` stops at. Watchpoints live in `memory`.
+    pub breakpoints: HashMap<u16, Breakpoint>,
+    /// Receives a `TraceRecord` for every instruction that runs, if set.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub tracer: Option<Box<dyn TraceSink>>,
+    /// Counts of executed instructions and memory accesses, if set. Loading a
+    /// program or resetting starts the counts over.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub profile: Option<Profile>,
+    /// The dynamic call tree of the program, if set.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub call_graph: Option<CallGraph>,
+    /// Which instructions ran and which way branches went, if set. Loading a
+    /// program or resetting starts it over.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub coverage: Option<Coverage>,
+    /// The registers before the last step, and the instruction it ran and the
+    /// address it was fetched from, for `last_delta` and `last_trace`.
+    last_before: Registers,
+    last_ir: Option<u16>,
+    last_pc: u16,
+    last_exception: Option<Exception>,
+    /// The program's registers when the machine last went from user mode into a
+    /// trap, interrupt or exception routine, with the PC it returns to. Cleared
+    /// once it is back in user mode.
+    caller: Option<Registers>,
 }
 
 impl VM {
+    /// Creates a VM that has booted the bundled OS.
     pub fn new(io: Lc3IO) -> VM {
-        let mut ins: HashMap<u8, Box<dyn Instruction>> = HashMap::new();
-
-        ins.insert(0, Box::new(Br {}));
-        ins.insert(1, Box::new(Add {}));
-        ins.insert(2, Box::new(Ld {}));
-        ins.insert(3, Box::new(St {}));
-        ins.insert(4, Box::new(Jsr {}));
-        ins.insert(5, Box::new(And {}));
-        ins.insert(6, Box::new(Ldr {}));
-        ins.insert(7, Box::new(Str {}));
-        ins.insert(8, Box::new(Rti {}));
-        ins.insert(9, Box::new(Not {}));
-        ins.insert(10, Box::new(Ldi {}));
-        ins.insert(11, Box::new(Sti {}));
-        ins.insert(12, Box::new(JmpRet {}));
-        // ins.insert(13, Box::new(Reserved {}));
-        ins.insert(14, Box::new(Lea {}));
-        ins.insert(15, Box::new(Trap {}));
-
-        VM {
-            instructions: ins,
+        return VM::with_trap_mode(io, TrapMode::Os);
+    }
+
+    pub fn with_trap_mode(io: Lc3IO, trap_mode: TrapMode) -> VM {
+        let mut vm = VM {
+            instructions: HashMap::new(),
             registers: Registers::new(),
             memory: Memory::new(),
+            interrupts: InterruptController::new(),
+            trap_mode: trap_mode,
+            os_segments: None,
+            start: StartState::new(),
             io: io,
+            history: History::new(0),
+            limits: ResourceLimits::new(),
+            usage: ResourceUsage::new(),
+            breakpoints: HashMap::new(),
+            tracer: None,
+            profile: None,
+            call_graph: None,
+            coverage: None,
+            last_before: Registers::new(),
+            last_ir: None,
+            last_pc: 0,
+            last_exception: None,
+            caller: None,
+        };
+        vm.boot();
+
+        return vm;
+    }
+
+    /// Loads the OS image, if this VM uses one.
+    fn boot(&mut self) {
+        if self.trap_mode != TrapMode::Os {
+            return;
+        }
+
+        let images = match &self.os_segments {
+            Some(images) => images.iter().collect(),
+            None => vec![os::image()],
+        };
+        for image in images {
+            for segment in &image.segments {
+                if let Err(msg) = self.memory.load_segment(segment) {
+                    self.io.print_error(msg);
+                }
+            }
+        }
+    }
+
+    /// Replaces the bundled OS with user-assembled programs, such as a trap
+    /// vector table and the service routines it points to. Every segment must
+    /// fit in system space (x0000-x2FFF). The machine is reset and then boots
+    /// the new OS, and from then on every TRAP is dispatched through its table.
+    pub fn load_os(&mut self, segments: Vec<Program>) -> Result<(), String> {
+        for segment in segments.iter().flat_map(|program| &program.segments) {
+            if segment.end() > USER_SPACE_START as usize {
+                return Err(format!(
+                    "The OS segment at x{:04X} does not fit in system space (x0000-x{:04X})",
+                    segment.origin,
+                    USER_SPACE_START - 1,
+                ));
+            }
+        }
+
+        self.trap_mode = TrapMode::Os;
+        self.os_segments = Some(segments);
+        self.reset();
+
+        return Ok(());
+    }
+
+    /// Puts the machine back into its power-on state, with the OS reloaded.
+    pub fn reset(&mut self) {
+        let decode_cache = self.memory.has_decode_cache();
+        let watchpoints = std::mem::take(&mut self.memory.watchpoints);
+        self.registers = Registers::new();
+        self.memory = Memory::new();
+        self.memory.set_decode_cache(decode_cache);
+        self.memory.watchpoints = watchpoints;
+        self.interrupts = InterruptController::new();
+        self.history.clear();
+        self.usage = ResourceUsage::new();
+        self.restart_profiling();
+        self.forget_last_step();
+        self.boot();
+    }
+
+    /// Loads a program and points the PC at its entry point, starting the
+    /// processor in the state described by `self.start`. R6 is set to whichever
+    /// stack pointer matches the starting privilege mode. Nothing is loaded if
+    /// any segment runs past xFFFF.
+    pub fn load(&mut self, program: &Program) -> Result<(), String> {
+        for segment in &program.segments {
+            segment.verify_fits_in_memory()?;
+        }
+        for segment in &program.segments {
+            self.memory.load_segment(segment)?;
+        }
+
+        let entry = program.entry;
+        self.history.clear();
+        self.caller = None;
+        self.usage = ResourceUsage::new();
+        self.restart_profiling();
+        self.registers.pc = entry;
+        self.registers.saved_usp = self.start.usp;
+        self.registers.saved_ssp = self.start.ssp;
+
+        match self.start.psr {
+            Some(psr) => self.registers.psr = psr,
+            None => self.registers.set_user_mode(!Memory::is_privileged(entry)),
+        }
+
+        if self.registers.is_user_mode() {
+            self.registers.r[6] = self.start.usp;
+        } else {
+            self.registers.r[6] = self.start.ssp;
         }
+        return Ok(());
     }
 
-    pub fn run(&mut self, file: Vec<u16>) {
-        self.registers.pc = file[0];
+    /// Loads `program` and runs it until it stops. Fails if it cannot be loaded.
+    pub fn run(&mut self, program: &Program) -> Result<StopReason, String> {
+        self.load(program)?;
+        return Ok(self.resume());
+    }
+
+    /// Runs the loaded program from wherever the PC is until it halts, until it
+    /// waits for input that the IO target will never give, or until it runs
+    /// into one of its `limits`. Breakpoints and watchpoints are ignored.
+    pub fn resume(&mut self) -> StopReason {
+        while !self.is_halted() {
+            match self.run_single_command() {
+                // The program is spinning on KBSR, so block until a key is typed.
+                InstructionResult::AwaitingInput if self.trap_mode == TrapMode::Os => {
+                    if !self.memory.devices.wait_for_keyboard(&mut self.io) {
+                        return StopReason::AwaitingInput;
+                    }
+                }
+                InstructionResult::LimitExceeded(limit) => return StopReason::LimitExceeded(limit),
+                _ => {}
+            }
+        }
+        return StopReason::Halted;
+    }
+
+    /// Runs until something a debugger would want to stop for, and says what it
+    /// was. A breakpoint stops the machine before the instruction at that address
+    /// runs, except at the PC it starts from, so calling this again continues
+    /// past it. A watchpoint stops the machine after the instruction that made the
+    /// access. `max_steps` limits how many instructions are run.
+    pub fn run_until_event(&mut self, max_steps: Option<u64>) -> StopReason {
+        let mut steps = 0;
+
+        loop {
+            if self.is_halted() {
+                return StopReason::Halted;
+            }
+            if steps > 0 {
+                let pc = self.registers.pc;
+                if let Some(bp) = self.breakpoints.get_mut(&pc) {
+                    if bp.hit(&self.registers, &self.memory) {
+                        return StopReason::Breakpoint(pc);
+                    }
+                }
+            }
+            if max_steps.is_some_and(|max| steps >= max) {
+                return StopReason::StepLimit;
+            }
 
-        self.memory.load_file(file);
+            let result = self.run_single_command();
+            steps += 1;
 
-        while self.registers.halt != true {
-            // print!("\n{:#06x}\t : ", self.registers.pc);
-            // print!("\n{:#04}\t : ", self.registers.pc);
+            if let Some(hit) = self.triggered_watchpoint() {
+                return StopReason::Watchpoint(hit);
+            }
 
-            self.run_single_command();
+            match result {
+                InstructionResult::Halted | InstructionResult::RuntimePanic => {
+                    return StopReason::Halted;
+                }
+                InstructionResult::AwaitingInput => return StopReason::AwaitingInput,
+                InstructionResult::PrivilegeModeException => {
+                    return StopReason::Exception(Exception::PrivilegeMode);
+                }
+                InstructionResult::IllegalOpcodeException => {
+                    return StopReason::Exception(Exception::IllegalOpcode);
+                }
+                InstructionResult::AccessControlViolation => {
+                    return StopReason::Exception(Exception::AccessControlViolation);
+                }
+                InstructionResult::LimitExceeded(limit) => {
+                    return StopReason::LimitExceeded(limit);
+                }
+                InstructionResult::Ready => {}
+            }
         }
     }
 
+    /// The first watched access of the last step whose watchpoint's condition
+    /// holds now that the step is done.
+    fn triggered_watchpoint(&self) -> Option<WatchHit> {
+        return self.memory.watch_hits().iter().copied().find(|hit| {
+            self.memory.watchpoints.iter().any(|wp| {
+                wp.matches(hit.address, hit.access)
+                    && wp
+                        .condition
+                        .as_ref()
+                        .is_none_or(|c| c.is_true(&self.registers, &self.memory))
+            })
+        });
+    }
+
+    /// Runs one instruction (after servicing any pending interrupt), and records
+    /// everything it changed in `last_delta`.
     pub fn run_single_command(&mut self) -> InstructionResult {
-        if self.registers.halt == true {
+        self.last_before = self.registers;
+        self.memory.start_journal();
+        self.io.start_journal();
+
+        if self.is_halted() {
+            self.last_ir = None;
+            self.memory.stop_journal();
+            self.io.stop_journal();
             return InstructionResult::Halted;
         }
+        if let Some(limit) = self.usage.exceeded(&self.limits) {
+            self.last_ir = None;
+            self.memory.stop_journal();
+            self.io.stop_journal();
+            return InstructionResult::LimitExceeded(limit);
+        }
+
+        let devices = self.memory.devices;
+        let interrupts = self.history.is_recording().then(|| self.interrupts.clone());
+
+        let result = self.step();
+        self.last_ir = Some(self.registers.ir);
+        self.last_exception = match result {
+            InstructionResult::PrivilegeModeException => Some(Exception::PrivilegeMode),
+            InstructionResult::IllegalOpcodeException => Some(Exception::IllegalOpcode),
+            InstructionResult::AccessControlViolation => Some(Exception::AccessControlViolation),
+            _ => None,
+        };
+        if self.last_before.is_user_mode() && !self.registers.is_user_mode() {
+            // the PC it returns to was pushed just below the PSR on the supervisor stack
+            let mut caller = self.last_before;
+            let return_pc = self.memory.get(caller.saved_ssp.wrapping_sub(2));
+            if return_pc != caller.pc {
+                // a trap or exception, rather than an interrupt taken before the fetch
+                caller.ir = self.registers.ir;
+            }
+            caller.pc = return_pc;
+            self.caller = Some(caller);
+        } else if self.registers.is_user_mode() {
+            self.caller = None;
+        }
+        self.memory.stop_journal();
+        self.io.stop_journal();
+        self.usage.record(self.io.journal());
+
+        if let Some(profile) = &mut self.profile {
+            profile.record(
+                self.last_pc,
+                self.registers.ir,
+                self.memory.reads(),
+                self.memory.journal(),
+            );
+        }
+        if let Some(call_graph) = &mut self.call_graph {
+            call_graph.record(
+                self.last_pc,
+                self.registers.ir,
+                self.registers.pc,
+                self.last_exception.is_some(),
+            );
+        }
+        if let Some(coverage) = &mut self.coverage {
+            coverage.record(self.last_pc, self.registers.ir, self.registers.pc);
+        }
+        // only build the record (and its disassembly) when something wants it
+        let record = self.tracer.is_some().then(|| self.last_trace()).flatten();
+        if let (Some(tracer), Some(record)) = (self.tracer.as_mut(), record) {
+            tracer.record(&record);
+        }
+
+        if let Some(interrupts) = interrupts {
+            let input = self
+                .io
+                .journal()
+                .iter()
+                .filter_map(|event| match event {
+                    IoEvent::Input(c) => Some(*c),
+                    IoEvent::Output(_) => None,
+                })
+                .collect();
+
+            self.history.push(HistoryEntry {
+                registers: self.last_before,
+                devices: devices,
+                interrupts: interrupts,
+                memory: self.memory.journal().to_vec(),
+                input: input,
+            });
+        }
+
+        return result;
+    }
+
+    /// Undoes the last step: registers, PSR, devices, pending interrupts and
+    /// memory are put back, and any input it consumed will be read again.
+    /// Output that was already displayed stays displayed. Returns `false` if
+    /// there is no history left to undo.
+    pub fn step_back(&mut self) -> bool {
+        let Some(entry) = self.history.pop() else {
+            return false;
+        };
+
+        for write in entry.memory.iter().rev() {
+            self.memory.set(write.address, write.old);
+        }
+        for c in entry.input.iter().rev() {
+            self.io.unread(*c);
+        }
+
+        self.registers = entry.registers;
+        self.memory.devices = entry.devices;
+        self.interrupts = entry.interrupts;
+        self.forget_last_step();
+
+        return true;
+    }
+
+    /// Steps backward until the PC is `address`, the point just before the
+    /// instruction there last ran. Returns `false` if the history ran out first.
+    pub fn run_back_to(&mut self, address: u16) -> bool {
+        while self.step_back() {
+            if self.registers.pc == address {
+                return true;
+            }
+        }
+        return false;
+    }
+
+    /// Saves the whole machine (registers, memory, devices, pending interrupts,
+    /// input that has not been read yet, and the OS and start state it resets
+    /// to) in the snapshot format described in `snapshot.rs`, ready to be
+    /// written to a file.
+    pub fn save_snapshot(&self) -> Result<Vec<u8>, String> {
+        let os_segments = self.os_segments.as_ref().map(|images| {
+            images
+                .iter()
+                .flat_map(|image| image.segments.iter().cloned())
+                .collect()
+        });
+        let snapshot = Snapshot {
+            trap_mode: self.trap_mode,
+            start: self.start,
+            registers: self.registers,
+            devices: self.memory.devices,
+            os_segments: os_segments,
+            interrupts: self.interrupts.pending().to_vec(),
+            input: self.io.pending_input(),
+            memory: self.memory.words().to_vec(),
+        };
+        return snapshot.to_bytes();
+    }
+
+    /// Restores a machine saved with `save_snapshot`, so that running it carries
+    /// on exactly where the saved machine stopped. The saved input is read before
+    /// anything the IO target provides. The undo history is cleared.
+    pub fn load_snapshot(&mut self, bytes: &[u8]) -> Result<(), String> {
+        let snapshot = Snapshot::from_bytes(bytes)?;
+
+        self.trap_mode = snapshot.trap_mode;
+        self.start = snapshot.start;
+        self.os_segments = snapshot.os_segments.map(|segments| {
+            let mut image = Program::new(0, vec![]);
+            image.segments = segments;
+            vec![image]
+        });
+        self.registers = snapshot.registers;
+        self.memory.set_words(&snapshot.memory);
+        self.memory.devices = snapshot.devices;
+        self.interrupts.clear();
+        for request in snapshot.interrupts {
+            self.interrupts.raise(request.vector, request.priority);
+        }
+        self.io.set_pending_input(&snapshot.input);
+        self.history.clear();
+        self.usage = ResourceUsage::new();
+        self.forget_last_step();
+
+        return Ok(());
+    }
+
+    /// What the last call to `run_single_command` changed. Register changes are
+    /// measured against the registers as they are now.
+    pub fn last_delta(&self) -> StateDelta {
+        let mut delta = StateDelta::between(&self.last_before, &self.registers);
+        delta.ir = self.last_ir;
+        delta.memory = self.memory.journal().to_vec();
+        delta.io = self.io.journal().to_vec();
+        return delta;
+    }
+
+    /// The trace record for the instruction the last call to
+    /// `run_single_command` ran, if it ran one.
+    pub fn last_trace(&self) -> Option<TraceRecord> {
+        let word = self.last_ir?;
+        let delta = self.last_delta();
+        return Some(TraceRecord {
+            pc: self.last_pc,
+            word: word,
+            disassembly: Op::decode(word).disassemble(self.last_pc),
+            registers: delta.registers,
+            cc: delta.cc,
+            memory: delta.memory,
+        });
+    }
+
+    /// A plain-English explanation of the instruction the last call to
+    /// `run_single_command` ran, if it ran one.
+    pub fn narrate_last_step(&self) -> Option<Narration> {
+        let word = self.last_ir?;
+        let delta = self.last_delta();
+        return Some(narrate::narrate(&Step {
+            pc: self.last_pc,
+            word: word,
+            before: &self.last_before,
+            after: &self.registers,
+            memory: &self.memory,
+            delta: &delta,
+            exception: self.last_exception,
+        }));
+    }
+
+    /// Tells the tracer that the trace is over, and hands back the first error
+    /// it ran into. The tracer stays set.
+    pub fn finish_trace(&mut self) -> Result<(), String> {
+        match &mut self.tracer {
+            Some(tracer) => return tracer.finish(),
+            None => return Ok(()),
+        }
+    }
+
+    fn restart_profiling(&mut self) {
+        if self.profile.is_some() {
+            self.profile = Some(Profile::new());
+        }
+        if let Some(call_graph) = &mut self.call_graph {
+            call_graph.restart();
+        }
+        if self.coverage.is_some() {
+            self.coverage = Some(Coverage::new());
+        }
+    }
+
+    fn forget_last_step(&mut self) {
+        self.last_before = self.registers;
+        self.caller = None;
+        self.last_ir = None;
+        self.memory.start_journal();
+        self.memory.stop_journal();
+        self.io.start_journal();
+        self.io.stop_journal();
+    }
+
+    fn step(&mut self) -> InstructionResult {
+        self.service_interrupts();
+
         let cmd = self.memory.get(self.registers.pc);
+        self.registers.ir = cmd;
+        self.last_pc = self.registers.pc;
 
         if self.registers.pc == u16::MAX {
             // throw error for trying to increment PC past xFFFF
@@ -89,20 +614,136 @@ impl VM {
                 "{} {}",
                 "Overflow Error:", "The PC attempted to increment past maximum xFFFF",
             ));
-            self.registers.halt = true;
+            self.memory.devices.stop_clock();
             return InstructionResult::RuntimePanic;
         }
         self.registers.pc += 1;
+        self.memory.devices.keyboard_starved = false;
+
+        if self.registers.is_user_mode() && Memory::is_privileged(self.registers.pc - 1) {
+            return self.raise_exception(InstructionResult::AccessControlViolation);
+        }
 
-        let opcode: u16 = cmd >> OPCODE_DELTA;
-        let value: u16 = cmd - (opcode << OPCODE_DELTA);
-        return self.instructions[&(opcode as u8)].exe(
-            value,
+        let result = self.execute(cmd, self.registers.pc - 1);
+
+        match result {
+            InstructionResult::PrivilegeModeException
+            | InstructionResult::IllegalOpcodeException
+            | InstructionResult::AccessControlViolation => return self.raise_exception(result),
+            _ => {}
+        }
+
+        if self.is_halted() {
+            return InstructionResult::Halted;
+        }
+        if self.memory.devices.keyboard_starved {
+            return InstructionResult::AwaitingInput;
+        }
+
+        return result;
+    }
+
+    /// Runs the instruction `cmd`, fetched from `address`. Overridden opcodes are
+    /// dispatched to their `Instruction`, and everything else is decoded (or taken
+    /// from the decode cache) and run by a `match`.
+    fn execute(&mut self, cmd: u16, address: u16) -> InstructionResult {
+        if !self.instructions.is_empty() {
+            let opcode = (cmd >> OPCODE_DELTA) as u8;
+            if let Some(ins) = self.instructions.get(&opcode) {
+                let value = cmd & ((1 << OPCODE_DELTA) - 1);
+                return ins.exe(value, &mut self.registers, &mut self.memory, &mut self.io);
+            }
+        }
+
+        let op = self.memory.decode(address);
+        return execute(
+            op,
+            self.trap_mode,
             &mut self.registers,
             &mut self.memory,
             &mut self.io,
         );
     }
+
+    /// Caches decoded instructions per address, so a loop only decodes its body
+    /// once. Writing to an address drops its cached instruction.
+    pub fn set_decode_cache(&mut self, enabled: bool) {
+        self.memory.set_decode_cache(enabled);
+    }
+
+    /// The machine is halted once software clears the clock enable bit of the MCR.
+    pub fn is_halted(&self) -> bool {
+        return !self.memory.devices.clock_enabled();
+    }
+
+    /// The registers as the program left them. While a routine entered from
+    /// user mode is running, such as HALT once it has stopped the clock, these
+    /// are the program's registers from when it was entered. Otherwise they are
+    /// the current registers.
+    pub fn program_registers(&self) -> Registers {
+        return self.caller.unwrap_or(self.registers);
+    }
+
+    /// Dispatches an exception through the interrupt vector table. If no service
+    /// routine has been installed for it, the error is reported and the machine halts.
+    fn raise_exception(&mut self, exception: InstructionResult) -> InstructionResult {
+        let (vector, msg) = match exception {
+            InstructionResult::PrivilegeModeException => (
+                interrupt::PRIVILEGE_MODE_VECTOR,
+                "Privilege Mode Exception: A privileged instruction was executed in user mode at",
+            ),
+            InstructionResult::IllegalOpcodeException => (
+                interrupt::ILLEGAL_OPCODE_VECTOR,
+                "Illegal Opcode Exception: The reserved opcode was executed at",
+            ),
+            InstructionResult::AccessControlViolation => (
+                interrupt::ACCESS_CONTROL_VECTOR,
+                "Access Control Violation: User mode accessed system or device memory at",
+            ),
+            _ => unreachable!(),
+        };
+
+        let vector_address = interrupt::vector_address(vector);
+        if self.memory.get(vector_address) == 0 {
+            self.io.print_error(format!(
+                "{} {:#06x}",
+                msg,
+                self.registers.pc.wrapping_sub(1)
+            ));
+            self.memory.devices.stop_clock();
+            return exception;
+        }
+
+        interrupt::initiate(&mut self.registers, &mut self.memory, vector_address, None);
+        return exception;
+    }
+
+    /// Checks for an interrupt that can preempt the running program before the
+    /// next fetch, and if there is one, enters its service routine.
+    fn service_interrupts(&mut self) {
+        let devices = &mut self.memory.devices;
+        if devices.keyboard_interrupt_enabled() {
+            devices.poll_keyboard(&mut self.io);
+        }
+
+        let keyboard = if devices.keyboard_interrupt_requested() {
+            Some(InterruptRequest::keyboard())
+        } else {
+            None
+        };
+
+        if let Some(request) = self
+            .interrupts
+            .take_next(self.registers.priority(), keyboard)
+        {
+            interrupt::initiate(
+                &mut self.registers,
+                &mut self.memory,
+                interrupt::vector_address(request.vector),
+                Some(request.priority),
+            );
+        }
+    }
 }
 
 #[cfg(test)]
@@ -110,8 +751,31 @@ mod tests {
     use super::*;
     use crate::asm::asm::Asm;
     use crate::io::*;
+    use crate::program::Segment;
+    use crate::vm::breakpoint::{Access, WatchHit, Watchpoint};
+    use crate::vm::delta::{Change, IoEvent, MemoryWrite, RegisterChange};
+    use crate::vm::device::KBDR;
+    use crate::vm::expr::Expr;
+    use crate::vm::history::DEFAULT_HISTORY_LIMIT;
+    use std::cell::RefCell;
+    use std::rc::Rc;
 
     fn run_vm(file: &str) -> VM {
+        return run_vm_with_input(file, "");
+    }
+
+    fn run_vm_with_cache(file: &str) -> VM {
+        let mut vm = VM::with_trap_mode(Lc3IO::new(Box::new(DebugIO::new())), TrapMode::FastPath);
+        vm.set_decode_cache(true);
+
+        let mut asm = Asm::new(Lc3IO::new(Box::new(DebugIO::new())));
+        let file = format!(".orig x0000\n{file}\nhalt\n.end");
+        vm.run(&asm.run(file).unwrap()).unwrap();
+
+        return vm;
+    }
+
+    fn run_vm_with_input(file: &str, input: &str) -> VM {
         let file = format!(
             ".orig x0000
 
@@ -128,16 +792,27 @@ mod tests {
             .run(file.to_string())
             .expect("Errors occurred during the assembly process, so the VM could not be run");
 
-        if binary_file.len() == 0 {
+        if binary_file.is_empty() {
             panic!();
         }
 
         println!("\nBinary file:");
 
-        let io = Lc3IO::new(Box::new(DebugIO::new()));
-        let mut vm = VM::new(io);
+        // these programs live at x0000, where the OS would be
+        let io = Lc3IO::new(Box::new(DebugIO::with_input(input)));
+        let mut vm = VM::with_trap_mode(io, TrapMode::FastPath);
+
+        vm.run(&binary_file).unwrap();
+
+        return vm;
+    }
+
+    fn run_os_vm(file: &str, input: &str) -> VM {
+        let mut asm = Asm::new(Lc3IO::new(Box::new(DebugIO::new())));
+        let binary_file = asm.run(file.to_string()).unwrap();
 
-        vm.run(binary_file);
+        let mut vm = VM::new(Lc3IO::new(Box::new(DebugIO::with_input(input))));
+        vm.run(&binary_file).unwrap();
 
         return vm;
     }
@@ -169,9 +844,9 @@ mod tests {
 
         assert_eq!(vm.registers.r[3], 5);
         assert_eq!(vm.registers.r[5], 4);
-        assert!(!vm.registers.n);
-        assert!(!vm.registers.z);
-        assert!(vm.registers.p);
+        assert!(!vm.registers.n());
+        assert!(!vm.registers.z());
+        assert!(vm.registers.p());
 
         let vm = run_vm("
             add r1, r1, #15 ; since every register should be set to 0 by default, this should always just put 10 in r1
@@ -274,14 +949,960 @@ start   ld r1, max
         assert_eq!(vm.registers.r[1], u16::MAX);
     }
 
+    #[test]
+    fn test_rti_returns_to_user_program() {
+        let vm = run_vm(
+            "
+        lea r6, stack
+        ld r0, user_pc
+        str r0, r6, #0
+        rti
+stack   .fill #0
+        .fill x8001
+user_pc .fill x3000
+        .blkw #12281    ; user code has to live in user space, at x3000
+user    add r2, r2, #7
+        ",
+        );
+
+        assert_eq!(vm.registers.r[2], 7);
+        assert!(vm.registers.is_user_mode());
+        assert_eq!(vm.registers.r[6], 0xFE00);
+    }
+
+    #[test]
+    fn test_polling_keyboard_and_display() {
+        let vm = run_vm_with_input(
+            "
+poll    ldi r1, kbsr
+        brzp poll
+        ldi r0, kbdr
+echo    ldi r1, dsr
+        brzp echo
+        sti r0, ddr
+        br done
+kbsr    .fill xFE00
+kbdr    .fill xFE02
+dsr     .fill xFE04
+ddr     .fill xFE06
+done    add r0, r0, #0
+        ",
+            "k",
+        );
+
+        assert_eq!(vm.registers.r[0], 'k' as u16);
+        assert_eq!(vm.memory.devices.ddr, 'k' as u16);
+        assert_eq!(vm.memory.get(0xFE00), 0);
+    }
+
+    #[test]
+    fn test_keyboard_interrupt() {
+        let vm = run_vm_with_input(
+            "
+        ld r6, ssp
+        lea r0, handler
+        sti r0, kb_vec
+        ld r0, ie
+        sti r0, kbsr
+wait    ld r1, flag
+        brz wait
+        br done
+handler ldi r2, kbdr
+        st r2, char
+        and r1, r1, #0
+        add r1, r1, #1
+        st r1, flag
+        rti
+ssp     .fill x2FF0
+kb_vec  .fill x0180
+ie      .fill x4000
+kbsr    .fill xFE00
+kbdr    .fill xFE02
+flag    .fill #0
+char    .fill #0
+done    ld r3, char
+        ",
+            "q",
+        );
+
+        assert_eq!(vm.registers.r[3], 'q' as u16);
+        assert_eq!(vm.registers.r[6], 0x2FF0);
+        assert_eq!(vm.registers.priority(), 0);
+    }
+
+    fn user_mode_vm(program: &[u16]) -> VM {
+        let io = Lc3IO::new(Box::new(DebugIO::new()));
+        let mut vm = VM::with_trap_mode(io, TrapMode::FastPath);
+        vm.registers.psr = 0x8002;
+        vm.registers.pc = 0x3000;
+        for (i, word) in program.iter().enumerate() {
+            vm.memory.set(0x3000 + i as u16, *word);
+        }
+        return vm;
+    }
+
+    #[test]
+    fn test_illegal_opcode_exception() {
+        let mut vm = user_mode_vm(&[0xD000]);
+        vm.memory.set(0x0101, 0x1000);
+
+        let result = vm.run_single_command();
+
+        assert!(matches!(result, InstructionResult::IllegalOpcodeException));
+        assert_eq!(vm.registers.pc, 0x1000);
+        assert!(!vm.registers.is_user_mode());
+        assert_eq!(vm.registers.r[6], 0x2FFE);
+        assert_eq!(vm.memory.get(0x2FFE), 0x3001);
+        assert_eq!(vm.memory.get(0x2FFF), 0x8002);
+    }
+
+    #[test]
+    fn test_access_control_violation() {
+        // LDR R0, R1, #0 with R1 pointing at KBSR
+        let mut vm = user_mode_vm(&[0b0110_000_001_000000]);
+        vm.registers.r[1] = 0xFE00;
+        vm.memory.set(0x0102, 0x1100);
+
+        let result = vm.run_single_command();
+
+        assert!(matches!(result, InstructionResult::AccessControlViolation));
+        assert_eq!(vm.registers.pc, 0x1100);
+        assert_eq!(vm.registers.r[0], 0);
+
+        // fetching from system space in user mode is also a violation
+        let mut vm = user_mode_vm(&[]);
+        vm.registers.pc = 0x0200;
+        vm.memory.set(0x0102, 0x1100);
+
+        let result = vm.run_single_command();
+        assert!(matches!(result, InstructionResult::AccessControlViolation));
+        assert_eq!(vm.memory.get(0x2FFE), 0x0201);
+    }
+
+    #[test]
+    fn test_exception_without_handler_halts() {
+        let mut vm = user_mode_vm(&[0b1000_0000_0000_0000]); // RTI
+
+        let result = vm.run_single_command();
+
+        assert!(matches!(result, InstructionResult::PrivilegeModeException));
+        assert!(vm.is_halted());
+    }
+
+    #[test]
+    fn test_trap_through_vector_table() {
+        let mut vm = user_mode_vm(&[0xF026]);
+        vm.memory.set(0x0026, 0x0500);
+        vm.memory.set(0x0500, 0b1000_0000_0000_0000); // RTI
+
+        vm.run_single_command();
+        assert_eq!(vm.registers.pc, 0x0500);
+        assert!(!vm.registers.is_user_mode());
+
+        vm.run_single_command();
+        assert_eq!(vm.registers.pc, 0x3001);
+        assert!(vm.registers.is_user_mode());
+    }
+
+    #[test]
+    fn test_unknown_trap_vector_halts() {
+        // the fast path still goes through the table for traps it does not service
+        let vm = run_vm(
+            "
+        .fill xF030
+        add r2, r2, #1  ; never runs
+        ",
+        );
+        assert!(vm.is_halted());
+        assert_eq!(vm.registers.r[2], 0);
+        assert_eq!(vm.registers.pc, 0x0001);
+
+        let vm = run_os_vm(
+            "
+.orig x3000
+        .fill xF030
+        add r2, r2, #1  ; never runs
+        halt
+.end
+        ",
+            "",
+        );
+        assert!(vm.is_halted());
+        assert_eq!(vm.registers.r[2], 0);
+        assert_eq!(vm.registers.pc, 0x3001);
+        assert!(vm.registers.is_user_mode());
+    }
+
+    #[test]
+    fn test_clearing_mcr_halts_vm() {
+        let vm = run_vm(
+            "
+        ldi r0, mcr
+        ld r1, clock_off
+        and r0, r0, r1
+        sti r0, mcr
+        add r2, r2, #1  ; never runs
+mcr       .fill xFFFE
+clock_off .fill x7FFF
+        ",
+        );
+
+        assert!(vm.is_halted());
+        assert_eq!(vm.registers.r[2], 0);
+        assert_eq!(vm.registers.pc, 0x0004);
+    }
+
+    #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+    struct Double;
+
+    #[cfg_attr(feature = "serde", typetag::serde)]
+    impl Instruction for Double {
+        fn exe(
+            &self,
+            value: u16,
+            reg: &mut Registers,
+            _mem: &mut Memory,
+            _io: &mut Lc3IO,
+        ) -> InstructionResult {
+            let r = (value >> 9) as usize;
+            reg.r[r] *= 2;
+            return InstructionResult::Ready;
+        }
+    }
+
+    #[test]
+    fn test_instruction_override() {
+        let mut vm = user_mode_vm(&[0b1101_011_000000000, 0b0001_011_011_1_00001]);
+        vm.instructions.insert(13, Box::new(Double));
+        vm.registers.r[3] = 4;
+
+        vm.run_single_command();
+        vm.run_single_command();
+        assert_eq!(vm.registers.r[3], 9);
+    }
+
+    #[test]
+    fn test_decode_cache_sees_self_modifying_code() {
+        let vm = run_vm_with_cache(
+            "
+patched add r1, r1, #1  ; replaced with ADD R1, R1, #5 after the first pass
+        add r2, r2, #1
+        ld r0, new_ins
+        st r0, patched
+        add r3, r2, #-2
+        brn patched
+        br done
+new_ins .fill x1265
+done    add r0, r0, #0
+        ",
+        );
+
+        assert_eq!(vm.registers.r[1], 6);
+    }
+
+    #[test]
+    fn test_state_delta() {
+        // ADD R1, R1, #5; ST R1, #1; OUT
+        let mut vm = user_mode_vm(&[0x1265, 0x3201, 0xF021]);
+        vm.registers.r[0] = 'x' as u16;
+
+        vm.run_single_command();
+        let delta = vm.last_delta();
+        assert_eq!(delta.ir, Some(0x1265));
+        assert_eq!(
+            delta.pc,
+            Change {
+                old: 0x3000,
+                new: 0x3001
+            }
+        );
+        assert_eq!(
+            delta.registers,
+            vec![RegisterChange {
+                register: 1,
+                old: 0,
+                new: 5
+            }]
+        );
+        assert_eq!(
+            delta.cc,
+            Some(Change {
+                old: 0b010,
+                new: 0b001
+            })
+        );
+
+        vm.run_single_command();
+        let delta = vm.last_delta();
+        assert!(delta.registers.is_empty());
+        assert_eq!(delta.cc, None);
+        assert_eq!(
+            delta.memory,
+            vec![MemoryWrite {
+                address: 0x3003,
+                old: 0,
+                new: 5
+            }]
+        );
+
+        vm.run_single_command();
+        let delta = vm.last_delta();
+        assert_eq!(delta.io, vec![IoEvent::Output('x')]);
+        assert_eq!(delta.registers[0].register, 7);
+    }
+
+    #[test]
+    fn test_step_back() {
+        let mut asm = Asm::new(Lc3IO::new(Box::new(DebugIO::new())));
+        let program = asm
+            .run(
+                "
+.orig x3000
+        getc
+        add r1, r0, #1
+        st r1, slot
+        halt
+slot    .fill #0
+.end"
+                    .to_string(),
+            )
+            .unwrap();
+
+        let io = Lc3IO::new(Box::new(DebugIO::with_input("a")));
+        let mut vm = VM::with_trap_mode(io, TrapMode::FastPath);
+        vm.history.set_limit(DEFAULT_HISTORY_LIMIT);
+        vm.run(&program).unwrap();
+        assert!(vm.is_halted());
+        assert_eq!(vm.memory.get(0x3004), 'b' as u16);
+
+        assert!(vm.step_back());
+        assert!(!vm.is_halted());
+        assert_eq!(vm.registers.pc, 0x3003);
+
+        assert!(vm.run_back_to(0x3000));
+        assert_eq!(vm.registers.r[0], 0);
+        assert_eq!(vm.registers.r[1], 0);
+        assert_eq!(vm.memory.get(0x3004), 0);
+        assert!(vm.registers.is_user_mode());
+        assert!(!vm.step_back());
+
+        // the input that was consumed is read again
+        while !vm.is_halted() {
+            vm.run_single_command();
+        }
+        assert_eq!(vm.registers.r[0], 'a' as u16);
+        assert_eq!(vm.memory.get(0x3004), 'b' as u16);
+    }
+
+    #[test]
+    fn test_snapshot_resumes_run() {
+        let mut asm = Asm::new(Lc3IO::new(Box::new(DebugIO::new())));
+        let program = asm
+            .run(
+                "
+.orig x3000
+        getc
+        add r1, r0, #0
+        getc
+        add r2, r0, r1
+        st r2, sum
+        halt
+sum     .fill #0
+.end"
+                    .to_string(),
+            )
+            .unwrap();
+
+        let mut vm = VM::new(Lc3IO::new(Box::new(DebugIO::with_input("ab"))));
+        vm.start.usp = 0xF000;
+        vm.load(&program).unwrap();
+        while vm.registers.pc != 0x3001 {
+            vm.run_single_command();
+        }
+        let snapshot = vm.save_snapshot().unwrap();
+        vm.resume();
+
+        // the second character hasn't been read yet, so it travels with the snapshot
+        let mut resumed = VM::new(Lc3IO::new(Box::new(DebugIO::new())));
+        resumed.load_snapshot(&snapshot).unwrap();
+        assert_eq!(resumed.registers.r[0], 'a' as u16);
+        resumed.resume();
+
+        assert!(resumed.is_halted());
+        assert_eq!(resumed.memory.get(0x3006), ('a' as u16) + ('b' as u16));
+        assert_eq!(resumed.registers, vm.registers);
+        assert_eq!(resumed.memory.words(), vm.memory.words());
+        assert_eq!(resumed.start, vm.start);
+
+        assert!(resumed.load_snapshot(&snapshot[..20]).is_err());
+    }
+
+    #[test]
+    fn test_run_until_breakpoint() {
+        let mut asm = Asm::new(Lc3IO::new(Box::new(DebugIO::new())));
+        let program = asm
+            .run(
+                "
+.orig x3000
+        and r3, r3, #0
+loop    add r3, r3, #1
+        add r2, r3, #-3
+        brn loop
+        halt
+.end"
+                    .to_string(),
+            )
+            .unwrap();
+
+        let mut vm = VM::new(Lc3IO::new(Box::new(DebugIO::new())));
+        vm.load(&program).unwrap();
+        vm.breakpoints.insert(0x3001, Breakpoint::new());
+
+        // the loop body runs three times, and continuing steps past the breakpoint
+        for i in 0..3 {
+            assert_eq!(vm.run_until_event(None), StopReason::Breakpoint(0x3001));
+            assert_eq!(vm.registers.r[3], i);
+        }
+        assert_eq!(vm.run_until_event(None), StopReason::Halted);
+        assert_eq!(vm.registers.r[3], 3);
+    }
+
+    #[test]
+    fn test_conditional_breakpoint() {
+        let mut asm = Asm::new(Lc3IO::new(Box::new(DebugIO::new())));
+        let program = asm
+            .run(
+                "
+.orig x3000
+        and r3, r3, #0
+LOOP    add r3, r3, #1
+        st r3, COUNT
+        add r2, r3, #-10
+        brn LOOP
+        halt
+COUNT   .fill #0
+.end"
+                    .to_string(),
+            )
+            .unwrap();
+        let symbols = asm.symbol_table();
+        assert_eq!(symbols["LOOP"], 0x3001);
+
+        let mut vm = VM::new(Lc3IO::new(Box::new(DebugIO::new())));
+        vm.load(&program).unwrap();
+        let condition = Expr::parse("M[COUNT] >= #3", &symbols).unwrap();
+        vm.breakpoints.insert(
+            symbols["LOOP"],
+            Breakpoint::with_condition(Some(condition), 4),
+        );
+
+        // the condition first holds with COUNT = 3, and the fourth such hit is COUNT = 6
+        assert_eq!(vm.run_until_event(None), StopReason::Breakpoint(0x3001));
+        assert_eq!(vm.memory.get(symbols["COUNT"]), 6);
+        assert_eq!(vm.breakpoints[&0x3001].hits, 4);
+    }
+
+    #[test]
+    fn test_run_until_watchpoint() {
+        let mut asm = Asm::new(Lc3IO::new(Box::new(DebugIO::new())));
+        let program = asm
+            .run(
+                "
+.orig x3000
+        ld r1, value
+        add r1, r1, #1
+        st r1, value
+        getc
+        halt
+value   .fill #41
+.end"
+                    .to_string(),
+            )
+            .unwrap();
+
+        let mut vm = VM::new(Lc3IO::new(Box::new(DebugIO::new())));
+        vm.load(&program).unwrap();
+        vm.memory
+            .watchpoints
+            .push(Watchpoint::new(0x3005, 0x3005, true, true));
+        vm.memory
+            .watchpoints
+            .push(Watchpoint::new(KBDR, KBDR, true, false));
+
+        let read = vm.run_until_event(None);
+        assert_eq!(
+            read,
+            StopReason::Watchpoint(WatchHit {
+                address: 0x3005,
+                access: Access::Read,
+                value: 41
+            })
+        );
+        assert_eq!(vm.registers.pc, 0x3001);
+
+        let write = vm.run_until_event(None);
+        assert_eq!(
+            write,
+            StopReason::Watchpoint(WatchHit {
+                address: 0x3005,
+                access: Access::Write,
+                value: 42
+            })
+        );
+
+        // GETC polls the keyboard in the OS until a key is typed
+        assert_eq!(vm.run_until_event(None), StopReason::AwaitingInput);
+        vm.io.unread('k');
+        let StopReason::Watchpoint(hit) = vm.run_until_event(None) else {
+            panic!("Expected the OS to read KBDR");
+        };
+        assert_eq!(hit.address, KBDR);
+        assert_eq!(hit.value, 'k' as u16);
+        assert_eq!(vm.registers.r[0], 'k' as u16);
+
+        assert_eq!(vm.run_until_event(Some(3)), StopReason::StepLimit);
+        assert_eq!(vm.run_until_event(None), StopReason::Halted);
+    }
+
+    #[test]
+    fn test_run_until_exception() {
+        let mut vm = user_mode_vm(&[0x1261, 0xD000]); // ADD R1, R1, #1; reserved opcode
+        vm.memory.set(0x0101, 0x1000);
+
+        assert_eq!(
+            vm.run_until_event(None),
+            StopReason::Exception(Exception::IllegalOpcode)
+        );
+        assert_eq!(vm.registers.r[1], 1);
+        assert_eq!(vm.registers.pc, 0x1000);
+    }
+
+    #[test]
+    fn test_resource_limits() {
+        let mut asm = Asm::new(Lc3IO::new(Box::new(DebugIO::new())));
+        let spin = asm
+            .run(".orig x3000\nloop br loop\n.end".to_string())
+            .unwrap();
+
+        let mut vm = VM::new(Lc3IO::new(Box::new(DebugIO::new())));
+        vm.limits.max_instructions = Some(1000);
+        assert_eq!(
+            vm.run(&spin),
+            Ok(StopReason::LimitExceeded(Limit::Instructions))
+        );
+        assert_eq!(vm.usage.instructions, 1000);
+        assert!(!vm.is_halted());
+
+        let mut asm = Asm::new(Lc3IO::new(Box::new(DebugIO::new())));
+        let chatty = asm
+            .run(
+                "
+.orig x3000
+        ld r0, char
+loop    out
+        br loop
+char    .fill x41
+.end"
+                    .to_string(),
+            )
+            .unwrap();
+
+        let mut vm = VM::new(Lc3IO::new(Box::new(DebugIO::new())));
+        vm.limits.max_output_chars = Some(5);
+        assert_eq!(
+            vm.run(&chatty),
+            Ok(StopReason::LimitExceeded(Limit::OutputChars))
+        );
+        assert_eq!(vm.usage.output_chars, 5);
+
+        // loading a program starts counting again
+        vm.limits = ResourceLimits::new();
+        vm.limits.max_input_reads = Some(1);
+        vm.io = Lc3IO::new(Box::new(DebugIO::with_input("abc")));
+        let mut asm = Asm::new(Lc3IO::new(Box::new(DebugIO::new())));
+        let reader = asm
+            .run(".orig x3000\nloop getc\nbr loop\n.end".to_string())
+            .unwrap();
+        assert_eq!(
+            vm.run(&reader),
+            Ok(StopReason::LimitExceeded(Limit::InputReads))
+        );
+        assert_eq!(vm.usage.input_reads, 1);
+    }
+
+    struct CollectTrace(Rc<RefCell<Vec<TraceRecord>>>);
+
+    impl TraceSink for CollectTrace {
+        fn record(&mut self, record: &TraceRecord) {
+            self.0.borrow_mut().push(record.clone());
+        }
+    }
+
+    #[test]
+    fn test_trace() {
+        let mut asm = Asm::new(Lc3IO::new(Box::new(DebugIO::new())));
+        let file = asm
+            .run(
+                "
+.orig x3000
+        add r1, r1, #10
+        st r1, saved
+        halt
+saved   .fill #0
+.end"
+                    .to_string(),
+            )
+            .unwrap();
+
+        let records = Rc::new(RefCell::new(vec![]));
+        let mut vm = VM::with_trap_mode(Lc3IO::new(Box::new(DebugIO::new())), TrapMode::FastPath);
+        vm.tracer = Some(Box::new(CollectTrace(records.clone())));
+        assert_eq!(vm.run(&file), Ok(StopReason::Halted));
+        assert_eq!(vm.finish_trace(), Ok(()));
+
+        let records = records.borrow();
+        assert_eq!(records.len(), 3);
+        assert_eq!(
+            records[0],
+            TraceRecord {
+                pc: 0x3000,
+                word: 0x126A,
+                disassembly: "ADD R1, R1, #10".to_string(),
+                registers: vec![RegisterChange {
+                    register: 1,
+                    old: 0,
+                    new: 10
+                }],
+                cc: Some(Change { old: 0, new: 0b001 }),
+                memory: vec![],
+            }
+        );
+        assert_eq!(records[1].disassembly, "ST R1, x3003");
+        assert_eq!(
+            records[1].memory,
+            vec![MemoryWrite {
+                address: 0x3003,
+                old: 0,
+                new: 10
+            }]
+        );
+        assert_eq!(records[2].disassembly, "HALT");
+    }
+
+    #[test]
+    fn test_profile() {
+        let mut asm = Asm::new(Lc3IO::new(Box::new(DebugIO::new())));
+        let file = asm
+            .run(
+                "
+.orig x3000
+        ld r1, count
+loop    add r1, r1, #-1
+        st r1, count
+        brp loop
+        halt
+count   .fill #3
+.end"
+                    .to_string(),
+            )
+            .unwrap();
+
+        let mut vm = VM::with_trap_mode(Lc3IO::new(Box::new(DebugIO::new())), TrapMode::FastPath);
+        vm.profile = Some(Profile::new());
+        assert_eq!(vm.run(&file), Ok(StopReason::Halted));
+
+        let profile = vm.profile.as_ref().unwrap();
+        assert_eq!(profile.instructions, 11);
+        assert_eq!(profile.hotspots(1), vec![(0x3001, 3)]);
+        assert_eq!(profile.reads.get(&0x3005), Some(&1));
+        assert_eq!(profile.writes.get(&0x3005), Some(&3));
+        assert_eq!(profile.traps.get(&0x25), Some(&1));
+
+        let report = profile.report(&vm.memory, &asm.symbol_table());
+        assert!(report.contains("x3001    loop            ADD R1, R1, #-1"));
+        assert!(report.contains("x3002    loop+1"));
+    }
+
+    #[test]
+    fn test_call_graph() {
+        let mut asm = Asm::new(Lc3IO::new(Box::new(DebugIO::new())));
+        let file = asm
+            .run(
+                "
+.orig x3000
+main    ld r6, stack
+        and r0, r0, #0
+        add r0, r0, #3
+        jsr fact
+        halt
+stack   .fill xFE00
+
+; recurses R0 levels deep, saving R7 and R0 on the stack in R6
+fact    add r6, r6, #-1
+        str r7, r6, #0
+        add r1, r0, #-1
+        brz done
+        add r6, r6, #-1
+        str r0, r6, #0
+        add r0, r0, #-1
+        jsr fact
+        ldr r1, r6, #0
+        add r6, r6, #1
+        add r0, r0, #0
+done    ldr r7, r6, #0
+        add r6, r6, #1
+        ret
+.end"
+                    .to_string(),
+            )
+            .unwrap();
+
+        let mut vm = VM::with_trap_mode(Lc3IO::new(Box::new(DebugIO::new())), TrapMode::FastPath);
+        vm.call_graph = Some(CallGraph::new(&asm.symbol_table()));
+        assert_eq!(vm.run(&file), Ok(StopReason::Halted));
+
+        let graph = vm.call_graph.as_ref().unwrap();
+        assert_eq!(
+            graph.folded(),
+            "main 5\nmain;fact 14\nmain;fact;fact 14\nmain;fact;fact;fact 7\n"
+        );
+        let totals = graph.totals();
+        assert_eq!(totals["fact"].calls, 3);
+        assert_eq!(totals["fact"].inclusive, 35);
+        assert_eq!(totals["HALT"].calls, 1);
+    }
+
+    #[test]
+    fn test_coverage() {
+        let source = "
+.orig x3000
+        ld r1, count
+loop    add r1, r1, #-1
+        brp loop
+        brn never
+        halt
+never   add r2, r2, #1
+count   .fill #2
+.end";
+        let mut asm = Asm::new(Lc3IO::new(Box::new(DebugIO::new())));
+        let file = asm.run(source.to_string()).unwrap();
+
+        let mut vm = VM::with_trap_mode(Lc3IO::new(Box::new(DebugIO::new())), TrapMode::FastPath);
+        vm.coverage = Some(Coverage::new());
+        assert_eq!(vm.run(&file), Ok(StopReason::Halted));
+
+        let coverage = vm.coverage.as_ref().unwrap();
+        let lcov = coverage.lcov(&file, "count.asm");
+        assert!(lcov.contains("DA:3,1\nDA:4,2\nDA:5,2\nDA:6,1\nDA:7,1\nDA:8,0\n"));
+        assert!(lcov.contains("BRDA:5,0,0,1\nBRDA:5,0,1,1\nBRDA:6,0,0,0\nBRDA:6,0,1,1\n"));
+        assert!(lcov.ends_with("LF:6\nLH:5\nend_of_record\n"));
+        assert_eq!(coverage.summary(&file).1, 75.0);
+    }
+
+    #[test]
+    fn test_history_limit() {
+        let mut vm = user_mode_vm(&[0x1261; 5]); // ADD R1, R1, #1
+        assert!(!vm.history.is_recording());
+        vm.history.set_limit(2);
+
+        for _ in 0..5 {
+            vm.run_single_command();
+        }
+
+        assert!(vm.step_back());
+        assert!(vm.step_back());
+        assert!(!vm.step_back());
+        assert_eq!(vm.registers.r[1], 3);
+        assert_eq!(vm.registers.pc, 0x3003);
+    }
+
     #[test]
     fn test_pc_overflow_halts_vm() {
         let io = Lc3IO::new(Box::new(DebugIO::new()));
         let mut vm = VM::new(io);
-        assert!(vm.registers.halt == false);
+        assert!(!vm.is_halted());
 
         vm.registers.pc = 0xFFFF;
         vm.run_single_command();
-        assert!(vm.registers.halt == true);
+        assert!(vm.is_halted());
+    }
+
+    #[test]
+    fn test_os_trap_routines() {
+        let vm = run_os_vm(
+            r#"
+.orig x3000
+        lea r0, msg
+        puts
+        getc
+        add r5, r0, #0
+        lea r0, packed
+        putsp
+        halt
+msg     .stringz "hi!"
+packed  .fill x6261     ; "ab"
+        .fill x0063     ; "c"
+.end
+        "#,
+            "q",
+        );
+
+        assert!(vm.is_halted());
+        assert_eq!(vm.registers.r[5], 'q' as u16);
+        assert_eq!(vm.memory.devices.ddr, 'c' as u16);
+        // the user stack is untouched, and the supervisor stack holds the HALT return address
+        assert_eq!(vm.registers.saved_usp, 0xFE00);
+        assert_eq!(vm.memory.get(vm.registers.r[6]), 0x3007);
+    }
+
+    #[test]
+    fn test_program_registers_after_os_halt() {
+        let vm = run_os_vm(
+            "
+.orig x3000
+        add r0, r0, #7
+        ldi r1, ptr
+        halt
+ptr     .fill x4000
+.end
+.orig x4000
+        .fill x1234
+.end
+        ",
+            "",
+        );
+
+        assert!(vm.is_halted());
+        // HALT stops the clock inside its routine, in supervisor mode
+        assert!(!vm.registers.is_user_mode());
+
+        let registers = vm.program_registers();
+        assert_eq!(registers.r[0], 7);
+        assert_eq!(registers.r[1], 0x1234);
+        assert_eq!(registers.r[6], 0xFE00);
+        assert_eq!(registers.pc, 0x3003);
+        assert_eq!(registers.ir, 0xF025);
+        assert!(registers.is_user_mode());
+    }
+
+    #[test]
+    fn test_os_stops_when_input_runs_out() {
+        let vm = run_os_vm(
+            "
+.orig x3000
+        getc
+        halt
+.end
+        ",
+            "",
+        );
+
+        assert!(!vm.is_halted());
+    }
+
+    #[test]
+    fn test_os_handles_exceptions() {
+        let vm = run_os_vm(
+            "
+.orig x3000
+        rti
+.end
+        ",
+            "",
+        );
+
+        assert!(vm.is_halted());
+        assert_eq!(vm.memory.devices.ddr, '\n' as u16);
+    }
+
+    #[test]
+    fn test_user_supplied_os() {
+        let mut asm = Asm::new(Lc3IO::new(Box::new(DebugIO::new())));
+        let trap_table = asm
+            .run(
+                "
+.orig x0025
+        .fill x1000     ; HALT
+.end"
+                    .to_string(),
+            )
+            .unwrap();
+
+        let mut asm = Asm::new(Lc3IO::new(Box::new(DebugIO::new())));
+        let halt = asm
+            .run(
+                "
+.orig x1000
+        add r3, r3, #9
+        and r0, r0, #0
+        sti r0, mcr
+mcr     .fill xFFFE
+.end"
+                    .to_string(),
+            )
+            .unwrap();
+
+        let mut vm = VM::with_trap_mode(Lc3IO::new(Box::new(DebugIO::new())), TrapMode::FastPath);
+        vm.load_os(vec![trap_table, halt]).unwrap();
+        vm.start.psr = Some(0x8004);
+        vm.start.usp = 0x4000;
+        vm.start.ssp = 0x2000;
+
+        // the bundled OS is gone
+        assert_eq!(vm.memory.get(0x0022), 0);
+
+        let mut asm = Asm::new(Lc3IO::new(Box::new(DebugIO::new())));
+        let program = asm.run(".orig x3000\nhalt\n.end".to_string()).unwrap();
+        vm.run(&program).unwrap();
+
+        assert!(vm.is_halted());
+        assert_eq!(vm.registers.r[3], 9);
+        assert_eq!(vm.registers.saved_usp, 0x4000);
+        assert_eq!(vm.registers.r[6], 0x1FFE);
+        assert_eq!(vm.memory.get(0x1FFF), 0x8004);
+    }
+
+    #[test]
+    fn test_load_every_section() {
+        let mut asm = Asm::new(Lc3IO::new(Box::new(DebugIO::new())));
+        let program = asm
+            .run(
+                "
+.orig x3000
+        ldi r3, ptr
+        halt
+ptr     .fill data
+.end
+.orig x4000
+data    .fill x1234
+.end"
+                    .to_string(),
+            )
+            .unwrap();
+
+        let mut vm = VM::with_trap_mode(Lc3IO::new(Box::new(DebugIO::new())), TrapMode::FastPath);
+        assert_eq!(vm.run(&program), Ok(StopReason::Halted));
+
+        assert_eq!(vm.memory.get(0x4000), 0x1234);
+        assert_eq!(vm.registers.r[3], 0x1234);
+    }
+
+    #[test]
+    fn test_load_segment_past_end_of_memory() {
+        let mut program = Program::new(0x3000, vec![0x1234]);
+        program.segments.push(Segment::new(0xFFFF, vec![1, 2]));
+
+        let mut vm = VM::with_trap_mode(Lc3IO::new(Box::new(DebugIO::new())), TrapMode::FastPath);
+        assert!(vm.load(&program).unwrap_err().contains("xFFFF"));
+        // the segment that fits isn't loaded either
+        assert_eq!(vm.memory.get(0x3000), 0);
+        assert!(vm.memory.load_segment(&program.segments[1]).is_err());
+    }
+
+    #[test]
+    fn test_os_segment_outside_system_space() {
+        let mut vm = VM::new(Lc3IO::new(Box::new(DebugIO::new())));
+        assert!(vm.load_os(vec![Program::new(0x2FFF, vec![1, 2])]).is_err());
+        assert!(vm.load_os(vec![Program::new(0x2FFE, vec![1, 2])]).is_ok());
     }
 }